@@ -1,4 +1,11 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/kvstore.proto")?;
+    // The descriptor set backs the gRPC reflection service
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("kvstore_descriptor.bin"))
+        // A BTreeMap encodes its entries in key order, so equal values always
+        // encode to equal bytes, which compare_and_swap and diff rely on
+        .btree_map(["Value.metadata", "ValueHeader.metadata"])
+        .compile(&["proto/kvstore.proto"], &["proto"])?;
     Ok(())
 } 
\ No newline at end of file
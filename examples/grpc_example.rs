@@ -40,9 +40,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let test_value = Value {
         shape: vec![2, 2],
         dtype: DataType::Fp64 as i32,
-        size_check: 16,
+        size_check: 32,
         key_check: 12345,
         data: vec![vec![1, 2, 3, 4, 5, 6, 7, 8]],
+        descriptor: None,
+        metadata: Default::default(),
     };
     let test_key = 12345u64;
 
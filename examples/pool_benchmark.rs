@@ -0,0 +1,59 @@
+use std::sync::Arc;
+use std::time::Instant;
+use tonic::transport::Server;
+
+use rust_kv_store::{KVStore, grpc_server::create_grpc_server, grpc_client::KvStorePool};
+use rust_kv_store::grpc_server::kvstore::{Value, DataType};
+
+const KEYS: u64 = 100;
+const TASKS: usize = 64;
+const GETS_PER_TASK: usize = 100;
+
+// Measures concurrent get throughput for a few pool sizes
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = std::env::temp_dir().join(format!("pool_benchmark_{}", uuid::Uuid::new_v4()));
+    let store = Arc::new(KVStore::new(&temp_dir)?);
+
+    let grpc_addr = "[::1]:50052".parse()?;
+    tokio::spawn(Server::builder().add_service(create_grpc_server(store.clone())).serve(grpc_addr));
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    // 64KB values, so transfer rather than the lookup dominates
+    for key in 0..KEYS {
+        store.put(key, Value {
+            shape: vec![8192],
+            dtype: DataType::Fp64 as i32,
+            size_check: 65536,
+            key_check: key,
+            data: vec![vec![key as u8; 65536]],
+            descriptor: None,
+            metadata: Default::default(),
+        })?;
+    }
+
+    for size in [1, 2, 4, 8] {
+        let pool = Arc::new(KvStorePool::connect("http://[::1]:50052".to_string(), size).await?);
+        let started = Instant::now();
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|task| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    for i in 0..GETS_PER_TASK {
+                        pool.get(((task * GETS_PER_TASK + i) as u64) % KEYS).await?;
+                    }
+                    Ok::<_, tonic::Status>(())
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await??;
+        }
+        let elapsed = started.elapsed();
+        let gets = TASKS * GETS_PER_TASK;
+        println!("pool size {}: {} gets in {:.2?} ({:.0} gets/s)", size, gets, elapsed, gets as f64 / elapsed.as_secs_f64());
+    }
+
+    std::fs::remove_dir_all(&temp_dir)?;
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use std::time::Instant;
+
+use rust_kv_store::RocksDBStore;
+use rust_kv_store::grpc_server::kvstore::{Value, DataType};
+
+const PUTS: u64 = 2000;
+
+// Measures put throughput with and without syncing the write-ahead log on
+// every write
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = std::env::temp_dir().join(format!("sync_benchmark_{}", uuid::Uuid::new_v4()));
+    let store = RocksDBStore::new(&temp_dir)?;
+
+    // 1KB values, small enough that the sync rather than the copy dominates
+    let value = |key: u64| Value {
+        shape: vec![128],
+        dtype: DataType::Fp64 as i32,
+        size_check: 1024,
+        key_check: key,
+        data: vec![vec![key as u8; 1024]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+
+    for sync in [false, true] {
+        let started = Instant::now();
+        for key in 0..PUTS {
+            store.put_sync(key, value(key), sync)?;
+        }
+        let elapsed = started.elapsed();
+        println!("sync {}: {} puts in {:.2?} ({:.0} puts/s)", sync, PUTS, elapsed, PUTS as f64 / elapsed.as_secs_f64());
+    }
+
+    std::fs::remove_dir_all(&temp_dir)?;
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::borrow::Cow;
+
+use anyhow::Result;
+use prost::Message;
+
+use crate::grpc_server::kvstore::{Value, ValueHeader};
+use crate::StoreError;
+
+// Stored values are framed as
+//
+//   [FRAME_MARKER] [flags: u8] [crc32 of flags and payload: u32 LE] [payload]
+//
+// where the payload is the protobuf-encoded Value, zstd-compressed if flags
+// has FLAG_ZSTD set. A protobuf message can't start with a zero byte (field
+// number 0 is invalid), so entries written before framing existed are told
+// apart by their first byte and still read.
+const FRAME_MARKER: u8 = 0x00;
+const HEADER_LEN: usize = 6;
+const FLAG_ZSTD: u8 = 0x01;
+
+pub(crate) fn encode_value(value: &Value) -> Vec<u8> {
+    encode_value_with(value, None)
+}
+
+// Like `encode_value`, but with `zstd_level` set the payload is compressed
+// at that level, unless that doesn't make it any smaller
+pub(crate) fn encode_value_with(value: &Value, zstd_level: Option<i32>) -> Vec<u8> {
+    let mut payload = value.encode_to_vec();
+    let mut flags = 0u8;
+    if let Some(level) = zstd_level {
+        // Compressing into memory can't fail
+        let compressed = zstd::bulk::compress(&payload, level).expect("in-memory zstd compression failed");
+        if compressed.len() < payload.len() {
+            payload = compressed;
+            flags |= FLAG_ZSTD;
+        }
+    }
+    let mut framed = Vec::with_capacity(HEADER_LEN + payload.len());
+    framed.push(FRAME_MARKER);
+    framed.push(flags);
+    framed.extend_from_slice(&checksum(flags, &payload).to_le_bytes());
+    framed.extend_from_slice(&payload);
+    framed
+}
+
+pub(crate) fn decode_value(bytes: &[u8]) -> Result<Value> {
+    Ok(Value::decode(value_payload(bytes)?.as_ref())?)
+}
+
+// The stored value's fields other than `data`, which is skipped over
+// rather than copied out
+pub(crate) fn decode_value_header(bytes: &[u8]) -> Result<ValueHeader> {
+    Ok(ValueHeader::decode(value_payload(bytes)?.as_ref())?)
+}
+
+// Like `decode_value`, but leaves `data` empty
+pub(crate) fn decode_value_without_data(bytes: &[u8]) -> Result<Value> {
+    let header = decode_value_header(bytes)?;
+    Ok(Value {
+        shape: header.shape,
+        dtype: header.dtype,
+        size_check: header.size_check,
+        key_check: header.key_check,
+        data: Vec::new(),
+        descriptor: header.descriptor,
+        metadata: header.metadata,
+    })
+}
+
+// The protobuf encoding of a stored value after checking its CRC. Fails with
+// StoreError::Corruption if the checksum doesn't match.
+pub(crate) fn value_payload(bytes: &[u8]) -> Result<Cow<'_, [u8]>> {
+    if bytes.first() != Some(&FRAME_MARKER) {
+        // Unframed entry from before checksums were added
+        return Ok(Cow::Borrowed(bytes));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(StoreError::Corruption(format!("truncated value header ({} bytes)", bytes.len())).into());
+    }
+    let flags = bytes[1];
+    let stored = u32::from_le_bytes(bytes[2..HEADER_LEN].try_into()?);
+    let payload = &bytes[HEADER_LEN..];
+    let computed = checksum(flags, payload);
+    if stored != computed {
+        return Err(StoreError::Corruption(format!(
+            "checksum mismatch (stored {:08x}, computed {:08x})", stored, computed
+        )).into());
+    }
+    if flags & FLAG_ZSTD == 0 {
+        return Ok(Cow::Borrowed(payload));
+    }
+    let decompressed = zstd::stream::decode_all(payload)
+        .map_err(|e| StoreError::Corruption(format!("undecodable zstd payload: {}", e)))?;
+    Ok(Cow::Owned(decompressed))
+}
+
+fn checksum(flags: u8, payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[flags]);
+    hasher.update(payload);
+    hasher.finalize()
+}
@@ -0,0 +1,304 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{DBCompressionType, RocksDBStoreBuilder};
+
+// Deployment settings of the server binary. `load` starts from the defaults,
+// which match what the server did before it was configurable, applies the
+// TOML file named by CONFIG_FILE if set, then the environment variables
+// listed on each field.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // DATA_DIR_PREFIX: each run stores its data in a fresh directory in here
+    pub data_dir_prefix: PathBuf,
+    // GRPC_ADDR and HTTP_ADDR: listen addresses. An empty string turns that
+    // server off.
+    pub grpc_addr: String,
+    pub http_addr: String,
+    // AUTH_TOKEN: bearer token gRPC clients must send. Unset serves without
+    // authentication.
+    pub auth_token: Option<String>,
+    // TLS_CERT and TLS_KEY: PEM certificate chain and private key. Setting
+    // both serves gRPC over TLS.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    // REQUEST_LOG: log every request
+    pub log_requests: bool,
+    // GRPC_REFLECTION: serve the gRPC reflection service
+    pub reflection: bool,
+    // RATE_LIMIT: requests per second each server accepts, summed over all
+    // clients; more get RESOURCE_EXHAUSTED (gRPC) or 429 (HTTP). Unset
+    // doesn't limit.
+    pub rate_limit: Option<u32>,
+    pub rocksdb: RocksDbConfig,
+}
+
+// RocksDB tuning; unset fields keep RocksDBStoreBuilder's defaults. Each
+// can be set with ROCKSDB_ and the field name in capitals, e.g.
+// ROCKSDB_BLOCK_CACHE_SIZE.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RocksDbConfig {
+    pub max_open_files: Option<i32>,
+    pub use_fsync: Option<bool>,
+    // Sync the write-ahead log on every write
+    pub sync_writes: Option<bool>,
+    pub write_buffer_size: Option<usize>,
+    // none, snappy, zlib, bz2, lz4, lz4hc or zstd
+    pub compression: Option<String>,
+    pub zstd_level: Option<i32>,
+    pub block_cache_size: Option<usize>,
+    pub statistics: Option<bool>,
+    // Zstd level for compressing each value whole; unset stores values as-is
+    pub value_zstd_level: Option<i32>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            data_dir_prefix: PathBuf::from("./data"),
+            grpc_addr: "0.0.0.0:50051".to_string(),
+            http_addr: "0.0.0.0:8080".to_string(),
+            auth_token: None,
+            tls_cert: None,
+            tls_key: None,
+            log_requests: true,
+            reflection: false,
+            rate_limit: None,
+            rocksdb: RocksDbConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    // The configuration from CONFIG_FILE (if set) and the environment
+    pub fn load() -> Result<Self> {
+        let config = match std::env::var("CONFIG_FILE") {
+            Ok(path) => Self::from_file(path)?,
+            Err(_) => Self::default(),
+        };
+        config.with_env(|name| std::env::var(name).ok())
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::from_toml(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    // Settings missing from `text` keep their defaults
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    // Overrides every setting for which `var` returns a value. `load` passes
+    // the process environment; anything else works too, e.g. in tests.
+    pub fn with_env(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        if let Some(prefix) = var("DATA_DIR_PREFIX") {
+            self.data_dir_prefix = prefix.into();
+        }
+        if let Some(addr) = var("GRPC_ADDR") {
+            self.grpc_addr = addr;
+        }
+        if let Some(addr) = var("HTTP_ADDR") {
+            self.http_addr = addr;
+        }
+        if let Some(token) = var("AUTH_TOKEN") {
+            self.auth_token = Some(token);
+        }
+        if let Some(path) = var("TLS_CERT") {
+            self.tls_cert = Some(path.into());
+        }
+        if let Some(path) = var("TLS_KEY") {
+            self.tls_key = Some(path.into());
+        }
+        if let Some(value) = var("REQUEST_LOG") {
+            self.log_requests = parse_env("REQUEST_LOG", &value, parse_bool)?;
+        }
+        if let Some(value) = var("GRPC_REFLECTION") {
+            self.reflection = parse_env("GRPC_REFLECTION", &value, parse_bool)?;
+        }
+        if let Some(value) = var("RATE_LIMIT") {
+            self.rate_limit = Some(parse_env("RATE_LIMIT", &value, |v| v.parse().ok().filter(|&n| n > 0))?);
+        }
+
+        let rocksdb = &mut self.rocksdb;
+        if let Some(value) = var("ROCKSDB_MAX_OPEN_FILES") {
+            rocksdb.max_open_files = Some(parse_env("ROCKSDB_MAX_OPEN_FILES", &value, |v| v.parse().ok())?);
+        }
+        if let Some(value) = var("ROCKSDB_USE_FSYNC") {
+            rocksdb.use_fsync = Some(parse_env("ROCKSDB_USE_FSYNC", &value, parse_bool)?);
+        }
+        if let Some(value) = var("ROCKSDB_SYNC_WRITES") {
+            rocksdb.sync_writes = Some(parse_env("ROCKSDB_SYNC_WRITES", &value, parse_bool)?);
+        }
+        if let Some(value) = var("ROCKSDB_WRITE_BUFFER_SIZE") {
+            rocksdb.write_buffer_size = Some(parse_env("ROCKSDB_WRITE_BUFFER_SIZE", &value, |v| v.parse().ok())?);
+        }
+        if let Some(value) = var("ROCKSDB_COMPRESSION") {
+            rocksdb.compression = Some(value);
+        }
+        if let Some(value) = var("ROCKSDB_ZSTD_LEVEL") {
+            rocksdb.zstd_level = Some(parse_env("ROCKSDB_ZSTD_LEVEL", &value, |v| v.parse().ok())?);
+        }
+        if let Some(value) = var("ROCKSDB_BLOCK_CACHE_SIZE") {
+            rocksdb.block_cache_size = Some(parse_env("ROCKSDB_BLOCK_CACHE_SIZE", &value, |v| v.parse().ok())?);
+        }
+        if let Some(value) = var("ROCKSDB_STATISTICS") {
+            rocksdb.statistics = Some(parse_env("ROCKSDB_STATISTICS", &value, parse_bool)?);
+        }
+        if let Some(value) = var("ROCKSDB_VALUE_ZSTD_LEVEL") {
+            rocksdb.value_zstd_level = Some(parse_env("ROCKSDB_VALUE_ZSTD_LEVEL", &value, |v| v.parse().ok())?);
+        }
+        Ok(self)
+    }
+
+    pub fn grpc_addr(&self) -> Result<Option<SocketAddr>> {
+        parse_listen_addr("grpc_addr", &self.grpc_addr)
+    }
+
+    pub fn http_addr(&self) -> Result<Option<SocketAddr>> {
+        parse_listen_addr("http_addr", &self.http_addr)
+    }
+
+    // Certificate and key paths, if TLS is configured. Setting only one of
+    // them is an error rather than silently serving plaintext.
+    pub fn tls_paths(&self) -> Result<Option<(&Path, &Path)>> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            _ => anyhow::bail!("tls_cert and tls_key must be set together"),
+        }
+    }
+
+    // A store builder with the RocksDB tuning applied
+    pub fn store_builder(&self) -> Result<RocksDBStoreBuilder> {
+        let rocksdb = &self.rocksdb;
+        let mut builder = RocksDBStoreBuilder::new();
+        if let Some(max_open_files) = rocksdb.max_open_files {
+            builder = builder.max_open_files(max_open_files);
+        }
+        if let Some(use_fsync) = rocksdb.use_fsync {
+            builder = builder.use_fsync(use_fsync);
+        }
+        if let Some(sync) = rocksdb.sync_writes {
+            builder = builder.sync_writes(sync);
+        }
+        if let Some(bytes) = rocksdb.write_buffer_size {
+            builder = builder.write_buffer_size(bytes);
+        }
+        if let Some(name) = &rocksdb.compression {
+            builder = builder.compression(parse_compression(name)?);
+        }
+        if let Some(level) = rocksdb.zstd_level {
+            builder = builder.zstd_level(level);
+        }
+        if let Some(bytes) = rocksdb.block_cache_size {
+            builder = builder.block_cache_size(bytes);
+        }
+        if let Some(enabled) = rocksdb.statistics {
+            builder = builder.statistics(enabled);
+        }
+        if let Some(level) = rocksdb.value_zstd_level {
+            builder = builder.value_zstd_level(level);
+        }
+        Ok(builder)
+    }
+}
+
+fn parse_env<T>(name: &str, value: &str, parse: impl Fn(&str) -> Option<T>) -> Result<T> {
+    parse(value).ok_or_else(|| anyhow::anyhow!("{} has an invalid value: '{}'", name, value))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_listen_addr(name: &str, addr: &str) -> Result<Option<SocketAddr>> {
+    if addr.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(addr.parse().with_context(|| format!("{} is not a socket address: '{}'", name, addr))?))
+}
+
+fn parse_compression(name: &str) -> Result<DBCompressionType> {
+    Ok(match name {
+        "none" => DBCompressionType::None,
+        "snappy" => DBCompressionType::Snappy,
+        "zlib" => DBCompressionType::Zlib,
+        "bz2" => DBCompressionType::Bz2,
+        "lz4" => DBCompressionType::Lz4,
+        "lz4hc" => DBCompressionType::Lz4hc,
+        "zstd" => DBCompressionType::Zstd,
+        _ => anyhow::bail!("Unknown compression '{}'", name),
+    })
+}
+
+#[test]
+fn test_server_config() {
+    use std::collections::HashMap;
+
+    let defaults = ServerConfig::default();
+    assert_eq!(defaults.grpc_addr().unwrap(), Some("0.0.0.0:50051".parse().unwrap()));
+    assert_eq!(defaults.http_addr().unwrap(), Some("0.0.0.0:8080".parse().unwrap()));
+    assert!(defaults.log_requests && !defaults.reflection);
+    assert_eq!(defaults.tls_paths().unwrap(), None);
+
+    // Missing keys keep their defaults
+    let config = ServerConfig::from_toml(r#"
+        data_dir_prefix = "/var/lib/kvstore"
+        http_addr = ""
+        auth_token = "secret"
+
+        [rocksdb]
+        compression = "zstd"
+        zstd_level = 9
+        block_cache_size = 1048576
+        value_zstd_level = 5
+        sync_writes = true
+    "#).unwrap();
+    assert_eq!(config.data_dir_prefix, PathBuf::from("/var/lib/kvstore"));
+    assert_eq!(config.grpc_addr, defaults.grpc_addr);
+    assert_eq!(config.http_addr().unwrap(), None);
+    assert_eq!(config.auth_token.as_deref(), Some("secret"));
+    assert_eq!(config.rocksdb.zstd_level, Some(9));
+    assert!(ServerConfig::from_toml("grpc_port = 1").is_err());
+
+    // Environment variables override the file
+    let env: HashMap<&str, &str> = [
+        ("GRPC_ADDR", "127.0.0.1:6000"),
+        ("REQUEST_LOG", "0"),
+        ("ROCKSDB_ZSTD_LEVEL", "3"),
+        ("RATE_LIMIT", "500"),
+        ("TLS_CERT", "/etc/kvstore/cert.pem"),
+    ].into();
+    let config = config.with_env(|name| env.get(name).map(|value| value.to_string())).unwrap();
+    assert_eq!(config.grpc_addr().unwrap(), Some("127.0.0.1:6000".parse().unwrap()));
+    assert!(!config.log_requests);
+    assert_eq!(config.rocksdb.zstd_level, Some(3));
+    assert_eq!(config.rocksdb.compression.as_deref(), Some("zstd"));
+    assert_eq!(config.rate_limit, Some(500));
+    assert!(ServerConfig::default().with_env(|name| (name == "RATE_LIMIT").then(|| "0".to_string())).is_err());
+    // A certificate without its key is a mistake, not plaintext
+    assert!(config.tls_paths().is_err());
+    assert!(ServerConfig::default().with_env(|_| Some("maybe".to_string())).is_err());
+
+    // The tuning reaches the store
+    let temp_dir = std::env::temp_dir().join(format!("kvstore_config_test_{}", uuid::Uuid::new_v4()));
+    let store = config.store_builder().unwrap().open(&temp_dir).unwrap();
+    assert_eq!(store.cf_tuning.compression, DBCompressionType::Zstd);
+    assert_eq!(store.cf_tuning.zstd_level, Some(3));
+    assert_eq!(store.value_zstd_level, Some(5));
+    assert!(store.sync_writes);
+    let bad = ServerConfig::from_toml("[rocksdb]\ncompression = \"brotli\"").unwrap();
+    assert!(bad.store_builder().is_err());
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+
+use crate::grpc_server::kvstore::DataType;
+
+impl DataType {
+    // Bits per element. Sub-byte types are packed, so a tensor's payload is
+    // its element count times this, rounded up to whole bytes.
+    pub fn bit_width(&self) -> u64 {
+        match self {
+            DataType::Fp1 | DataType::Int1 => 1,
+            DataType::Fp2 | DataType::Int2 => 2,
+            DataType::Fp4 | DataType::Int4 => 4,
+            DataType::Fp8 | DataType::Int8 | DataType::Bool => 8,
+            DataType::Bf16 | DataType::Fp16 | DataType::Int16 => 16,
+            DataType::Fp32 | DataType::Int32 => 32,
+            DataType::Fp64 | DataType::Int64 => 64,
+        }
+    }
+
+    // Bytes per element. Sub-byte types share bytes with their neighbours and
+    // report 1; use `payload_size` for the size of a whole tensor.
+    pub fn byte_size(&self) -> usize {
+        self.bit_width().div_ceil(8) as usize
+    }
+
+    // Size in bytes of `elements` elements of this type, or None on overflow
+    pub fn payload_size(&self, elements: u64) -> Option<u64> {
+        elements.checked_mul(self.bit_width()).map(|bits| bits.div_ceil(8))
+    }
+
+    // Decodes little-endian elements to f64. Only types with a native Rust
+    // counterpart are supported; FP8, the 16-bit floats, BOOL and the packed
+    // sub-byte types are rejected.
+    pub fn decode_f64(&self, bytes: &[u8]) -> Result<Vec<f64>> {
+        let size = self.byte_size();
+        if !bytes.len().is_multiple_of(size) {
+            anyhow::bail!(
+                "{} data bytes is not a multiple of the {}-byte {:?} element size", bytes.len(), size, self
+            );
+        }
+
+        macro_rules! decode {
+            ($ty:ty) => {
+                bytes
+                    .chunks_exact(size)
+                    .map(|chunk| <$ty>::from_le_bytes(chunk.try_into().unwrap()) as f64)
+                    .collect()
+            };
+        }
+
+        Ok(match self {
+            DataType::Int8 => decode!(i8),
+            DataType::Int16 => decode!(i16),
+            DataType::Int32 => decode!(i32),
+            DataType::Int64 => decode!(i64),
+            DataType::Fp32 => decode!(f32),
+            DataType::Fp64 => decode!(f64),
+            _ => anyhow::bail!("{:?} values can't be decoded as numbers", self),
+        })
+    }
+}
+
+#[test]
+fn test_dtype_sizes() {
+    assert_eq!(DataType::Fp64.byte_size(), 8);
+    assert_eq!(DataType::Int64.byte_size(), 8);
+    assert_eq!(DataType::Fp32.byte_size(), 4);
+    assert_eq!(DataType::Int32.byte_size(), 4);
+    assert_eq!(DataType::Bf16.byte_size(), 2);
+    assert_eq!(DataType::Int8.byte_size(), 1);
+    assert_eq!(DataType::Int4.byte_size(), 1);
+    assert_eq!(DataType::Int4.payload_size(3), Some(2));
+    assert_eq!(DataType::Fp1.payload_size(9), Some(2));
+    assert_eq!(DataType::Fp64.payload_size(u64::MAX), None);
+
+    let ints: Vec<u8> = [1i32, -2, 3].iter().flat_map(|e| e.to_le_bytes()).collect();
+    assert_eq!(DataType::Int32.decode_f64(&ints).unwrap(), vec![1.0, -2.0, 3.0]);
+    assert!(DataType::Int32.decode_f64(&ints[..10]).is_err());
+    assert!(DataType::Fp16.decode_f64(&[0, 0]).is_err());
+}
@@ -0,0 +1,127 @@
+use std::io::{self, Read, Write};
+
+use anyhow::Result;
+use prost::Message;
+
+use crate::grpc_server::kvstore::Value;
+use crate::StoreError;
+
+// Dumps written by `RocksDBStore::export` are laid out as
+//
+//   [MAGIC: 8 bytes] [version: u32 LE]
+//   [TAG_ENTRY] [key: u64 LE] [length: u32 LE] [protobuf-encoded Value]   (repeated)
+//   [TAG_END] [entry count: u64 LE]
+//
+// Values are written as plain protobuf, whatever compression or framing the
+// store keeps them in, so any version of the store can read them back. The
+// trailing count lets a reader tell a complete dump from a truncated one.
+const MAGIC: &[u8; 8] = b"KVSDUMP\0";
+const VERSION: u32 = 1;
+const TAG_ENTRY: u8 = 1;
+const TAG_END: u8 = 0;
+
+pub(crate) struct DumpWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> DumpWriter<W> {
+    pub(crate) fn new(mut inner: W) -> Result<Self> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self { inner, count: 0 })
+    }
+
+    // `payload` is the protobuf encoding of the Value stored under `key`
+    pub(crate) fn write_entry(&mut self, key: u64, payload: &[u8]) -> Result<()> {
+        let len = u32::try_from(payload.len())
+            .map_err(|_| anyhow::anyhow!("Value of key {} is too large to export ({} bytes)", key, payload.len()))?;
+        self.inner.write_all(&[TAG_ENTRY])?;
+        self.inner.write_all(&key.to_le_bytes())?;
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(payload)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    // Writes the end marker and returns how many entries were written
+    pub(crate) fn finish(mut self) -> Result<u64> {
+        self.inner.write_all(&[TAG_END])?;
+        self.inner.write_all(&self.count.to_le_bytes())?;
+        self.inner.flush()?;
+        Ok(self.count)
+    }
+}
+
+pub(crate) struct DumpReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> DumpReader<R> {
+    // Fails with StoreError::InvalidArgument unless `inner` starts with the
+    // header of a dump this version can read
+    pub(crate) fn new(mut inner: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        read_exact(&mut inner, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(StoreError::InvalidArgument("not a store dump".to_string()).into());
+        }
+        let version = u32::from_le_bytes(read_array(&mut inner)?);
+        if version != VERSION {
+            return Err(StoreError::InvalidArgument(format!("unsupported dump version {}", version)).into());
+        }
+        Ok(Self { inner, count: 0 })
+    }
+
+    // The next entry, or None after the end marker. A dump that stops short
+    // of its end marker, or whose count doesn't match, fails with
+    // StoreError::Corruption.
+    pub(crate) fn next_entry(&mut self) -> Result<Option<(u64, Value)>> {
+        let [tag] = read_array(&mut self.inner)?;
+        match tag {
+            TAG_ENTRY => {
+                let key = u64::from_le_bytes(read_array(&mut self.inner)?);
+                let len = u32::from_le_bytes(read_array(&mut self.inner)?);
+                // Read through `take` so a corrupt length can't make us
+                // allocate more than the dump actually holds
+                let mut payload = Vec::new();
+                (&mut self.inner).take(u64::from(len)).read_to_end(&mut payload)?;
+                if payload.len() != len as usize {
+                    return Err(truncated());
+                }
+                let value = Value::decode(payload.as_slice())
+                    .map_err(|e| StoreError::Corruption(format!("undecodable value for key {} in dump: {}", key, e)))?;
+                self.count += 1;
+                Ok(Some((key, value)))
+            }
+            TAG_END => {
+                let count = u64::from_le_bytes(read_array(&mut self.inner)?);
+                if count != self.count {
+                    return Err(StoreError::Corruption(format!(
+                        "dump says it holds {} entries but {} were read", count, self.count
+                    )).into());
+                }
+                Ok(None)
+            }
+            tag => Err(StoreError::Corruption(format!("unknown record tag {} in dump", tag)).into()),
+        }
+    }
+}
+
+fn truncated() -> anyhow::Error {
+    StoreError::Corruption("dump is truncated".to_string()).into()
+}
+
+fn read_exact(inner: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    inner.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => truncated(),
+        _ => e.into(),
+    })
+}
+
+fn read_array<const N: usize>(inner: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    read_exact(inner, &mut buf)?;
+    Ok(buf)
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use rand::Rng;
+use serde::Serialize;
+
+// Store-level failures that callers may want to tell apart from generic
+// storage errors. They travel inside `anyhow::Error`; use
+// `err.downcast_ref::<StoreError>()` to inspect them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreError {
+    // The operation ran past its deadline and was aborted
+    Timeout,
+    // The caller supplied a value or argument the store rejects
+    InvalidArgument(String),
+    // A concurrent writer got in the way (RocksDB Busy/TimedOut/TryAgain);
+    // retrying the operation may succeed
+    Conflict(String),
+    // The named namespace or store doesn't exist
+    NotFound(String),
+    // A stored value failed its integrity check
+    Corruption(String),
+    // The key or thing named is already there and the operation won't
+    // replace it
+    AlreadyExists(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Timeout => write!(f, "Operation timed out"),
+            StoreError::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            StoreError::Conflict(message) => write!(f, "Conflict: {}", message),
+            StoreError::NotFound(what) => write!(f, "Not found: {}", what),
+            StoreError::Corruption(message) => write!(f, "Corrupted value: {}", message),
+            StoreError::AlreadyExists(what) => write!(f, "Already exists: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+// JSON body of every error response from the HTTP routes, e.g.
+// `{"success":false,"error":"Rate limit exceeded","code":"resource_exhausted"}`.
+// Codes are the snake_case names of the gRPC status the same failure gets,
+// so clients of either API can tell failures apart the same way.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    #[serde(skip)]
+    status: StatusCode,
+    // Always false; lets clients check every response the same way
+    success: bool,
+    error: String,
+    code: &'static str,
+}
+
+impl ErrorBody {
+    pub fn new(status: StatusCode, code: &'static str, error: impl Into<String>) -> Self {
+        Self { status, success: false, error: error.into(), code }
+    }
+}
+
+// Maps StoreError to HTTP statuses the way grpc_server maps it to gRPC ones
+impl From<anyhow::Error> for ErrorBody {
+    fn from(e: anyhow::Error) -> Self {
+        let (status, code) = match e.downcast_ref::<StoreError>() {
+            Some(StoreError::Timeout) => (StatusCode::GATEWAY_TIMEOUT, "deadline_exceeded"),
+            Some(StoreError::InvalidArgument(_)) => (StatusCode::BAD_REQUEST, "invalid_argument"),
+            Some(StoreError::Conflict(_)) => (StatusCode::CONFLICT, "aborted"),
+            Some(StoreError::NotFound(_)) => (StatusCode::NOT_FOUND, "not_found"),
+            Some(StoreError::Corruption(_)) => (StatusCode::INTERNAL_SERVER_ERROR, "data_loss"),
+            Some(StoreError::AlreadyExists(_)) => (StatusCode::CONFLICT, "already_exists"),
+            None => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        };
+        Self::new(status, code, e.to_string())
+    }
+}
+
+impl IntoResponse for ErrorBody {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
+
+// How many entries a scan visits between deadline checks
+pub(crate) const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+// Fails with StoreError::Timeout once `deadline` has passed. Scans call this
+// every DEADLINE_CHECK_INTERVAL entries (starting with the first one).
+pub(crate) fn check_deadline(deadline: Option<Instant>, visited: u64) -> anyhow::Result<()> {
+    if let Some(deadline) = deadline {
+        if visited.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+            return Err(StoreError::Timeout.into());
+        }
+    }
+    Ok(())
+}
+
+// Converts a RocksDB error, turning lock/contention failures into
+// StoreError::Conflict so callers don't need to match RocksDB's strings
+pub(crate) fn map_rocksdb_error(e: rocksdb::Error) -> anyhow::Error {
+    match e.kind() {
+        rocksdb::ErrorKind::Busy | rocksdb::ErrorKind::TimedOut | rocksdb::ErrorKind::TryAgain => {
+            StoreError::Conflict(e.into_string()).into()
+        }
+        _ => e.into(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // Total number of attempts, including the first one
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+// Runs `op`, retrying it while it fails with StoreError::Conflict. Backoff
+// doubles after every attempt (capped at `max_backoff`) with random jitter so
+// competing writers don't retry in lockstep. Any other error, or a conflict on
+// the last attempt, is returned as-is.
+pub fn with_retry<T, F>(policy: RetryPolicy, mut op: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> anyhow::Result<T>,
+{
+    let mut backoff = policy.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if attempt < policy.max_attempts
+                && matches!(e.downcast_ref::<StoreError>(), Some(StoreError::Conflict(_))) =>
+            {
+                let jitter = rand::thread_rng().gen_range(0..=backoff.as_micros() as u64);
+                std::thread::sleep(backoff + Duration::from_micros(jitter));
+                backoff = (backoff * 2).min(policy.max_backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_error_body() {
+    // A malformed key is rejected with a 400 and the JSON error shape
+    let response = ErrorBody::from(anyhow::Error::from(StoreError::InvalidArgument("bad key".to_string()))).into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()[axum::http::header::CONTENT_TYPE], "application/json");
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(), serde_json::json!({
+        "success": false,
+        "error": "Invalid argument: bad key",
+        "code": "invalid_argument",
+    }));
+
+    let body = ErrorBody::from(anyhow::anyhow!("disk on fire"));
+    assert_eq!((body.status, body.code), (StatusCode::INTERNAL_SERVER_ERROR, "internal"));
+}
+
+#[test]
+fn test_map_rocksdb_error() {
+    use rocksdb::{OptimisticTransactionDB, Options, TransactionDB, TransactionDBOptions, TransactionOptions, WriteOptions};
+    use crate::test_support::TempDir;
+
+    let is_conflict = |e: rocksdb::Error| {
+        let kind = e.kind();
+        let message = e.to_string();
+        let mapped = map_rocksdb_error(e);
+        assert!(mapped.to_string().contains(&message), "{} lost its message", mapped);
+        (kind, matches!(mapped.downcast_ref::<StoreError>(), Some(StoreError::Conflict(_))))
+    };
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.set_write_buffer_size(64 * 1024);
+
+    // TimedOut: waiting on a key another transaction has locked
+    let dir = TempDir::new("kvstore_map_error_pessimistic");
+    let db: TransactionDB = TransactionDB::open(&opts, &TransactionDBOptions::default(), &*dir).unwrap();
+    let holder = db.transaction();
+    holder.put(b"key", b"first").unwrap();
+    let mut no_wait = TransactionOptions::default();
+    no_wait.set_lock_timeout(0);
+    let waiter = db.transaction_opt(&WriteOptions::default(), &no_wait);
+    let e = waiter.put(b"key", b"second").unwrap_err();
+    assert_eq!(is_conflict(e), (rocksdb::ErrorKind::TimedOut, true));
+    drop((waiter, holder));
+
+    // Busy: the key changed after an optimistic transaction read it
+    let dir = TempDir::new("kvstore_map_error_optimistic");
+    let db: OptimisticTransactionDB = OptimisticTransactionDB::open(&opts, &*dir).unwrap();
+    let txn = db.transaction();
+    txn.get_for_update(b"key", true).unwrap();
+    db.put(b"key", b"other").unwrap();
+    txn.put(b"key", b"mine").unwrap();
+    assert_eq!(is_conflict(txn.commit().unwrap_err()), (rocksdb::ErrorKind::Busy, true));
+
+    // TryAgain: the memtables kept for conflict checking no longer go back
+    // to the read. They are kept up to the write buffer size.
+    let txn = db.transaction();
+    txn.get_for_update(b"key", true).unwrap();
+    for _ in 0..4 {
+        db.put(b"unrelated", vec![0u8; 1 << 20]).unwrap();
+        db.flush().unwrap();
+    }
+    txn.put(b"key", b"mine").unwrap();
+    assert_eq!(is_conflict(txn.commit().unwrap_err()), (rocksdb::ErrorKind::TryAgain, true));
+
+    // Other failures aren't contention and keep their RocksDB error
+    let e = OptimisticTransactionDB::<rocksdb::SingleThreaded>::open(&opts, &*dir).err().unwrap();
+    assert_eq!(is_conflict(e), (rocksdb::ErrorKind::IOError, false));
+    let missing = TempDir::new("kvstore_map_error_missing");
+    let e = rocksdb::DB::open(&Options::default(), &*missing).err().unwrap();
+    assert_eq!(is_conflict(e), (rocksdb::ErrorKind::InvalidArgument, false));
+}
@@ -1,44 +1,800 @@
-use tonic::transport::Channel;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use futures_util::{stream, Stream, StreamExt};
+use rand::Rng;
+use std::path::Path;
+use tonic::codec::CompressionEncoding;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
 use crate::grpc_server::kvstore::kv_store_service_client::KvStoreServiceClient;
-use crate::grpc_server::kvstore::{PutRequest, GetRequest, DeleteRequest, ListRequest, HealthRequest};
+use crate::grpc_server::kvstore::{batch_op, BatchOp, BatchRequest, CompareAndSwapRequest, CountRequest, CreateStoreRequest, ScanRequest, PutRequest, WatchEvent, WatchRequest, GetRequest, DeleteRequest, DeleteBatchRequest, DeleteRangeRequest, GetBatchRequest, GetMetaRequest, ValueHeader, ExistsBatchRequest, ExistsRequest, FlushRequest, ListRequest, HealthRequest, AggregateRequest, AggregateResponse, AggKind, ListStoresRequest, StoreInfo};
+use crate::grpc_server::kvstore::Value;
+use crate::RetryPolicy;
+
+type ServiceClient = KvStoreServiceClient<InterceptedService<Channel, AttachToken>>;
+
+// Sleeps for `backoff` plus up to as much again of random jitter, so clients
+// retrying together don't do so in lockstep
+async fn sleep_with_jitter(backoff: Duration) {
+    let jitter = rand::thread_rng().gen_range(0..=backoff.as_micros() as u64);
+    tokio::time::sleep(backoff + Duration::from_micros(jitter)).await;
+}
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn deadline_exceeded(timeout: Duration) -> tonic::Status {
+    tonic::Status::deadline_exceeded(format!("No response within {:?}", timeout))
+}
+
+// How long a unary call may take unless the builder or the call says
+// otherwise. Generous, since a Value can be large, but finite so a hung
+// server can't hang its clients.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Failures worth retrying: the server was unreachable or didn't answer in
+// time. Anything else would fail the same way again.
+fn is_transient(status: &tonic::Status) -> bool {
+    matches!(status.code(), tonic::Code::Unavailable | tonic::Code::DeadlineExceeded)
+}
+
+// Read-through cache of recently fetched values. Entries expire `ttl` after
+// they were fetched and the oldest entry is evicted once `capacity` is hit.
+struct ClientCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: HashMap<u64, (Instant, Value)>,
+    order: VecDeque<u64>,
+}
+
+impl ClientCache {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, key: u64) -> Option<Value> {
+        match self.entries.get(&key) {
+            Some((fetched_at, value)) if fetched_at.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                self.invalidate(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, key: u64, value: Value) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.invalidate(key);
+        while self.entries.len() >= self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.entries.insert(key, (Instant::now(), value));
+        self.order.push_back(key);
+    }
+
+    fn invalidate(&mut self, key: u64) {
+        if self.entries.remove(&key).is_some() {
+            self.order.retain(|k| *k != key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+// Attaches `authorization: Bearer <token>` to every request when a token is set
+#[derive(Clone)]
+struct AttachToken {
+    token: Option<String>,
+}
+
+impl Interceptor for AttachToken {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+        if let Some(token) = &self.token {
+            let value = MetadataValue::try_from(format!("Bearer {}", token))
+                .map_err(|_| tonic::Status::invalid_argument("Auth token is not valid metadata"))?;
+            request.metadata_mut().insert("authorization", value);
+        }
+        Ok(request)
+    }
+}
+
+// A service client over `channel` sending `auth` and compressing requests
+// and responses with `compression`, if set
+fn service_client(channel: Channel, auth: AttachToken, compression: Option<CompressionEncoding>) -> ServiceClient {
+    let client = KvStoreServiceClient::with_interceptor(channel, auth);
+    match compression {
+        Some(encoding) => client.send_compressed(encoding).accept_compressed(encoding),
+        None => client,
+    }
+}
+
+// Store statistics reported by the server's health check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreStats {
+    pub healthy: bool,
+    pub entries: u64,
+    pub db_size_bytes: u64,
+    pub writable: bool,
+}
+
+pub struct KvStoreClientBuilder {
+    addr: String,
+    cache: Option<(Duration, usize)>,
+    store_name: String,
+    tls: Option<ClientTlsConfig>,
+    token: Option<String>,
+    retry: Option<RetryPolicy>,
+    retry_puts: bool,
+    timeout: Duration,
+    compression: Option<CompressionEncoding>,
+}
+
+impl KvStoreClientBuilder {
+    pub fn new(addr: String) -> Self {
+        Self {
+            addr,
+            cache: None,
+            store_name: String::new(),
+            tls: None,
+            token: None,
+            retry: None,
+            retry_puts: false,
+            timeout: DEFAULT_TIMEOUT,
+            compression: Some(CompressionEncoding::Gzip),
+        }
+    }
+
+    // Deadline for each attempt of every unary call, DEFAULT_TIMEOUT unless
+    // set. It's sent to the server as grpc-timeout and enforced locally, so
+    // an unresponsive server fails the call with DeadlineExceeded. Streaming
+    // calls (scan, bulk_put) aren't limited.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Compresses requests with this encoding and asks the server to compress
+    // responses; gzip unless set. The server must accept the encoding (ours
+    // do unless built with `.compression(None)`), otherwise calls fail with
+    // Unimplemented. None sends everything uncompressed.
+    pub fn compression(mut self, compression: Option<CompressionEncoding>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // Retries get, list, health, stats and delete when they fail with
+    // Unavailable or DeadlineExceeded, backing off exponentially with jitter
+    // between attempts. Without this every failure is returned straight away.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    // Retries put too. A put that timed out may still have been applied, so
+    // only opt in if writing the same value twice is harmless.
+    pub fn retry_puts(mut self, retry_puts: bool) -> Self {
+        self.retry_puts = retry_puts;
+        self
+    }
+
+    // Sends puts, gets, deletes and lists to the named store instead of the
+    // default one. The store must already exist, see `create_store`.
+    pub fn store(mut self, name: impl Into<String>) -> Self {
+        self.store_name = name.into();
+        self
+    }
+
+    // Enables the local read cache. Gets of a key fetched less than `ttl` ago
+    // are served locally; this client's own puts and deletes invalidate it,
+    // but writes from other clients are only seen once the entry expires.
+    pub fn cache(mut self, ttl: Duration, capacity: usize) -> Self {
+        self.cache = Some((ttl, capacity));
+        self
+    }
+
+    // Connects over TLS, trusting servers whose certificate chains up to
+    // `ca_cert` and is issued for `domain`. `addr` should use https://.
+    pub fn tls(mut self, ca_cert: Certificate, domain: impl Into<String>) -> Self {
+        self.tls = Some(ClientTlsConfig::new().ca_certificate(ca_cert).domain_name(domain));
+        self
+    }
+
+    // Bearer token for servers created with `create_grpc_server_authed`
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn endpoint(&self) -> Result<Endpoint, tonic::transport::Error> {
+        // Keepalive pings notice a connection whose server vanished without
+        // closing it, which would otherwise only show up as timeouts
+        let mut endpoint = Endpoint::from_shared(self.addr.clone())?
+            .http2_keep_alive_interval(KEEPALIVE_INTERVAL)
+            .keep_alive_timeout(KEEPALIVE_TIMEOUT);
+        if let Some(tls) = &self.tls {
+            endpoint = endpoint.tls_config(tls.clone())?;
+        }
+        Ok(endpoint)
+    }
+
+    pub async fn connect(self) -> Result<KvStoreClient, tonic::transport::Error> {
+        let endpoint = self.endpoint()?;
+        let auth = AttachToken { token: self.token };
+        let channel = endpoint.connect().await?;
+        Ok(KvStoreClient {
+            client: service_client(channel, auth.clone(), self.compression),
+            endpoint,
+            auth,
+            disconnected: false,
+            cache: self.cache.map(|(ttl, capacity)| ClientCache::new(ttl, capacity)),
+            store_name: self.store_name,
+            retry: self.retry,
+            retry_puts: self.retry_puts,
+            timeout: self.timeout,
+            compression: self.compression,
+        })
+    }
+
+    // Opens `size` separate connections and returns a pool spreading
+    // requests over them. The cache setting doesn't apply to pools.
+    pub async fn connect_pool(self, size: usize) -> Result<KvStorePool, tonic::transport::Error> {
+        let endpoint = self.endpoint()?;
+        let mut clients = Vec::with_capacity(size.max(1));
+        for _ in 0..size.max(1) {
+            let channel = endpoint.connect().await?;
+            clients.push(service_client(channel, AttachToken { token: self.token.clone() }, self.compression));
+        }
+        Ok(KvStorePool {
+            clients,
+            next: AtomicUsize::new(0),
+            store_name: self.store_name,
+            timeout: self.timeout,
+        })
+    }
+}
+
+// Several connections to one server, used round-robin. A single connection
+// multiplexes concurrent requests too, but they share one HTTP/2 connection's
+// flow control and framing; a pool spreads heavy concurrent traffic over
+// several. Unlike KvStoreClient, methods take `&self`, so one pool can be
+// shared between tasks behind an Arc.
+pub struct KvStorePool {
+    clients: Vec<ServiceClient>,
+    next: AtomicUsize,
+    store_name: String,
+    timeout: Duration,
+}
+
+impl KvStorePool {
+    pub async fn connect(addr: String, size: usize) -> Result<Self, tonic::transport::Error> {
+        KvStoreClient::builder(addr).connect_pool(size).await
+    }
+
+    pub fn size(&self) -> usize {
+        self.clients.len()
+    }
+
+    // Clients share their connection when cloned, so this is cheap
+    fn client(&self) -> ServiceClient {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len();
+        self.clients[i].clone()
+    }
+
+    // Sends `message` on the next connection, limited to the builder's
+    // timeout the way KvStoreClient::call_with_timeout limits its calls
+    async fn call<M, T, F, Fut>(&self, message: M, rpc: F) -> Result<T, tonic::Status>
+    where
+        F: FnOnce(ServiceClient, tonic::Request<M>) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    {
+        let mut request = tonic::Request::new(message);
+        request.set_timeout(self.timeout);
+        let deadline = Instant::now() + self.timeout;
+        match tokio::time::timeout(self.timeout, rpc(self.client(), request)).await {
+            Err(_) => Err(deadline_exceeded(self.timeout)),
+            Ok(Err(status)) if status.code() == tonic::Code::Cancelled && Instant::now() >= deadline => {
+                Err(deadline_exceeded(self.timeout))
+            }
+            Ok(result) => result.map(tonic::Response::into_inner),
+        }
+    }
+
+    pub async fn put(&self, key: u64, value: Value) -> Result<(), tonic::Status> {
+        let request = PutRequest { key, value: Some(value), store_name: self.store_name.clone(), return_old: false, sync: false };
+        self.call(request, |mut client, request| async move { client.put(request).await }).await?;
+        Ok(())
+    }
+
+    pub async fn get(&self, key: u64) -> Result<Option<Value>, tonic::Status> {
+        let request = GetRequest { key, store_name: self.store_name.clone(), metadata_only: false };
+        let response = self.call(request, |mut client, request| async move { client.get(request).await }).await?;
+        Ok(response.value)
+    }
+
+    pub async fn delete(&self, key: u64) -> Result<(), tonic::Status> {
+        let request = DeleteRequest { key, store_name: self.store_name.clone(), return_old: false };
+        self.call(request, |mut client, request| async move { client.delete(request).await }).await?;
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Result<Vec<u64>, tonic::Status> {
+        let request = ListRequest { store_name: self.store_name.clone(), ..Default::default() };
+        let response = self.call(request, |mut client, request| async move { client.list(request).await }).await?;
+        Ok(response.keys)
+    }
+
+    pub async fn health(&self) -> Result<String, tonic::Status> {
+        let response = self.call(HealthRequest {}, |mut client, request| async move { client.health(request).await }).await?;
+        Ok(response.status)
+    }
+}
 
 pub struct KvStoreClient {
-    client: KvStoreServiceClient<Channel>,
+    client: ServiceClient,
+    // The original address, credentials and compression, kept to reconnect
+    // with
+    endpoint: Endpoint,
+    auth: AttachToken,
+    compression: Option<CompressionEncoding>,
+    // Set when a call failed with Unavailable; the next call reconnects first
+    disconnected: bool,
+    cache: Option<ClientCache>,
+    store_name: String,
+    retry: Option<RetryPolicy>,
+    retry_puts: bool,
+    timeout: Duration,
 }
 
 impl KvStoreClient {
     pub async fn connect(addr: String) -> Result<Self, tonic::transport::Error> {
-        let client = KvStoreServiceClient::connect(addr).await?;
-        Ok(Self { client })
+        Self::builder(addr).connect().await
+    }
+
+    pub async fn connect_with_retry(addr: String, policy: RetryPolicy) -> Result<Self, tonic::transport::Error> {
+        Self::builder(addr).retry(policy).connect().await
+    }
+
+    async fn call<M, T, F, Fut>(&mut self, retryable: bool, message: M, rpc: F) -> Result<T, tonic::Status>
+    where
+        M: Clone,
+        F: Fn(ServiceClient, tonic::Request<M>) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    {
+        self.call_with_timeout(retryable, self.timeout, message, rpc).await
+    }
+
+    // Sends `message` through `rpc`, retrying transient failures under the
+    // retry policy if `retryable`. Each attempt gets a fresh request built
+    // from `message`, limited to `timeout`, and a clone of the client, which
+    // shares its channel.
+    async fn call_with_timeout<M, T, F, Fut>(&mut self, retryable: bool, timeout: Duration, message: M, rpc: F) -> Result<T, tonic::Status>
+    where
+        M: Clone,
+        F: Fn(ServiceClient, tonic::Request<M>) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    {
+        let policy = match self.retry {
+            Some(policy) if retryable => policy,
+            _ => RetryPolicy { max_attempts: 1, ..RetryPolicy::default() },
+        };
+        let mut backoff = policy.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            self.ensure_connected().await?;
+            let mut request = tonic::Request::new(message.clone());
+            request.set_timeout(timeout);
+            let deadline = Instant::now() + timeout;
+            // The channel enforces the grpc-timeout header itself but reports
+            // expiry as Cancelled; either way the caller sees DeadlineExceeded
+            let result = match tokio::time::timeout(timeout, rpc(self.client.clone(), request)).await {
+                Err(_) => Err(deadline_exceeded(timeout)),
+                Ok(Err(status)) if status.code() == tonic::Code::Cancelled && Instant::now() >= deadline => {
+                    Err(deadline_exceeded(timeout))
+                }
+                Ok(result) => result,
+            };
+            self.note_failure(&result);
+            match result {
+                Err(status) if attempt < policy.max_attempts && is_transient(&status) => {
+                    sleep_with_jitter(backoff).await;
+                    backoff = (backoff * 2).min(policy.max_backoff);
+                    attempt += 1;
+                }
+                result => return result.map(tonic::Response::into_inner),
+            }
+        }
+    }
+
+    // Unavailable means the server couldn't be reached over this channel,
+    // e.g. because it restarted; the next call reconnects before sending
+    fn note_failure<T>(&mut self, result: &Result<T, tonic::Status>) {
+        if matches!(result, Err(status) if status.code() == tonic::Code::Unavailable) {
+            self.disconnected = true;
+        }
+    }
+
+    // Re-establishes the connection to the original address if the last call
+    // found it broken. Attempts back off like retries (under the default
+    // policy if none is set); if they all fail the call fails with
+    // Unavailable and the next one tries again.
+    async fn ensure_connected(&mut self) -> Result<(), tonic::Status> {
+        if !self.disconnected {
+            return Ok(());
+        }
+        let policy = self.retry.unwrap_or_default();
+        let mut backoff = policy.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.endpoint.connect().await {
+                Ok(channel) => {
+                    self.client = service_client(channel, self.auth.clone(), self.compression);
+                    self.disconnected = false;
+                    return Ok(());
+                }
+                Err(_) if attempt < policy.max_attempts => {
+                    sleep_with_jitter(backoff).await;
+                    backoff = (backoff * 2).min(policy.max_backoff);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(tonic::Status::unavailable(format!(
+                        "Could not reconnect to {}: {}", self.endpoint.uri(), e
+                    )));
+                }
+            }
+        }
+    }
+
+    pub fn builder(addr: String) -> KvStoreClientBuilder {
+        KvStoreClientBuilder::new(addr)
+    }
+
+    pub async fn connect_tls(addr: String, ca_cert: Certificate, domain: &str) -> Result<Self, tonic::transport::Error> {
+        Self::builder(addr).tls(ca_cert, domain).connect().await
+    }
+
+    // Loads a PEM encoded CA certificate to pass to `connect_tls`
+    pub fn load_ca_certificate<P: AsRef<Path>>(path: P) -> std::io::Result<Certificate> {
+        Ok(Certificate::from_pem(std::fs::read(path)?))
     }
 
     pub async fn put(&mut self, key: u64, value: crate::grpc_server::kvstore::Value) -> Result<(), tonic::Status> {
-        let request = tonic::Request::new(PutRequest { key, value: Some(value) });
-        let _response = self.client.put(request).await?;
+        self.put_with_timeout(key, value, self.timeout).await
+    }
+
+    // Like `put`, with `timeout` in place of the client's default
+    pub async fn put_with_timeout(&mut self, key: u64, value: Value, timeout: Duration) -> Result<(), tonic::Status> {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.invalidate(key);
+        }
+        let request = PutRequest { key, value: Some(value), store_name: self.store_name.clone(), return_old: false, sync: false };
+        self.call_with_timeout(self.retry_puts, timeout, request, |mut client, request| async move { client.put(request).await }).await?;
+        Ok(())
+    }
+
+    // Like `put`, but with `sync` the server syncs its write-ahead log
+    // before responding, so the write survives the server losing power.
+    // Without it the server's default applies.
+    pub async fn put_sync(&mut self, key: u64, value: Value, sync: bool) -> Result<(), tonic::Status> {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.invalidate(key);
+        }
+        let request = PutRequest { key, value: Some(value), store_name: self.store_name.clone(), return_old: false, sync };
+        self.call(self.retry_puts, request, |mut client, request| async move { client.put(request).await }).await?;
         Ok(())
     }
 
+    // Like `put`, but returns the value that was replaced, if any. Not
+    // retried: a retry after a lost response would return the value the
+    // first attempt wrote. `bulk_put` can't return old values.
+    pub async fn put_returning(&mut self, key: u64, value: Value) -> Result<Option<Value>, tonic::Status> {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.invalidate(key);
+        }
+        let request = PutRequest { key, value: Some(value), store_name: self.store_name.clone(), return_old: true, sync: false };
+        let response = self.call(false, request, |mut client, request| async move { client.put(request).await }).await?;
+        Ok(response.old_value)
+    }
+
     pub async fn get(&mut self, key: u64) -> Result<Option<crate::grpc_server::kvstore::Value>, tonic::Status> {
-        let request = tonic::Request::new(GetRequest { key });
-        let response = self.client.get(request).await?;
-        Ok(response.into_inner().value)
+        self.get_with_timeout(key, self.timeout).await
+    }
+
+    // Like `get`, with `timeout` in place of the client's default
+    pub async fn get_with_timeout(&mut self, key: u64, timeout: Duration) -> Result<Option<Value>, tonic::Status> {
+        if let Some(value) = self.cache.as_mut().and_then(|cache| cache.get(key)) {
+            return Ok(Some(value));
+        }
+        let request = GetRequest { key, store_name: self.store_name.clone(), metadata_only: false };
+        let value = self.call_with_timeout(true, timeout, request, |mut client, request| async move { client.get(request).await }).await?.value;
+        if let (Some(cache), Some(value)) = (self.cache.as_mut(), value.as_ref()) {
+            cache.insert(key, value.clone());
+        }
+        Ok(value)
+    }
+
+    // Values of `keys` in the client's store, in the same order, with None
+    // for missing keys. Cached keys are served locally and only the rest
+    // are fetched, in one round trip.
+    pub async fn get_batch(&mut self, keys: Vec<u64>) -> Result<Vec<Option<Value>>, tonic::Status> {
+        let mut values: Vec<Option<Value>> = match self.cache.as_mut() {
+            Some(cache) => keys.iter().map(|key| cache.get(*key)).collect(),
+            None => vec![None; keys.len()],
+        };
+        let missing: Vec<usize> = (0..keys.len()).filter(|&i| values[i].is_none()).collect();
+        if missing.is_empty() {
+            return Ok(values);
+        }
+
+        let request = GetBatchRequest {
+            keys: missing.iter().map(|&i| keys[i]).collect(),
+            store_name: self.store_name.clone(),
+        };
+        let response = self.call(true, request, |mut client, request| async move { client.get_batch(request).await }).await?;
+        if response.entries.len() != missing.len() {
+            return Err(tonic::Status::internal(format!(
+                "GetBatch returned {} values for {} keys", response.entries.len(), missing.len())));
+        }
+        for (i, entry) in missing.into_iter().zip(response.entries) {
+            if let (Some(cache), Some(value)) = (self.cache.as_mut(), entry.value.as_ref()) {
+                cache.insert(keys[i], value.clone());
+            }
+            values[i] = entry.value;
+        }
+        Ok(values)
+    }
+
+    // Just the metadata of the value under `key`; the server leaves the
+    // tensor data out of the response
+    pub async fn get_metadata(&mut self, key: u64) -> Result<Option<BTreeMap<String, String>>, tonic::Status> {
+        if let Some(value) = self.cache.as_mut().and_then(|cache| cache.get(key)) {
+            return Ok(Some(value.metadata));
+        }
+        let request = GetRequest { key, store_name: self.store_name.clone(), metadata_only: true };
+        let response = self.call(true, request, |mut client, request| async move { client.get(request).await }).await?;
+        Ok(response.value.map(|value| value.metadata))
+    }
+
+    // Shape, dtype and the other fields of the value under `key`, without
+    // downloading its data
+    pub async fn get_meta(&mut self, key: u64) -> Result<Option<ValueHeader>, tonic::Status> {
+        let request = GetMetaRequest { key, store_name: self.store_name.clone() };
+        let response = self.call(true, request, |mut client, request| async move { client.get_meta(request).await }).await?;
+        Ok(response.header)
     }
 
     pub async fn delete(&mut self, key: u64) -> Result<(), tonic::Status> {
-        let request = tonic::Request::new(DeleteRequest { key });
-        let _response = self.client.delete(request).await?;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.invalidate(key);
+        }
+        let request = DeleteRequest { key, store_name: self.store_name.clone(), return_old: false };
+        self.call(true, request, |mut client, request| async move { client.delete(request).await }).await?;
+        Ok(())
+    }
+
+    // Like `delete`, but returns the value that was deleted, if any. Not
+    // retried: a retry after a lost response would find nothing to delete
+    // and return None.
+    pub async fn delete_returning(&mut self, key: u64) -> Result<Option<Value>, tonic::Status> {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.invalidate(key);
+        }
+        let request = DeleteRequest { key, store_name: self.store_name.clone(), return_old: true };
+        let response = self.call(false, request, |mut client, request| async move { client.delete(request).await }).await?;
+        Ok(response.old_value)
+    }
+
+    // Stores `new` under `key` in the client's store only if its current value
+    // is `expected` (None: only if the key is absent), atomically on the
+    // server. Returns whether the swap happened. Not retried, since a swap
+    // that timed out may have been applied.
+    pub async fn compare_and_swap(&mut self, key: u64, expected: Option<Value>, new: Value) -> Result<bool, tonic::Status> {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.invalidate(key);
+        }
+        let request = CompareAndSwapRequest { key, expected, new_value: Some(new), store_name: self.store_name.clone() };
+        let response = self.call(false, request, |mut client, request| async move { client.compare_and_swap(request).await }).await?;
+        Ok(response.swapped)
+    }
+
+    // Deletes `keys` from the client's store in one atomic write and returns
+    // how many of them existed. Not retried, so a lost response doesn't turn
+    // into a second call reporting 0.
+    pub async fn delete_batch(&mut self, keys: Vec<u64>) -> Result<u64, tonic::Status> {
+        if let Some(cache) = self.cache.as_mut() {
+            for key in &keys {
+                cache.invalidate(*key);
+            }
+        }
+        let request = DeleteBatchRequest { keys, store_name: self.store_name.clone() };
+        let response = self.call(false, request, |mut client, request| async move { client.delete_batch(request).await }).await?;
+        Ok(response.deleted)
+    }
+
+    // Deletes every key in [start, end)
+    pub async fn delete_range(&mut self, start: u64, end: u64) -> Result<(), tonic::Status> {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.clear();
+        }
+        let request = DeleteRangeRequest { start, end, store_name: self.store_name.clone() };
+        self.call(false, request, |mut client, request| async move { client.delete_range(request).await }).await?;
         Ok(())
     }
 
     pub async fn list(&mut self) -> Result<Vec<u64>, tonic::Status> {
-        let request = tonic::Request::new(ListRequest {});
-        let response = self.client.list(request).await?;
-        Ok(response.into_inner().keys)
+        let request = ListRequest { store_name: self.store_name.clone(), ..Default::default() };
+        let response = self.call(true, request, |mut client, request| async move { client.list(request).await }).await?;
+        Ok(response.keys)
+    }
+
+    // Up to `limit` keys after `start_after`, in ascending order, and the
+    // cursor to pass as `start_after` for the next page (None on the last)
+    pub async fn list_page(&mut self, start_after: Option<u64>, limit: u32) -> Result<(Vec<u64>, Option<u64>), tonic::Status> {
+        let request = ListRequest { store_name: self.store_name.clone(), start_after, limit };
+        let response = self.call(true, request, |mut client, request| async move { client.list(request).await }).await?;
+        Ok((response.keys, response.next_cursor))
+    }
+
+    pub async fn create_store(&mut self, name: &str) -> Result<(), tonic::Status> {
+        let request = CreateStoreRequest { name: name.to_string(), ..Default::default() };
+        self.call(false, request, |mut client, request| async move { client.create_store(request).await }).await?;
+        Ok(())
+    }
+
+    // Number of entries in the store; much cheaper than `list().len()`
+    pub async fn count(&mut self) -> Result<u64, tonic::Status> {
+        let request = CountRequest { store_name: self.store_name.clone() };
+        let response = self.call(true, request, |mut client, request| async move { client.count(request).await }).await?;
+        Ok(response.count)
+    }
+
+    // Whether `key` is in the client's store; the value isn't transferred
+    pub async fn exists(&mut self, key: u64) -> Result<bool, tonic::Status> {
+        let request = ExistsRequest { key, store_name: self.store_name.clone() };
+        let response = self.call(true, request, |mut client, request| async move { client.exists(request).await }).await?;
+        Ok(response.exists)
+    }
+
+    pub async fn exists_batch(&mut self, keys: Vec<u64>) -> Result<Vec<bool>, tonic::Status> {
+        let request = ExistsBatchRequest { keys, store_name: self.store_name.clone() };
+        let response = self.call(true, request, |mut client, request| async move { client.exists_batch(request).await }).await?;
+        Ok(response.exists)
+    }
+
+    // Has the server write its memtables to SST files and sync its log, so
+    // every write so far is durable without replaying the log. Retried,
+    // since flushing twice is harmless.
+    pub async fn flush(&mut self) -> Result<(), tonic::Status> {
+        let request = FlushRequest { wal_only: false };
+        self.call(true, request, |mut client, request| async move { client.flush(request).await }).await?;
+        Ok(())
+    }
+
+    // Has the server fsync its write-ahead log only: cheaper than `flush`,
+    // and writes still survive a crash, by replay on the next open
+    pub async fn sync_wal(&mut self) -> Result<(), tonic::Status> {
+        let request = FlushRequest { wal_only: true };
+        self.call(true, request, |mut client, request| async move { client.flush(request).await }).await?;
+        Ok(())
+    }
+
+    // The server's health probe in full: status plus the default store's
+    // entry count, size and whether it accepts writes
+    pub async fn stats(&mut self) -> Result<StoreStats, tonic::Status> {
+        let response = self.call(true, HealthRequest {}, |mut client, request| async move { client.health(request).await }).await?;
+        Ok(StoreStats {
+            healthy: response.status == "healthy",
+            entries: response.entries,
+            db_size_bytes: response.db_size_bytes,
+            writable: response.writable,
+        })
     }
 
     pub async fn health(&mut self) -> Result<String, tonic::Status> {
-        let request = tonic::Request::new(HealthRequest {});
-        let response = self.client.health(request).await?;
-        Ok(response.into_inner().status)
+        let response = self.call(true, HealthRequest {}, |mut client, request| async move { client.health(request).await }).await?;
+        Ok(response.status)
+    }
+
+    pub async fn aggregate(&mut self, start: Option<u64>, end: Option<u64>, kind: AggKind) -> Result<AggregateResponse, tonic::Status> {
+        let request = AggregateRequest { start, end, kind: kind as i32, store_name: self.store_name.clone() };
+        self.call(true, request, |mut client, request| async move { client.aggregate(request).await }).await
+    }
+
+    // Applies all operations atomically: if the server rejects any of them,
+    // none is written
+    pub async fn batch(&mut self, ops: Vec<BatchOp>) -> Result<(), tonic::Status> {
+        if let Some(cache) = self.cache.as_mut() {
+            for op in &ops {
+                match &op.op {
+                    Some(batch_op::Op::Put(put)) => cache.invalidate(put.key),
+                    Some(batch_op::Op::Delete(delete)) => cache.invalidate(delete.key),
+                    None => {}
+                }
+            }
+        }
+        let request = BatchRequest { ops, store_name: self.store_name.clone() };
+        self.call(false, request, |mut client, request| async move { client.batch(request).await }).await?;
+        Ok(())
     }
-} 
\ No newline at end of file
+
+    // Streams `items` to the server, which writes them in batches, and returns
+    // how many were stored. On error, batches the server already wrote stay
+    // written.
+    pub async fn bulk_put<S>(&mut self, items: S) -> Result<u64, tonic::Status>
+    where
+        S: Stream<Item = (u64, Value)> + Send + 'static,
+    {
+        // The keys aren't known up front, so drop the whole cache
+        if let Some(cache) = self.cache.as_mut() {
+            cache.clear();
+        }
+        let store_name = self.store_name.clone();
+        let requests = items.map(move |(key, value)| PutRequest { key, value: Some(value), store_name: store_name.clone(), return_old: false, sync: false });
+        self.ensure_connected().await?;
+        let response = self.client.bulk_put(requests).await;
+        self.note_failure(&response);
+        Ok(response?.into_inner().count)
+    }
+
+    // Streams the client's store's entries in key order from `start` on. The
+    // server sends them in pages and pauses while this client falls behind;
+    // dropping the stream ends the scan on the server too. Bypasses the cache.
+    #[allow(clippy::result_large_err)] // tonic::Status is this client's error type
+    pub async fn scan(&mut self, start: Option<u64>) -> Result<impl Stream<Item = Result<(u64, Value), tonic::Status>>, tonic::Status> {
+        let request = tonic::Request::new(ScanRequest { start, page_size: 0, store_name: self.store_name.clone() });
+        self.ensure_connected().await?;
+        let response = self.client.scan(request).await;
+        self.note_failure(&response);
+        let pages = response?.into_inner();
+        Ok(pages.flat_map(|page| {
+            let entries: Vec<Result<(u64, Value), tonic::Status>> = match page {
+                Ok(page) => page.entries
+                    .into_iter()
+                    .map(|entry| match entry.value {
+                        Some(value) => Ok((entry.key, value)),
+                        None => Err(tonic::Status::internal(format!("Scan entry {} has no value", entry.key))),
+                    })
+                    .collect(),
+                Err(status) => vec![Err(status)],
+            };
+            stream::iter(entries)
+        }))
+    }
+
+    // Streams the puts and deletes made from now on to keys in [start, end)
+    // of the client's store; an unset bound leaves that end open. Events are
+    // best-effort, see WatchEvent.missed. Like `scan` the stream isn't
+    // retried or limited by the client's timeout.
+    pub async fn watch(&mut self, start: Option<u64>, end: Option<u64>) -> Result<impl Stream<Item = Result<WatchEvent, tonic::Status>>, tonic::Status> {
+        let request = tonic::Request::new(WatchRequest { start, end, store_name: self.store_name.clone() });
+        self.ensure_connected().await?;
+        let response = self.client.watch(request).await;
+        self.note_failure(&response);
+        Ok(response?.into_inner())
+    }
+
+    pub async fn list_stores(&mut self) -> Result<Vec<StoreInfo>, tonic::Status> {
+        let response = self.call(true, ListStoresRequest {}, |mut client, request| async move { client.list_stores(request).await }).await?;
+        Ok(response.stores)
+    }
+}
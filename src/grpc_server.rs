@@ -1,28 +1,211 @@
+use std::path::Path;
 use std::sync::Arc;
-use tonic::{Request, Response, Status};
+use std::time::{Duration, Instant};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use sha2::{Digest, Sha256};
+use tonic::codec::CompressionEncoding;
+use tonic::codegen::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::server::Router;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use prost::Message;
+use tonic::{Request, Response, Status, Streaming};
+use tonic_reflection::server::{ServerReflection, ServerReflectionServer};
 
-use crate::{KVStore};
+use crate::metrics::Metrics;
+use crate::request_log::RequestLog;
+use crate::{AggResult, KVStore, RocksDBStore, StoreError, WriteOp};
 
 // Include the generated protobuf code
 pub mod kvstore {
     tonic::include_proto!("kvstore");
+
+    // Encoded descriptors of kvstore.proto, for the reflection service
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("kvstore_descriptor");
 }
 
 use kvstore::kv_store_service_server::{KvStoreService, KvStoreServiceServer};
 use kvstore::{
+    AggKind, AggregateRequest, AggregateResponse,
+    BatchRequest, BatchResponse, batch_op, BulkPutResponse,
+    CompareAndSwapRequest, CompareAndSwapResponse,
+    CountRequest, CountResponse,
     CreateStoreRequest, CreateStoreResponse,
-    DeleteRequest, DeleteResponse, GetRequest, GetResponse,
+    DeleteBatchRequest, DeleteBatchResponse,
+    DeleteRangeRequest, DeleteRangeResponse,
+    ExistsBatchRequest, ExistsBatchResponse, ExistsRequest, ExistsResponse,
+    FlushRequest, FlushResponse,
+    DeleteRequest, DeleteResponse, GetBatchEntry, GetBatchRequest, GetBatchResponse, GetRequest, GetResponse,
+    GetMetaRequest, GetMetaResponse,
     HealthRequest, HealthResponse, ListRequest, ListResponse,
+    ListStoresRequest, ListStoresResponse, StoreInfo,
     PutRequest, PutResponse,
+    ScanEntry, ScanRequest, ScanResponse, Value,
+    WatchEvent, WatchRequest,
 };
 
+// Entries per Scan message when the request doesn't say, and the most a
+// request may ask for
+pub const DEFAULT_SCAN_PAGE_SIZE: usize = 100;
+pub const MAX_SCAN_PAGE_SIZE: usize = 10_000;
+
+// Items BulkPut collects into one WriteBatch unless configured otherwise
+pub const DEFAULT_BULK_PUT_BATCH_SIZE: usize = 1000;
+
+// Scan pages buffered ahead of a slow client before the scan pauses
+const SCAN_BUFFERED_PAGES: usize = 4;
+
+// Watch events buffered for a slow client; past them the watch lags behind
+// the store and starts missing events
+const WATCH_BUFFERED_EVENTS: usize = 256;
+
+// Name under which the server's store is reported
+pub const DEFAULT_STORE_NAME: &str = "default";
+
+// Parses a `grpc-timeout` header value (e.g. "100m", "5S") per the gRPC spec
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount.saturating_mul(3600))),
+        "M" => Some(Duration::from_secs(amount.saturating_mul(60))),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+// Deadline the client attached to the call, so server-side scans can give up
+// once the client has stopped waiting for them
+fn request_deadline<T>(request: &Request<T>) -> Option<Instant> {
+    let timeout = request.metadata().get("grpc-timeout")?.to_str().ok()?;
+    Some(Instant::now() + parse_grpc_timeout(timeout)?)
+}
+
+fn store_status(e: anyhow::Error) -> Status {
+    match e.downcast_ref::<StoreError>() {
+        Some(StoreError::Timeout) => Status::deadline_exceeded("Request exceeded its deadline"),
+        Some(StoreError::InvalidArgument(message)) => Status::invalid_argument(message.clone()),
+        Some(StoreError::Conflict(message)) => Status::aborted(message.clone()),
+        Some(StoreError::NotFound(what)) => Status::not_found(format!("{} does not exist", what)),
+        Some(StoreError::Corruption(message)) => Status::data_loss(message.clone()),
+        Some(StoreError::AlreadyExists(what)) => Status::already_exists(format!("{} already exists", what)),
+        None => Status::internal("Storage error"),
+    }
+}
+
+// Sends `entries` to a scan's stream in pages of `page_size`, stopping at the
+// first error or once the client has gone away
+fn send_scan_pages<I>(entries: I, page_size: usize, tx: &tokio::sync::mpsc::Sender<Result<ScanResponse, Status>>)
+where
+    I: Iterator<Item = anyhow::Result<(u64, Value)>>,
+{
+    let mut page = Vec::with_capacity(page_size);
+    for result in entries {
+        match result {
+            Ok((key, value)) => page.push(ScanEntry { key, value: Some(value) }),
+            Err(e) => {
+                let _ = tx.blocking_send(Err(store_status(e)));
+                return;
+            }
+        }
+        if page.len() == page_size {
+            let full = ScanResponse { entries: std::mem::replace(&mut page, Vec::with_capacity(page_size)) };
+            if tx.blocking_send(Ok(full)).is_err() {
+                return;
+            }
+        }
+    }
+    if !page.is_empty() {
+        let _ = tx.blocking_send(Ok(ScanResponse { entries: page }));
+    }
+}
+
+// Requests name their store; empty and DEFAULT_STORE_NAME both mean the served
+// store itself, any other name is one of its namespaces
+fn namespace(store_name: &str) -> Option<&str> {
+    if store_name.is_empty() || store_name == DEFAULT_STORE_NAME {
+        None
+    } else {
+        Some(store_name)
+    }
+}
+
 pub struct KvStoreGrpcService {
     store: Arc<KVStore>,
+    bulk_put_batch_size: usize,
+    metrics: Arc<Metrics>,
+    compression: Option<CompressionEncoding>,
+    log_requests: bool,
 }
 
 impl KvStoreGrpcService {
     pub fn new(store: Arc<KVStore>) -> Self {
-        Self { store }
+        Self {
+            store,
+            bulk_put_batch_size: DEFAULT_BULK_PUT_BATCH_SIZE,
+            metrics: Arc::new(Metrics::new()),
+            compression: Some(CompressionEncoding::Gzip),
+            log_requests: true,
+        }
+    }
+
+    // Records into `metrics`, e.g. one also served by `metrics::router`,
+    // instead of a private instance
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    // Encoding accepted on requests and used on responses to clients that
+    // accept it; gzip unless set. None serves everything uncompressed.
+    pub fn compression(mut self, compression: Option<CompressionEncoding>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // The tonic server for this service, with its compression applied
+    pub fn into_server(self) -> KvStoreServiceServer<Self> {
+        let compression = self.compression;
+        let server = KvStoreServiceServer::new(self);
+        match compression {
+            Some(encoding) => server.accept_compressed(encoding).send_compressed(encoding),
+            None => server,
+        }
+    }
+
+    // Logs every request (method, key, payload size, peer and latency) in a
+    // tracing span of its own; on unless turned off here. Completions are
+    // logged at info, arrivals at debug.
+    pub fn log_requests(mut self, log_requests: bool) -> Self {
+        self.log_requests = log_requests;
+        self
+    }
+
+    fn log<M: Message>(&self, method: &'static str, request: &Request<M>, key: Option<u64>) -> Option<RequestLog> {
+        self.log_requests.then(|| RequestLog::start(method, key, Some(request.get_ref().encoded_len()), request.remote_addr()))
+    }
+
+    // Number of streamed items BulkPut writes per WriteBatch
+    pub fn bulk_put_batch_size(mut self, batch_size: usize) -> Self {
+        self.bulk_put_batch_size = batch_size.max(1);
+        self
+    }
+
+    async fn write_items(&self, store_name: String, items: Vec<(u64, Value)>) -> anyhow::Result<()> {
+        self.store.run_blocking(move |store| match namespace(&store_name) {
+            None => store.put_batch(items),
+            Some(namespace) => store.write_batch_cf(
+                namespace,
+                items.into_iter().map(|(key, value)| WriteOp::Put(key, value)).collect(),
+            ),
+        }).await
     }
 }
 
@@ -32,10 +215,16 @@ impl KvStoreService for KvStoreGrpcService {
         &self,
         request: Request<CreateStoreRequest>,
     ) -> Result<Response<CreateStoreResponse>, Status> {
+        let _timer = self.metrics.time("create_store");
+        let _log = self.log("create_store", &request, None);
         let req = request.into_inner();
         
-        // For now, just return success since the store is already created
-        // In a real implementation, you might create a new store instance
+        let Some(namespace) = namespace(&req.name) else {
+            return Err(Status::already_exists(format!("Store '{}' already exists", DEFAULT_STORE_NAME)));
+        };
+        // Each named store is a namespace of the served store
+        let namespace = namespace.to_string();
+        self.store.run_blocking(move |store| store.create_namespace(&namespace)).await.map_err(store_status)?;
         Ok(Response::new(CreateStoreResponse {
             success: true,
             message: format!("Store '{}' created successfully", req.name),
@@ -46,6 +235,8 @@ impl KvStoreService for KvStoreGrpcService {
         &self,
         request: Request<PutRequest>,
     ) -> Result<Response<PutResponse>, Status> {
+        let _timer = self.metrics.time("put");
+        let _log = self.log("put", &request, Some(request.get_ref().key));
         let req = request.into_inner();
         
         let value = match req.value {
@@ -53,8 +244,14 @@ impl KvStoreService for KvStoreGrpcService {
             None => return Err(Status::invalid_argument("Value is required")),
         };
 
-        let existing = self.store.put(req.key, value.clone())
-            .map_err(|_| Status::internal("Storage error"))?;
+        RocksDBStore::validate_value(req.key, &value).map_err(store_status)?;
+        let existing = match (namespace(&req.store_name).map(str::to_string), req.sync) {
+            (None, false) => self.store.put_async(req.key, value).await,
+            (None, true) => self.store.run_blocking(move |store| store.put_sync(req.key, value, true)).await,
+            (Some(namespace), false) => self.store.run_blocking(move |store| store.put_cf(&namespace, req.key, value)).await,
+            (Some(namespace), true) => self.store.run_blocking(move |store| store.put_sync_cf(&namespace, req.key, value, true)).await,
+        }.map_err(store_status)?;
+        self.metrics.record("put", 1);
         
         let message = if existing.is_some() {
             "Value updated successfully"
@@ -62,10 +259,13 @@ impl KvStoreService for KvStoreGrpcService {
             "Value stored successfully"
         };
 
+        // The store reads the old value anyway, so returning it costs only
+        // the bytes on the wire
         Ok(Response::new(PutResponse {
             key: req.key,
             success: true,
             message: message.to_string(),
+            old_value: existing.filter(|_| req.return_old),
         }))
     }
 
@@ -73,10 +273,18 @@ impl KvStoreService for KvStoreGrpcService {
         &self,
         request: Request<GetRequest>,
     ) -> Result<Response<GetResponse>, Status> {
+        let _timer = self.metrics.time("get");
+        let _log = self.log("get", &request, Some(request.get_ref().key));
         let req = request.into_inner();
         
-        let value = self.store.get(&req.key)
-            .map_err(|_| Status::internal("Storage error"))?;
+        let key = req.key;
+        let value = match (namespace(&req.store_name).map(str::to_string), req.metadata_only) {
+            (None, false) => self.store.get_async(key).await,
+            (None, true) => self.store.run_blocking(move |store| store.get_without_data(&key)).await,
+            (Some(namespace), false) => self.store.run_blocking(move |store| store.get_cf(&namespace, &key)).await,
+            (Some(namespace), true) => self.store.run_blocking(move |store| store.get_without_data_cf(&namespace, &key)).await,
+        }.map_err(store_status)?;
+        self.metrics.record("get", 1);
         
         let (success, message) = if value.is_some() {
             (true, "Value retrieved successfully")
@@ -96,12 +304,23 @@ impl KvStoreService for KvStoreGrpcService {
         &self,
         request: Request<DeleteRequest>,
     ) -> Result<Response<DeleteResponse>, Status> {
+        let _timer = self.metrics.time("delete");
+        let _log = self.log("delete", &request, Some(request.get_ref().key));
         let req = request.into_inner();
         
-        let deleted = self.store.delete(&req.key)
-            .map_err(|_| Status::internal("Storage error"))?;
+        let key = req.key;
+        // Only decode the old value if it's wanted
+        let (deleted, old_value) = match (namespace(&req.store_name).map(str::to_string), req.return_old) {
+            (None, true) => self.store.delete_async(key).await.map(|old| (old.is_some(), old)),
+            (None, false) => self.store.run_blocking(move |store| store.remove(&key)).await.map(|deleted| (deleted, None)),
+            (Some(namespace), true) => self.store.run_blocking(move |store| store.delete_cf(&namespace, &key)).await
+                .map(|old| (old.is_some(), old)),
+            (Some(namespace), false) => self.store.run_blocking(move |store| store.remove_cf(&namespace, &key)).await
+                .map(|deleted| (deleted, None)),
+        }.map_err(store_status)?;
+        self.metrics.record("delete", 1);
         
-        let (success, message) = if deleted.is_some() {
+        let (success, message) = if deleted {
             (true, "Value deleted successfully")
         } else {
             (false, "Value not found")
@@ -111,15 +330,28 @@ impl KvStoreService for KvStoreGrpcService {
             key: req.key,
             success,
             message: message.to_string(),
+            old_value,
         }))
     }
 
     async fn list(
         &self,
-        _request: Request<ListRequest>,
+        request: Request<ListRequest>,
     ) -> Result<Response<ListResponse>, Status> {
-        let keys = self.store.keys()
-            .map_err(|_| Status::internal("Storage error"))?;
+        let _timer = self.metrics.time("list");
+        let _log = self.log("list", &request, None);
+        let deadline = request_deadline(&request);
+        let req = request.into_inner();
+        let limit = match req.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        let (keys, next_cursor) = self.store.run_blocking(move |store| match (namespace(&req.store_name), req.start_after, limit) {
+            (None, None, usize::MAX) => Ok((store.keys_with_deadline(deadline)?, None)),
+            (Some(namespace), None, usize::MAX) => Ok((store.keys_cf_with_deadline(namespace, deadline)?, None)),
+            (None, start_after, limit) => store.keys_page_with_deadline(start_after, limit, deadline),
+            (Some(namespace), start_after, limit) => store.keys_page_cf_with_deadline(namespace, start_after, limit, deadline),
+        }).await.map_err(store_status)?;
         
         let count = keys.len() as u32;
 
@@ -127,6 +359,7 @@ impl KvStoreService for KvStoreGrpcService {
             keys,
             count,
             success: true,
+            next_cursor,
         }))
     }
 
@@ -134,13 +367,481 @@ impl KvStoreService for KvStoreGrpcService {
         &self,
         _request: Request<HealthRequest>,
     ) -> Result<Response<HealthResponse>, Status> {
+        // Probes the store rather than just answering, so a wedged RocksDB
+        // reports unhealthy. Failures are part of the answer, not an error.
+        let probe = self.store.run_blocking(|store| {
+            Ok((store.len()? as u64, store.get_db_size()?, store.accepts_writes()?))
+        }).await;
+        let (healthy, entries, db_size_bytes, writable) = match probe {
+            Ok((entries, db_size_bytes, writable)) => (writable, entries, db_size_bytes, writable),
+            Err(_) => (false, 0, 0, false),
+        };
         Ok(Response::new(HealthResponse {
-            status: "healthy".to_string(),
+            status: if healthy { "healthy" } else { "unhealthy" }.to_string(),
             service: "rust-kv-store".to_string(),
+            entries,
+            db_size_bytes,
+            writable,
+        }))
+    }
+
+    async fn aggregate(
+        &self,
+        request: Request<AggregateRequest>,
+    ) -> Result<Response<AggregateResponse>, Status> {
+        let _timer = self.metrics.time("aggregate");
+        let _log = self.log("aggregate", &request, None);
+        let deadline = request_deadline(&request);
+        let req = request.into_inner();
+        let kind = AggKind::try_from(req.kind)
+            .map_err(|_| Status::invalid_argument("Unknown aggregation kind"))?;
+
+        let start = req.start.unwrap_or(0);
+        let result = match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.run_blocking(move |store| store.aggregate(start, req.end, kind, deadline)).await,
+            Some(namespace) => self.store
+                .run_blocking(move |store| store.aggregate_cf(&namespace, start, req.end, kind, deadline))
+                .await,
+        }.map_err(|e| match e.downcast_ref::<StoreError>() {
+            Some(_) => store_status(e),
+            None => Status::failed_precondition(e.to_string()),
+        })?;
+
+        let mut response = AggregateResponse {
+            kind: kind as i32,
+            success: true,
+            ..Default::default()
+        };
+        match result {
+            AggResult::Count(count) => response.count = count,
+            AggResult::TotalBytes(total_bytes) => response.total_bytes = total_bytes,
+            AggResult::Sum(sum) => response.sum = sum,
+        }
+
+        Ok(Response::new(response))
+    }
+
+    async fn batch(
+        &self,
+        request: Request<BatchRequest>,
+    ) -> Result<Response<BatchResponse>, Status> {
+        let _timer = self.metrics.time("batch");
+        let _log = self.log("batch", &request, None);
+        let req = request.into_inner();
+
+        let mut ops = Vec::with_capacity(req.ops.len());
+        for (i, op) in req.ops.into_iter().enumerate() {
+            ops.push(match op.op {
+                Some(batch_op::Op::Put(put)) => match put.value {
+                    Some(value) => {
+                        RocksDBStore::validate_value(put.key, &value).map_err(store_status)?;
+                        WriteOp::Put(put.key, value)
+                    }
+                    None => return Err(Status::invalid_argument(format!("Operation {}: value is required", i))),
+                },
+                Some(batch_op::Op::Delete(delete)) => WriteOp::Delete(delete.key),
+                None => return Err(Status::invalid_argument(format!("Operation {}: no operation given", i))),
+            });
+        }
+        let applied = ops.len() as u32;
+        let puts = ops.iter().filter(|op| matches!(op, WriteOp::Put(..))).count() as u64;
+
+        match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.write_batch_async(ops).await,
+            Some(namespace) => self.store.run_blocking(move |store| store.write_batch_cf(&namespace, ops)).await,
+        }.map_err(store_status)?;
+        self.metrics.record("put", puts);
+        self.metrics.record("delete", applied as u64 - puts);
+
+        Ok(Response::new(BatchResponse {
+            success: true,
+            applied,
+        }))
+    }
+
+    type ScanStream = ReceiverStream<Result<ScanResponse, Status>>;
+
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> Result<Response<Self::ScanStream>, Status> {
+        // Covers setting the scan up; the stream outlives the handler
+        let _log = self.log("scan", &request, request.get_ref().start);
+        let req = request.into_inner();
+        let page_size = match req.page_size as usize {
+            0 => DEFAULT_SCAN_PAGE_SIZE,
+            n => n.min(MAX_SCAN_PAGE_SIZE),
+        };
+
+        // The iterator stays open on a blocking thread for the whole stream.
+        // Sends fail once the client goes away, which ends the scan and
+        // drops the iterator.
+        let store = self.store.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(SCAN_BUFFERED_PAGES);
+        tokio::task::spawn_blocking(move || {
+            let start = req.start.unwrap_or(0);
+            match namespace(&req.store_name) {
+                None => send_scan_pages(store.iter_from(start), page_size, &tx),
+                Some(namespace) => match store.iter_from_cf(namespace, start) {
+                    Ok(iter) => send_scan_pages(iter, page_size, &tx),
+                    Err(e) => {
+                        let _ = tx.blocking_send(Err(store_status(e)));
+                    }
+                },
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type WatchStream = ReceiverStream<Result<WatchEvent, Status>>;
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        // Covers setting the watch up; the stream outlives the handler
+        let _log = self.log("watch", &request, request.get_ref().start);
+        let req = request.into_inner();
+        // Default store events have an empty store_name
+        let store_name = namespace(&req.store_name).unwrap_or_default().to_string();
+        let watched = move |event: &WatchEvent| event.store_name == store_name
+            && req.start.is_none_or(|start| event.key >= start)
+            && req.end.is_none_or(|end| event.key < end);
+
+        // Subscribed before responding, so every change made once the client
+        // has the stream is reported
+        let mut changes = self.store.watch();
+        let (tx, rx) = tokio::sync::mpsc::channel(WATCH_BUFFERED_EVENTS);
+        tokio::spawn(async move {
+            let mut missed = 0;
+            loop {
+                let event = tokio::select! {
+                    // Ends the watch when the client goes away, not at the
+                    // next change
+                    _ = tx.closed() => return,
+                    event = changes.recv() => event,
+                };
+                match event {
+                    Ok(mut event) if watched(&event) => {
+                        event.missed = std::mem::take(&mut missed);
+                        if tx.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(dropped)) => missed += dropped,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn bulk_put(
+        &self,
+        request: Request<Streaming<PutRequest>>,
+    ) -> Result<Response<BulkPutResponse>, Status> {
+        let _timer = self.metrics.time("bulk_put");
+        let _log = self.log_requests.then(|| RequestLog::start("bulk_put", None, None, request.remote_addr()));
+        let mut requests = request.into_inner();
+        let mut items = Vec::with_capacity(self.bulk_put_batch_size);
+        let mut store_name = String::new();
+        let mut count = 0u64;
+
+        while let Some(req) = requests.next().await {
+            let req = req?;
+            let value = req.value
+                .ok_or_else(|| Status::invalid_argument(format!("Value is required for key {}", req.key)))?;
+            RocksDBStore::validate_value(req.key, &value).map_err(store_status)?;
+            // A batch only ever targets one store
+            if req.store_name != store_name && !items.is_empty() {
+                count += items.len() as u64;
+                self.write_items(store_name.clone(), std::mem::take(&mut items)).await.map_err(store_status)?;
+            }
+            store_name = req.store_name;
+            items.push((req.key, value));
+            if items.len() >= self.bulk_put_batch_size {
+                count += items.len() as u64;
+                self.write_items(store_name.clone(), std::mem::take(&mut items)).await.map_err(store_status)?;
+            }
+        }
+        // The final, partial batch
+        if !items.is_empty() {
+            count += items.len() as u64;
+            self.write_items(store_name, items).await.map_err(store_status)?;
+        }
+
+        self.metrics.record("put", count);
+        Ok(Response::new(BulkPutResponse {
+            count,
+            success: true,
+        }))
+    }
+
+    async fn count(
+        &self,
+        request: Request<CountRequest>,
+    ) -> Result<Response<CountResponse>, Status> {
+        let _timer = self.metrics.time("count");
+        let _log = self.log("count", &request, None);
+        let req = request.into_inner();
+        // The default store keeps a running count, so this is O(1) there
+        let count = match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.len(),
+            Some(namespace) => self.store.run_blocking(move |store| store.len_cf(&namespace)).await,
+        }.map_err(store_status)?;
+
+        Ok(Response::new(CountResponse {
+            count: count as u64,
+        }))
+    }
+
+    async fn delete_range(
+        &self,
+        request: Request<DeleteRangeRequest>,
+    ) -> Result<Response<DeleteRangeResponse>, Status> {
+        let _timer = self.metrics.time("delete_range");
+        let _log = self.log("delete_range", &request, None);
+        let req = request.into_inner();
+        match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.delete_range_async(req.start, req.end).await,
+            Some(namespace) => self.store.run_blocking(move |store| store.delete_range_cf(&namespace, req.start, req.end)).await,
+        }.map_err(store_status)?;
+
+        Ok(Response::new(DeleteRangeResponse { success: true }))
+    }
+
+    async fn exists(
+        &self,
+        request: Request<ExistsRequest>,
+    ) -> Result<Response<ExistsResponse>, Status> {
+        let _timer = self.metrics.time("exists");
+        let _log = self.log("exists", &request, Some(request.get_ref().key));
+        let req = request.into_inner();
+        let key = req.key;
+        let exists = match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.run_blocking(move |store| store.contains_key(&key)).await,
+            Some(namespace) => self.store.run_blocking(move |store| store.contains_key_cf(&namespace, &key)).await,
+        }.map_err(store_status)?;
+
+        Ok(Response::new(ExistsResponse { exists }))
+    }
+
+    async fn exists_batch(
+        &self,
+        request: Request<ExistsBatchRequest>,
+    ) -> Result<Response<ExistsBatchResponse>, Status> {
+        let _timer = self.metrics.time("exists_batch");
+        let _log = self.log("exists_batch", &request, None);
+        let req = request.into_inner();
+        let keys = req.keys;
+        let exists = match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.run_blocking(move |store| store.contains_keys(&keys)).await,
+            Some(namespace) => self.store.run_blocking(move |store| store.contains_keys_cf(&namespace, &keys)).await,
+        }.map_err(store_status)?;
+
+        Ok(Response::new(ExistsBatchResponse { exists }))
+    }
+
+    async fn delete_batch(
+        &self,
+        request: Request<DeleteBatchRequest>,
+    ) -> Result<Response<DeleteBatchResponse>, Status> {
+        let _timer = self.metrics.time("delete_batch");
+        let _log = self.log("delete_batch", &request, None);
+        let req = request.into_inner();
+        let keys = req.keys;
+        let deleted = match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.run_blocking(move |store| store.delete_batch(&keys)).await,
+            Some(namespace) => self.store.run_blocking(move |store| store.delete_batch_cf(&namespace, &keys)).await,
+        }.map_err(store_status)?;
+        self.metrics.record("delete", deleted);
+
+        Ok(Response::new(DeleteBatchResponse { deleted }))
+    }
+
+    async fn get_batch(
+        &self,
+        request: Request<GetBatchRequest>,
+    ) -> Result<Response<GetBatchResponse>, Status> {
+        let _timer = self.metrics.time("get_batch");
+        let _log = self.log("get_batch", &request, None);
+        let req = request.into_inner();
+        let keys = req.keys;
+        let values = match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.run_blocking(move |store| store.multi_get(&keys)).await,
+            Some(namespace) => self.store.run_blocking(move |store| store.multi_get_cf(&namespace, &keys)).await,
+        }.map_err(store_status)?;
+        self.metrics.record("get", values.len() as u64);
+
+        Ok(Response::new(GetBatchResponse {
+            entries: values.into_iter().map(|value| GetBatchEntry { value }).collect(),
+        }))
+    }
+
+    async fn flush(
+        &self,
+        request: Request<FlushRequest>,
+    ) -> Result<Response<FlushResponse>, Status> {
+        let _timer = self.metrics.time("flush");
+        let _log = self.log("flush", &request, None);
+        let wal_only = request.into_inner().wal_only;
+        self.store.run_blocking(move |store| if wal_only { store.sync_wal() } else { store.flush() })
+            .await
+            .map_err(store_status)?;
+
+        Ok(Response::new(FlushResponse { success: true }))
+    }
+
+    async fn get_meta(
+        &self,
+        request: Request<GetMetaRequest>,
+    ) -> Result<Response<GetMetaResponse>, Status> {
+        let _timer = self.metrics.time("get_meta");
+        let _log = self.log("get_meta", &request, Some(request.get_ref().key));
+        let req = request.into_inner();
+        let key = req.key;
+        let header = match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.run_blocking(move |store| store.get_meta(&key)).await,
+            Some(namespace) => self.store.run_blocking(move |store| store.get_meta_cf(&namespace, &key)).await,
+        }.map_err(store_status)?;
+        self.metrics.record("get", 1);
+
+        Ok(Response::new(GetMetaResponse { header }))
+    }
+
+    async fn compare_and_swap(
+        &self,
+        request: Request<CompareAndSwapRequest>,
+    ) -> Result<Response<CompareAndSwapResponse>, Status> {
+        let _timer = self.metrics.time("compare_and_swap");
+        let _log = self.log("compare_and_swap", &request, Some(request.get_ref().key));
+        let req = request.into_inner();
+        let new = req.new_value.ok_or_else(|| Status::invalid_argument("New value is required"))?;
+        RocksDBStore::validate_value(req.key, &new).map_err(store_status)?;
+
+        let swapped = match namespace(&req.store_name).map(str::to_string) {
+            None => self.store.run_blocking(move |store| store.compare_and_swap(req.key, req.expected, new)).await,
+            Some(namespace) => self.store
+                .run_blocking(move |store| store.compare_and_swap_cf(&namespace, req.key, req.expected, new))
+                .await,
+        }.map_err(store_status)?;
+        if swapped {
+            self.metrics.record("put", 1);
+        }
+
+        Ok(Response::new(CompareAndSwapResponse { swapped }))
+    }
+
+    async fn list_stores(
+        &self,
+        request: Request<ListStoresRequest>,
+    ) -> Result<Response<ListStoresResponse>, Status> {
+        let _timer = self.metrics.time("list_stores");
+        let _log = self.log("list_stores", &request, None);
+        // Counting a namespace scans it
+        let stores = self.store.run_blocking(|store| {
+            let mut stores = vec![StoreInfo {
+                name: DEFAULT_STORE_NAME.to_string(),
+                count: store.len()? as u64,
+                size_bytes: store.get_db_size()?,
+            }];
+            for namespace in store.namespaces()? {
+                let count = store.len_cf(&namespace)?;
+                let size_bytes = store.get_db_size_cf(&namespace)?;
+                stores.push(StoreInfo {
+                    name: namespace,
+                    count: count as u64,
+                    size_bytes,
+                });
+            }
+            Ok(stores)
+        }).await.map_err(store_status)?;
+
+        Ok(Response::new(ListStoresResponse {
+            stores,
+            success: true,
         }))
     }
 }
 
 pub fn create_grpc_server(store: Arc<KVStore>) -> KvStoreServiceServer<KvStoreGrpcService> {
-    KvStoreServiceServer::new(KvStoreGrpcService::new(store))
-} 
\ No newline at end of file
+    KvStoreGrpcService::new(store).into_server()
+}
+
+// gRPC server reflection for the kvstore service, so tools like grpcurl can
+// list and call its methods without the .proto file
+pub fn reflection_service() -> Result<ServerReflectionServer<impl ServerReflection>, tonic_reflection::server::Error> {
+    tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(kvstore::FILE_DESCRIPTOR_SET)
+        .build()
+}
+
+// Same service as `create_grpc_server`, with `reflection_service` next to it.
+// Call `.serve(addr)` on the result to run it.
+pub fn create_grpc_server_with_reflection(store: Arc<KVStore>) -> Result<Router, tonic_reflection::server::Error> {
+    Ok(Server::builder()
+        .add_service(reflection_service()?)
+        .add_service(create_grpc_server(store)))
+}
+
+// Rejects requests whose `authorization` metadata isn't `Bearer <token>`
+#[derive(Clone)]
+pub struct BearerAuth {
+    expected: [u8; 32],
+}
+
+impl BearerAuth {
+    pub fn new(token: &str) -> Self {
+        Self { expected: Sha256::digest(format!("Bearer {}", token)).into() }
+    }
+}
+
+impl Interceptor for BearerAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let provided = request.metadata().get("authorization")
+            .ok_or_else(|| Status::unauthenticated("Missing authorization token"))?;
+        // Comparing fixed-size digests with a constant-time fold means the
+        // time taken reveals neither how much of the token matched nor its length
+        let provided: [u8; 32] = Sha256::digest(provided.as_bytes()).into();
+        let diff = provided.iter().zip(self.expected.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 {
+            return Err(Status::unauthenticated("Invalid authorization token"));
+        }
+        Ok(request)
+    }
+}
+
+// Same service as `create_grpc_server`, but every request must carry
+// `authorization: Bearer <token>` metadata
+pub fn create_grpc_server_authed(store: Arc<KVStore>, token: &str) -> InterceptedService<KvStoreServiceServer<KvStoreGrpcService>, BearerAuth> {
+    InterceptedService::new(KvStoreGrpcService::new(store).into_server(), BearerAuth::new(token))
+}
+
+// Same service as `create_grpc_server`, behind TLS with the given server
+// certificate and key. Call `.serve(addr)` on the result to run it.
+pub fn create_grpc_server_tls(store: Arc<KVStore>, identity: Identity) -> Result<Router, tonic::transport::Error> {
+    Ok(Server::builder()
+        .tls_config(ServerTlsConfig::new().identity(identity))?
+        .add_service(create_grpc_server(store)))
+}
+
+// Loads a server identity from PEM files holding the certificate chain and
+// its private key
+pub fn load_identity<P: AsRef<Path>>(cert_path: P, key_path: P) -> std::io::Result<Identity> {
+    let cert = std::fs::read(cert_path)?;
+    let key = std::fs::read(key_path)?;
+    Ok(Identity::from_pem(cert, key))
+} 
+
+#[test]
+fn test_parse_grpc_timeout() {
+    assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+    assert_eq!(parse_grpc_timeout("5S"), Some(Duration::from_secs(5)));
+    assert_eq!(parse_grpc_timeout("2H"), Some(Duration::from_secs(7200)));
+    assert_eq!(parse_grpc_timeout("250u"), Some(Duration::from_micros(250)));
+    assert_eq!(parse_grpc_timeout("m"), None);
+    assert_eq!(parse_grpc_timeout("10x"), None);
+}
@@ -1,93 +1,988 @@
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, Weak};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use anyhow::Result;
-use rocksdb::{DB, Options, WriteBatch};
+use rocksdb::backup::{BackupEngine, BackupEngineOptions, RestoreOptions};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{BlockBasedOptions, BoundColumnFamily, Cache, ColumnFamilyDescriptor, DBWithThreadMode, DEFAULT_COLUMN_FAMILY_NAME, Env, MultiThreaded, Options, ReadOptions, WriteBatch, WriteOptions};
 use prost::Message;
 
 pub mod grpc_server;
 pub mod grpc_client;
+pub mod metrics;
+pub mod config;
+pub mod request_log;
+pub mod rate_limit;
+mod codec;
+mod dtype;
+mod dump;
+mod error;
+mod merge;
+mod netfs;
+mod value;
+
+pub use error::{with_retry, ErrorBody, RetryPolicy, StoreError};
+use error::{check_deadline, map_rocksdb_error};
+pub use netfs::{detect_network_fs, NetworkFsPolicy};
+pub use rocksdb::DBCompressionType;
+pub use grpc_server::kvstore::Value;
 
 // Include the generated protobuf types
-use grpc_server::kvstore::Value;
+use grpc_server::kvstore::{AggKind, ChangeOp, DataType, ValueHeader, WatchEvent};
+
+// Internal bookkeeping (schema version, counters, ...) lives in its own column
+// family so it can never collide with user u64 keys, which are stored in the
+// default column family. Iteration helpers (`keys`, `len`, `clear`, ...) only
+// ever touch the default column family, so metadata is invisible to users.
+pub const META_CF: &str = "__meta";
+
+// Expiry times of keys written with `put_with_ttl`, as big-endian u64 Unix
+// milliseconds under the same big-endian u64 key as the value. Keys without
+// an entry here never expire.
+pub const TTL_CF: &str = "__ttl";
+
+// Values stored under arbitrary byte-string keys by `put_str` and friends.
+// The key bytes are used as-is (a &str key is its UTF-8 bytes) and the value
+// is framed like any other. Being a separate column family, the string key
+// space can't collide with the big-endian u64 keys of the default one, not
+// even for 8-byte strings, and u64 iteration never sees string keys.
+pub const STR_KEYS_CF: &str = "__str";
+
+// How often KVStore's background thread deletes expired keys
+pub const TTL_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// Column family name prefix for user namespaces, so a namespace can never be
+// mistaken for the default column family or an internal one like META_CF
+const NAMESPACE_CF_PREFIX: &str = "ns/";
+
+// Multi-threaded mode so column families can be created on a shared store
+type Db = DBWithThreadMode<MultiThreaded>;
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+const SCHEMA_VERSION: u64 = 1;
+// Meta key rewritten by `accepts_writes` to check that writes go through
+const WRITE_PROBE_KEY: &str = "write_probe";
+
+// Writers serialize on one of these mutexes (picked by key) so that checking
+// whether a key exists and writing it happen atomically, which keeps the entry
+// counter exact under concurrent puts and deletes of the same key
+const KEY_LOCK_STRIPES: usize = 256;
+
+// Change events buffered for each watcher; one that falls further behind
+// misses the oldest
+pub const WATCH_CHANNEL_CAPACITY: usize = 4096;
+
+// Maximum length, in bytes, of a value's descriptor
+pub const MAX_DESCRIPTOR_LEN: usize = 1024;
+
+// Maximum total length, in bytes, of the keys and values in a value's
+// metadata map; it's meant for small tags, not payload
+pub const MAX_METADATA_LEN: usize = 4096;
+
+// Maximum number of example keys kept per category in a DiffReport
+pub const DIFF_SAMPLE_LIMIT: usize = 100;
+
+// Deletes `clear` puts into one WriteBatch, bounding its memory use
+pub const CLEAR_BATCH_SIZE: usize = 10_000;
+
+// Entries `import` writes per put_batch
+pub const IMPORT_BATCH_SIZE: usize = 1_000;
+
+// Summary of the differences between two stores. Counts are exact; the key
+// lists only hold the first DIFF_SAMPLE_LIMIT keys of each category so that
+// diffing two large stores doesn't hold every key in memory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffReport {
+    pub only_in_self: u64,
+    pub only_in_other: u64,
+    pub different: u64,
+    pub identical: u64,
+    pub sample_only_in_self: Vec<u64>,
+    pub sample_only_in_other: Vec<u64>,
+    pub sample_different: Vec<u64>,
+}
+
+impl DiffReport {
+    pub fn is_identical(&self) -> bool {
+        self.only_in_self == 0 && self.only_in_other == 0 && self.different == 0
+    }
+
+    fn record(count: &mut u64, sample: &mut Vec<u64>, key: u64) {
+        *count += 1;
+        if sample.len() < DIFF_SAMPLE_LIMIT {
+            sample.push(key);
+        }
+    }
+}
+
+// One operation of an atomic multi-key write, see `RocksDBStore::write_batch`
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteOp {
+    Put(u64, Value),
+    Delete(u64),
+}
+
+// What `import` does with a dump entry whose key already holds a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportPolicy {
+    // The dump's value replaces the stored one
+    #[default]
+    Overwrite,
+    // The stored value is kept and the dump entry skipped
+    SkipExisting,
+    // The import fails with StoreError::AlreadyExists before writing anything
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggResult {
+    Count(u64),
+    TotalBytes(u64),
+    Sum(f64),
+}
 
 #[derive(Debug, Clone)]
 pub struct RocksDBStore {
-    db: Arc<DB>,
+    db: Arc<Db>,
+    // Number of user entries, maintained by every write path
+    entries: Arc<AtomicU64>,
+    key_locks: Arc<Vec<Mutex<()>>>,
+    // Kept so namespaces created after open get the same tuning
+    cf_tuning: Arc<CfTuning>,
+    statistics: Option<Arc<Statistics>>,
+    value_zstd_level: Option<i32>,
+    sync_writes: bool,
+    // Puts and deletes of the default store, for `watch`
+    changes: tokio::sync::broadcast::Sender<WatchEvent>,
+}
+
+// The DB options the store was opened with, kept when statistics are on:
+// RocksDB hangs the statistics object off them
+struct Statistics(Options);
+
+impl std::fmt::Debug for Statistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Statistics")
+    }
+}
+
+// Builder settings that apply per column family, plus the block cache they
+// all share
+#[derive(Clone)]
+struct CfTuning {
+    write_buffer_size: Option<usize>,
+    compression: DBCompressionType,
+    zstd_level: Option<i32>,
+    block_cache: Option<Cache>,
+    periodic_compaction: Option<Duration>,
+}
+
+impl std::fmt::Debug for CfTuning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CfTuning")
+            .field("write_buffer_size", &self.write_buffer_size)
+            .field("compression", &self.compression)
+            .field("zstd_level", &self.zstd_level)
+            .field("block_cache", &self.block_cache.is_some())
+            .field("periodic_compaction", &self.periodic_compaction)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RocksDBStoreBuilder {
+    network_fs_policy: NetworkFsPolicy,
+    periodic_compaction: Option<Duration>,
+    namespaces: Vec<String>,
+    max_open_files: i32,
+    use_fsync: bool,
+    write_buffer_size: Option<usize>,
+    compression: DBCompressionType,
+    zstd_level: Option<i32>,
+    block_cache_size: Option<usize>,
+    statistics: bool,
+    value_zstd_level: Option<i32>,
+    sync_writes: bool,
+}
+
+impl Default for RocksDBStoreBuilder {
+    fn default() -> Self {
+        Self {
+            network_fs_policy: NetworkFsPolicy::default(),
+            periodic_compaction: None,
+            namespaces: Vec::new(),
+            max_open_files: 10000,
+            use_fsync: true,
+            write_buffer_size: None,
+            compression: DBCompressionType::Lz4,
+            zstd_level: None,
+            block_cache_size: None,
+            statistics: false,
+            value_zstd_level: None,
+            sync_writes: false,
+        }
+    }
+}
+
+impl RocksDBStoreBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // -1 keeps every SST file open
+    pub fn max_open_files(mut self, max_open_files: i32) -> Self {
+        self.max_open_files = max_open_files;
+        self
+    }
+
+    // With fsync off RocksDB syncs with fdatasync, which skips flushing file
+    // metadata and is cheaper on most filesystems
+    pub fn use_fsync(mut self, use_fsync: bool) -> Self {
+        self.use_fsync = use_fsync;
+        self
+    }
+
+    // Whether writes sync the write-ahead log before returning. Off by
+    // default: a write then survives the process crashing, since the log is
+    // in the OS page cache, but not a power loss or kernel crash until the
+    // next sync. On makes every write durable at the cost of a sync each;
+    // `put_sync` picks per write instead.
+    pub fn sync_writes(mut self, sync: bool) -> Self {
+        self.sync_writes = sync;
+        self
+    }
+
+    // Size of each memtable before it's flushed to an SST file. Unset uses
+    // RocksDB's default (64MB).
+    pub fn write_buffer_size(mut self, bytes: usize) -> Self {
+        self.write_buffer_size = Some(bytes);
+        self
+    }
+
+    // Compression for SST files of user data. Defaults to LZ4, which costs
+    // little CPU and still shrinks tensor payloads well. Changing it only
+    // affects files written from then on; existing files are readable
+    // whatever they were compressed with.
+    pub fn compression(mut self, compression: DBCompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // ZSTD compression level, used when compression is Zstd. Unset uses
+    // ZSTD's default level (3).
+    pub fn zstd_level(mut self, level: i32) -> Self {
+        self.zstd_level = Some(level);
+        self
+    }
+
+    // Size of an LRU block cache shared by the default store and all
+    // namespaces. Unset gives each its own 8MB cache, RocksDB's default.
+    pub fn block_cache_size(mut self, bytes: usize) -> Self {
+        self.block_cache_size = Some(bytes);
+        self
+    }
+
+    // Has RocksDB collect its internal statistics (cache hits, bytes
+    // compacted, ...), see `RocksDBStore::statistics`. Off by default since
+    // the counting and timing costs something on every operation: RocksDB
+    // puts it at 5-10%, more where reading the clock is slow.
+    pub fn statistics(mut self, enabled: bool) -> Self {
+        self.statistics = enabled;
+        self
+    }
+
+    // Zstd-compresses each value as a whole, at `level`, before it's stored.
+    // Tensor payloads often compress much better this way than block by
+    // block. Values stay readable whichever way they were written, so this
+    // can be switched on or off for an existing store; values that don't
+    // shrink, and the results of merge_add, are stored uncompressed.
+    pub fn value_zstd_level(mut self, level: i32) -> Self {
+        self.value_zstd_level = Some(level);
+        self
+    }
+
+    pub fn network_fs_policy(mut self, policy: NetworkFsPolicy) -> Self {
+        self.network_fs_policy = policy;
+        self
+    }
+
+    // Recompacts SST files older than `interval` even if nothing else would
+    // pick them, so files full of stale entries eventually get rewritten.
+    // Applies to every column family holding user data, namespaces included.
+    // Compaction priority can't be offered alongside it: the rocksdb crate
+    // has no setter for it and RocksDB rejects it in SetOptions, since it
+    // can't change on an open column family. Its default,
+    // kMinOverlappingRatio, already picks the files whose compaction is
+    // cheapest relative to what it reclaims.
+    pub fn periodic_compaction(mut self, interval: Duration) -> Self {
+        self.periodic_compaction = Some(interval);
+        self
+    }
+
+    // Namespaces to create if the store doesn't have them yet. Namespaces
+    // created earlier are always opened, whether listed here or not.
+    pub fn namespaces<I, S>(mut self, namespaces: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.namespaces = namespaces.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<RocksDBStore> {
+        netfs::check(path.as_ref(), self.network_fs_policy)?;
+        RocksDBStore::open(path, &self)
+    }
 }
 
 impl RocksDBStore {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::builder().open(path)
+    }
+
+    pub fn builder() -> RocksDBStoreBuilder {
+        RocksDBStoreBuilder::new()
+    }
+
+    // Options for column families holding u64 -> Value maps
+    fn user_cf_options(tuning: &CfTuning) -> Options {
         let mut opts = Options::default();
+        opts.set_merge_operator_associative(merge::TENSOR_ADD_MERGE, merge::tensor_add_merge);
+        if let Some(size) = tuning.write_buffer_size {
+            opts.set_write_buffer_size(size);
+        }
+        opts.set_compression_type(tuning.compression);
+        if let (DBCompressionType::Zstd, Some(level)) = (tuning.compression, tuning.zstd_level) {
+            // Window bits and strategy are zlib-only; -14 and 0 are RocksDB's defaults
+            opts.set_compression_options(-14, level, 0, 0);
+        }
+        if let Some(cache) = &tuning.block_cache {
+            let mut table_opts = BlockBasedOptions::default();
+            table_opts.set_block_cache(cache);
+            opts.set_block_based_table_factory(&table_opts);
+        }
+        opts
+    }
+
+    // User column family options the rocksdb crate has no setter for. They
+    // are mutable, so they're applied once the column family is open.
+    fn set_mutable_cf_options(db: &Db, cf: &Arc<BoundColumnFamily<'_>>, tuning: &CfTuning) -> Result<()> {
+        if let Some(interval) = tuning.periodic_compaction {
+            db.set_options_cf(cf, &[("periodic_compaction_seconds", interval.as_secs().to_string().as_str())])?;
+        }
+        Ok(())
+    }
+
+    fn open<P: AsRef<Path>>(path: P, config: &RocksDBStoreBuilder) -> Result<Self> {
+        let path = path.as_ref();
+        let cf_tuning = CfTuning {
+            write_buffer_size: config.write_buffer_size,
+            compression: config.compression,
+            zstd_level: config.zstd_level,
+            block_cache: config.block_cache_size.map(Cache::new_lru_cache),
+            periodic_compaction: config.periodic_compaction,
+        };
+        let mut opts = Self::user_cf_options(&cf_tuning);
         opts.create_if_missing(true);
-        opts.set_max_open_files(10000);
-        opts.set_use_fsync(true);
+        opts.set_max_open_files(config.max_open_files);
+        opts.set_use_fsync(config.use_fsync);
         opts.set_bytes_per_sync(1024 * 1024); // 1MB
+        opts.create_missing_column_families(true);
+        if config.statistics {
+            opts.enable_statistics();
+        }
         
-        let db = DB::open(&opts, path)?;
-        Ok(Self {
+        // Column family options given to open_cf only cover the named column
+        // families, so the default one (user data) needs its own descriptor
+        // for the merge operator to apply to it
+        let mut cfs = vec![
+            ColumnFamilyDescriptor::new(DEFAULT_COLUMN_FAMILY_NAME, opts.clone()),
+            ColumnFamilyDescriptor::new(META_CF, Options::default()),
+            ColumnFamilyDescriptor::new(TTL_CF, Options::default()),
+            ColumnFamilyDescriptor::new(STR_KEYS_CF, Self::user_cf_options(&cf_tuning)),
+        ];
+        // RocksDB refuses to open a database without all of its column
+        // families, so namespaces created in earlier runs must be listed too
+        let mut namespace_cfs: Vec<String> = config.namespaces.iter().map(|ns| Self::namespace_cf_name(ns)).collect::<Result<_>>()?;
+        if path.join("CURRENT").exists() {
+            namespace_cfs.extend(Db::list_cf(&opts, path)?.into_iter().filter(|cf| cf.starts_with(NAMESPACE_CF_PREFIX)));
+        }
+        namespace_cfs.sort();
+        namespace_cfs.dedup();
+        cfs.extend(namespace_cfs.iter().map(|cf| ColumnFamilyDescriptor::new(cf, Self::user_cf_options(&cf_tuning))));
+        let db = Db::open_cf_descriptors(&opts, path, cfs)?;
+        for cf in [DEFAULT_COLUMN_FAMILY_NAME, STR_KEYS_CF].into_iter().chain(namespace_cfs.iter().map(String::as_str)) {
+            let handle = db.cf_handle(cf).ok_or_else(|| anyhow::anyhow!("Missing column family '{}'", cf))?;
+            Self::set_mutable_cf_options(&db, &handle, &cf_tuning)?;
+        }
+        let store = Self {
             db: Arc::new(db),
-        })
+            entries: Arc::new(AtomicU64::new(0)),
+            key_locks: Arc::new((0..KEY_LOCK_STRIPES).map(|_| Mutex::new(())).collect()),
+            cf_tuning: Arc::new(cf_tuning),
+            statistics: config.statistics.then(|| Arc::new(Statistics(opts))),
+            value_zstd_level: config.value_zstd_level,
+            sync_writes: config.sync_writes,
+            changes: tokio::sync::broadcast::channel(WATCH_CHANNEL_CAPACITY).0,
+        };
+        match store.read_meta_key(SCHEMA_VERSION_KEY)? {
+            Some(bytes) => {
+                let version = u64::from_be_bytes(bytes.as_slice().try_into()?);
+                if version > SCHEMA_VERSION {
+                    anyhow::bail!("Store schema version {} is newer than supported version {}", version, SCHEMA_VERSION);
+                }
+            }
+            None => store.write_meta_key(SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_be_bytes())?,
+        }
+        // One-time scan; from here on the write paths keep the count current
+        let count = store.keys()?.len() as u64;
+        store.entries.store(count, Ordering::SeqCst);
+        Ok(store)
+    }
+
+    fn key_stripe(key: u64) -> usize {
+        (key % KEY_LOCK_STRIPES as u64) as usize
+    }
+
+    fn lock_key(&self, key: u64) -> MutexGuard<'_, ()> {
+        // A panic while holding the lock can't leave the guarded `()` inconsistent
+        self.key_locks[Self::key_stripe(key)].lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Locks the stripes of all `keys` in ascending order so that concurrent
+    // multi-key writers can't deadlock each other
+    fn lock_keys(&self, keys: impl Iterator<Item = u64>) -> Vec<MutexGuard<'_, ()>> {
+        let mut stripes: Vec<usize> = keys.map(Self::key_stripe).collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+            .into_iter()
+            .map(|stripe| self.key_locks[stripe].lock().unwrap_or_else(|e| e.into_inner()))
+            .collect()
+    }
+
+    fn meta_cf(&self) -> Result<Arc<BoundColumnFamily<'_>>> {
+        self.db.cf_handle(META_CF)
+            .ok_or_else(|| anyhow::anyhow!("Missing column family '{}'", META_CF))
+    }
+
+    fn ttl_cf(&self) -> Result<Arc<BoundColumnFamily<'_>>> {
+        self.db.cf_handle(TTL_CF)
+            .ok_or_else(|| anyhow::anyhow!("Missing column family '{}'", TTL_CF))
+    }
+
+    fn now_millis() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+    }
+
+    fn is_expired(&self, key: u64) -> Result<bool> {
+        let expiry = self.db.get_cf(&self.ttl_cf()?, key.to_be_bytes())?;
+        expiry_passed(expiry.as_deref(), Self::now_millis())
+    }
+
+    // Expired entries stay on disk until the next sweep, but reads treat them
+    // as absent
+    fn live(&self, key: u64, bytes: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+        match bytes {
+            Some(bytes) if !self.is_expired(key)? => Ok(Some(bytes)),
+            _ => Ok(None),
+        }
+    }
+
+    // Key filter for scans over the default store: skips expired entries the
+    // sweeper hasn't removed yet, and fails with StoreError::Timeout once
+    // `deadline` passes
+    fn live_until(&self, deadline: Option<Instant>) -> impl FnMut(u64) -> Result<bool> + '_ {
+        let mut visit = until(deadline);
+        move |key| Ok(visit(key)? && !self.is_expired(key)?)
+    }
+
+    fn encode_value(&self, value: &Value) -> Vec<u8> {
+        codec::encode_value_with(value, self.value_zstd_level)
+    }
+
+    // Tells watchers that `key` changed. Write paths call this after their
+    // write lands and before releasing the key's lock, so the events for a
+    // key come in the order its writes did.
+    fn publish(&self, key: u64, op: ChangeOp) {
+        self.publish_event(WatchEvent { key, op: op as i32, missed: 0, store_name: String::new() });
+    }
+
+    // Like `publish`, for a key in `namespace`
+    fn publish_cf(&self, namespace: &str, key: u64, op: ChangeOp) {
+        if self.changes.receiver_count() > 0 {
+            self.publish_event(WatchEvent { key, op: op as i32, missed: 0, store_name: namespace.to_string() });
+        }
+    }
+
+    fn publish_event(&self, event: WatchEvent) {
+        // Fails only when nobody is watching
+        let _ = self.changes.send(event);
+    }
+
+    // Options for a write that syncs the write-ahead log before returning
+    // if `sync`
+    fn write_options(sync: bool) -> WriteOptions {
+        let mut options = WriteOptions::default();
+        options.set_sync(sync);
+        options
+    }
+
+    // Writes `batch` atomically, syncing as the builder's `sync_writes` says
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        self.write_with(batch, self.sync_writes)
+    }
+
+    fn write_with(&self, batch: WriteBatch, sync: bool) -> Result<()> {
+        self.db.write_opt(batch, &Self::write_options(sync)).map_err(map_rocksdb_error)
+    }
+
+    fn str_keys_cf(&self) -> Result<Arc<BoundColumnFamily<'_>>> {
+        self.db.cf_handle(STR_KEYS_CF)
+            .ok_or_else(|| anyhow::anyhow!("Missing column family '{}'", STR_KEYS_CF))
+    }
+
+    // String keys share the u64 keys' lock stripes, picked by a hash
+    fn lock_str_key(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+        self.lock_key(crc32fast::hash(key) as u64)
+    }
+
+    fn namespace_cf_name(namespace: &str) -> Result<String> {
+        if namespace.is_empty() {
+            return Err(StoreError::InvalidArgument("namespace must not be empty".to_string()).into());
+        }
+        Ok(format!("{}{}", NAMESPACE_CF_PREFIX, namespace))
+    }
+
+    fn namespace_cf(&self, namespace: &str) -> Result<Arc<BoundColumnFamily<'_>>> {
+        self.db.cf_handle(&Self::namespace_cf_name(namespace)?)
+            .ok_or_else(|| StoreError::NotFound(format!("namespace '{}'", namespace)).into())
+    }
+
+    pub(crate) fn write_meta_key(&self, name: &str, value: &[u8]) -> Result<()> {
+        self.db.put_cf(&self.meta_cf()?, name.as_bytes(), value)?;
+        Ok(())
+    }
+
+    pub(crate) fn read_meta_key(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get_cf(&self.meta_cf()?, name.as_bytes())?)
+    }
+
+    // Checks the descriptor and metadata map against their size limits
+    fn validate_metadata(value: &Value) -> Result<()> {
+        if let Some(descriptor) = &value.descriptor {
+            if descriptor.is_empty() {
+                return Err(StoreError::InvalidArgument("descriptor must not be empty".to_string()).into());
+            }
+            if descriptor.len() > MAX_DESCRIPTOR_LEN {
+                return Err(StoreError::InvalidArgument(format!(
+                    "descriptor is {} bytes, the maximum is {}", descriptor.len(), MAX_DESCRIPTOR_LEN
+                )).into());
+            }
+        }
+        let metadata_len: usize = value.metadata.iter().map(|(name, tag)| name.len() + tag.len()).sum();
+        if metadata_len > MAX_METADATA_LEN {
+            return Err(StoreError::InvalidArgument(format!(
+                "metadata is {} bytes, the maximum is {}", metadata_len, MAX_METADATA_LEN
+            )).into());
+        }
+        Ok(())
     }
 
     pub fn put(&self, key: u64, value: Value) -> Result<Option<Value>> {
+        self.put_entry(key, value, None, self.sync_writes)
+    }
+
+    // Like `put`, but `sync` rather than the builder's `sync_writes` decides
+    // whether the write-ahead log is synced before returning. With `sync`
+    // the write survives a power loss once this returns; without it this
+    // is cheaper but only survives the process crashing.
+    pub fn put_sync(&self, key: u64, value: Value, sync: bool) -> Result<Option<Value>> {
+        self.put_entry(key, value, None, sync)
+    }
+
+    // Like `put`, but first checks the value's integrity fields with
+    // `validate_value`
+    pub fn put_validated(&self, key: u64, value: Value) -> Result<Option<Value>> {
+        Self::validate_value(key, &value)?;
+        self.put(key, value)
+    }
+
+    // Checks the integrity fields clients send along with a value: size_check
+    // must be the payload size implied by shape and dtype, and key_check must
+    // repeat the key the value is stored under
+    pub fn validate_value(key: u64, value: &Value) -> Result<()> {
+        let dtype = DataType::try_from(value.dtype)
+            .map_err(|_| StoreError::InvalidArgument(format!("unknown dtype {}", value.dtype)))?;
+        let expected_size = value.shape.iter()
+            .try_fold(1u64, |acc, &dim| acc.checked_mul(dim))
+            .and_then(|elements| dtype.payload_size(elements))
+            .ok_or_else(|| StoreError::InvalidArgument(format!("shape {:?} is too large", value.shape)))?;
+        if value.size_check != expected_size {
+            return Err(StoreError::InvalidArgument(format!(
+                "size_check is {} but shape {:?} of {:?} needs {} bytes", value.size_check, value.shape, dtype, expected_size
+            )).into());
+        }
+        if value.key_check != key {
+            return Err(StoreError::InvalidArgument(format!(
+                "key_check {} does not match key {}", value.key_check, key
+            )).into());
+        }
+        Ok(())
+    }
+
+    // Like `put`, but the key reads as absent once `ttl` has elapsed, to point
+    // reads and scans alike, and is deleted by the next sweep. Until then it
+    // still counts towards `len`. A later `put` of the same key without a TTL
+    // makes it permanent again.
+    pub fn put_with_ttl(&self, key: u64, value: Value, ttl: Duration) -> Result<Option<Value>> {
+        let expires_at = Self::now_millis().saturating_add(ttl.as_millis() as u64);
+        self.put_entry(key, value, Some(expires_at), self.sync_writes)
+    }
+
+    fn put_entry(&self, key: u64, value: Value, expires_at: Option<u64>, sync: bool) -> Result<Option<Value>> {
+        Self::validate_metadata(&value)?;
         let key_bytes = key.to_be_bytes();
-        let value_bytes = value.encode_to_vec();
+        let value_bytes = self.encode_value(&value);
+        let _guard = self.lock_key(key);
         
         // Check if key exists first
         let existing = self.db.get(key_bytes)?;
-        let old_value = if let Some(existing_bytes) = existing {
-            Some(Value::decode(existing_bytes.as_slice())?)
+        let existed = existing.is_some();
+        let old_value = if let Some(existing_bytes) = self.live(key, existing)? {
+            Some(codec::decode_value(existing_bytes.as_slice())?)
         } else {
             None
         };
         
-        // Insert new value
-        self.db.put(key_bytes, value_bytes)?;
+        // Insert new value, replacing or clearing any previous expiry
+        let mut batch = WriteBatch::default();
+        batch.put(key_bytes, value_bytes);
+        match expires_at {
+            Some(expires_at) => batch.put_cf(&self.ttl_cf()?, key_bytes, expires_at.to_be_bytes()),
+            None => batch.delete_cf(&self.ttl_cf()?, key_bytes),
+        }
+        self.write_with(batch, sync)?;
+        if !existed {
+            self.entries.fetch_add(1, Ordering::SeqCst);
+        }
+        self.publish(key, ChangeOp::Put);
         
         Ok(old_value)
     }
 
+    // Writes all items in a single atomic WriteBatch: either every item is
+    // stored or none is. Unlike `put` it doesn't decode or return the previous
+    // values; the only read is one multi_get to learn which keys are new so
+    // the entry counter stays exact.
+    pub fn put_batch(&self, items: Vec<(u64, Value)>) -> Result<()> {
+        self.write_batch(items.into_iter().map(|(key, value)| WriteOp::Put(key, value)).collect())
+    }
+
+    // Applies puts and deletes in order as one atomic WriteBatch. Every value
+    // is validated before anything is written, so an invalid operation
+    // anywhere in `ops` rejects the whole batch.
+    pub fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        let batch = self.ops_batch(&ops)?;
+        let _guards = self.lock_keys(ops.iter().map(WriteOp::key));
+        self.commit_ops(batch, &ops)
+    }
+
+    // The WriteBatch applying `ops`, clearing the expiry of every key touched
+    fn ops_batch(&self, ops: &[WriteOp]) -> Result<WriteBatch> {
+        let ttl_cf = self.ttl_cf()?;
+        let mut batch = WriteBatch::default();
+        for op in ops {
+            match op {
+                WriteOp::Put(key, value) => {
+                    Self::validate_metadata(value)?;
+                    batch.put(key.to_be_bytes(), self.encode_value(value));
+                }
+                WriteOp::Delete(key) => batch.delete(key.to_be_bytes()),
+            }
+            batch.delete_cf(&ttl_cf, op.key().to_be_bytes());
+        }
+        Ok(batch)
+    }
+
+    // Writes `batch`, built from `ops` by `ops_batch`, and updates the entry
+    // count. The caller must hold the locks of every key in `ops`.
+    fn commit_ops(&self, batch: WriteBatch, ops: &[WriteOp]) -> Result<()> {
+        // Replay the ops over the keys' current presence to learn how the
+        // entry count changes; a key may appear several times in one batch
+        let keys: Vec<u64> = ops.iter().map(WriteOp::key).collect();
+        let mut present = std::collections::HashMap::new();
+        for (key, result) in keys.iter().zip(self.db.multi_get(keys.iter().map(|key| key.to_be_bytes()))) {
+            present.insert(*key, result?.is_some());
+        }
+        let before = present.values().filter(|&&p| p).count() as u64;
+        let events = replay_ops(ops, &mut present);
+        let after = present.values().filter(|&&p| p).count() as u64;
+
+        self.write(batch)?;
+        if after >= before {
+            self.entries.fetch_add(after - before, Ordering::SeqCst);
+        } else {
+            self.entries.fetch_sub(before - after, Ordering::SeqCst);
+        }
+        for (key, op) in events {
+            self.publish(key, op);
+        }
+        Ok(())
+    }
+
+    // Returns the value stored under `key`, or stores and returns `f()` if
+    // there is none. `f` runs while the key lock is held, so racing callers
+    // see the first caller's value and only one of them calls `f`; it
+    // shouldn't touch the same key itself. An expired value counts as absent.
+    pub fn get_or_insert_with(&self, key: u64, f: impl FnOnce() -> Value) -> Result<Value> {
+        let key_bytes = key.to_be_bytes();
+        let _guard = self.lock_key(key);
+
+        let current = self.db.get(key_bytes)?;
+        let existed = current.is_some();
+        if let Some(bytes) = self.live(key, current)? {
+            return codec::decode_value(&bytes);
+        }
+
+        let value = f();
+        Self::validate_metadata(&value)?;
+        let mut batch = WriteBatch::default();
+        batch.put(key_bytes, self.encode_value(&value));
+        batch.delete_cf(&self.ttl_cf()?, key_bytes);
+        self.write(batch)?;
+        if !existed {
+            self.entries.fetch_add(1, Ordering::SeqCst);
+        }
+        self.publish(key, ChangeOp::Put);
+        Ok(value)
+    }
+
+    // Writes `new` only if the current value's encoding equals `expected`'s
+    // (None meaning the key must be absent) and returns whether it did. Every
+    // writer of this key holds the same key lock, so the read and the write
+    // can't interleave with another put, delete or compare_and_swap.
+    pub fn compare_and_swap(&self, key: u64, expected: Option<Value>, new: Value) -> Result<bool> {
+        Self::validate_metadata(&new)?;
+        let key_bytes = key.to_be_bytes();
+        let _guard = self.lock_key(key);
+
+        let current = self.db.get(key_bytes)?;
+        let existed = current.is_some();
+        // Compare protobuf payloads: the frame around them isn't part of the value
+        let current = match self.live(key, current)? {
+            Some(bytes) => Some(codec::value_payload(&bytes)?.into_owned()),
+            None => None,
+        };
+        if current != expected.map(|value| value.encode_to_vec()) {
+            return Ok(false);
+        }
+
+        // Like `put`, a successful swap clears any expiry
+        let mut batch = WriteBatch::default();
+        batch.put(key_bytes, self.encode_value(&new));
+        batch.delete_cf(&self.ttl_cf()?, key_bytes);
+        self.write(batch)?;
+        if !existed {
+            self.entries.fetch_add(1, Ordering::SeqCst);
+        }
+        self.publish(key, ChangeOp::Put);
+        Ok(true)
+    }
+
+    // Adds `delta` element-wise to the stored value through the RocksDB merge
+    // operator, or stores `delta` as is when the key is absent. Shapes and
+    // dtypes are checked against the current value under the key lock, so a
+    // mismatch is rejected here instead of failing the merge later. An
+    // existing expiry is kept.
+    pub fn merge_add(&self, key: u64, delta: Value) -> Result<()> {
+        Self::validate_metadata(&delta)?;
+        let key_bytes = key.to_be_bytes();
+        let _guard = self.lock_key(key);
+
+        let current = self.db.get(key_bytes)?;
+        let existed = current.is_some();
+        match self.live(key, current)? {
+            Some(bytes) => {
+                merge::check_addable(&codec::decode_value(bytes.as_slice())?, &delta)?;
+                self.db.merge_opt(key_bytes, self.encode_value(&delta), &Self::write_options(self.sync_writes))
+                    .map_err(map_rocksdb_error)?;
+            }
+            None => {
+                // Don't merge into an expired value that hasn't been swept yet
+                let mut batch = WriteBatch::default();
+                batch.put(key_bytes, self.encode_value(&delta));
+                batch.delete_cf(&self.ttl_cf()?, key_bytes);
+                self.write(batch)?;
+                if !existed {
+                    self.entries.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+        self.publish(key, ChangeOp::Put);
+        Ok(())
+    }
+
     pub fn get(&self, key: &u64) -> Result<Option<Value>> {
         let key_bytes = key.to_be_bytes();
-        let value_bytes = self.db.get(key_bytes)?;
+        let value_bytes = self.live(*key, self.db.get(key_bytes)?)?;
         
         if let Some(bytes) = value_bytes {
-            let value = Value::decode(bytes.as_slice())?;
+            let value = codec::decode_value(bytes.as_slice())?;
             Ok(Some(value))
         } else {
             Ok(None)
         }
     }
 
+    // Like `get`, but the value comes back with empty `data`: for looking at
+    // a tensor's shape, descriptor or metadata without copying its bytes
+    pub fn get_without_data(&self, key: &u64) -> Result<Option<Value>> {
+        match self.live(*key, self.db.get(key.to_be_bytes())?)? {
+            Some(bytes) => Ok(Some(codec::decode_value_without_data(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // The header of the value under `key`: shape, dtype and the rest but
+    // the data. RocksDB still reads the whole stored value, but its data is
+    // skipped over rather than decoded.
+    pub fn get_meta(&self, key: &u64) -> Result<Option<ValueHeader>> {
+        match self.live(*key, self.db.get(key.to_be_bytes())?)? {
+            Some(bytes) => Ok(Some(codec::decode_value_header(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Just the metadata map of the value under `key`
+    pub fn get_metadata(&self, key: &u64) -> Result<Option<BTreeMap<String, String>>> {
+        Ok(self.get_without_data(key)?.map(|value| value.metadata))
+    }
+
+    // Fetches many keys in one RocksDB call. The result has the same length
+    // and order as `keys`, with None for keys that aren't present.
+    pub fn multi_get(&self, keys: &[u64]) -> Result<Vec<Option<Value>>> {
+        let ttl_cf = self.ttl_cf()?;
+        let now = Self::now_millis();
+        let values = self.db.multi_get(keys.iter().map(|key| key.to_be_bytes()));
+        let expiries = self.db.multi_get_cf(keys.iter().map(|key| (&ttl_cf, key.to_be_bytes())));
+        values
+            .into_iter()
+            .zip(expiries)
+            .map(|(value, expiry)| {
+                let expired = expiry_passed(expiry?.as_deref(), now)?;
+                match value? {
+                    Some(bytes) if !expired => Ok(Some(codec::decode_value(bytes.as_slice())?)),
+                    _ => Ok(None),
+                }
+            })
+            .collect()
+    }
+
     pub fn delete(&self, key: &u64) -> Result<Option<Value>> {
+        self.delete_with(key, codec::decode_value)
+    }
+
+    // Like `delete`, but only says whether the key had a value, which saves
+    // decoding it
+    pub fn remove(&self, key: &u64) -> Result<bool> {
+        Ok(self.delete_with(key, |_| Ok(()))?.is_some())
+    }
+
+    // Deletes `key` and returns `read` of its value, if it had a live one
+    fn delete_with<T>(&self, key: &u64, read: impl FnOnce(&[u8]) -> Result<T>) -> Result<Option<T>> {
         let key_bytes = key.to_be_bytes();
+        let _guard = self.lock_key(*key);
         
         // Get the value before deleting
         let value_bytes = self.db.get(key_bytes)?;
-        let value = if let Some(bytes) = value_bytes {
-            Some(Value::decode(bytes.as_slice())?)
+        let existed = value_bytes.is_some();
+        let value = if let Some(bytes) = self.live(*key, value_bytes)? {
+            Some(read(bytes.as_slice())?)
         } else {
             None
         };
         
-        // Delete the key
-        self.db.delete(key_bytes)?;
+        // Delete the key along with its expiry, if any
+        let mut batch = WriteBatch::default();
+        batch.delete(key_bytes);
+        batch.delete_cf(&self.ttl_cf()?, key_bytes);
+        self.write(batch)?;
+        if existed {
+            self.entries.fetch_sub(1, Ordering::SeqCst);
+            self.publish(*key, ChangeOp::Delete);
+        }
         
         Ok(value)
     }
 
+    // Deletes all of `keys` in one WriteBatch, so either every delete lands
+    // or none does, and returns how many of the distinct keys had a live
+    // value. Keeping the entry count exact needs each key's presence anyway,
+    // so this costs one multi_get over the keys and their expiries, under
+    // the keys' locks, but no value is decoded.
+    pub fn delete_batch(&self, keys: &[u64]) -> Result<u64> {
+        let ttl_cf = self.ttl_cf()?;
+        let mut unique = keys.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+
+        let mut batch = WriteBatch::default();
+        for key in &unique {
+            batch.delete(key.to_be_bytes());
+            batch.delete_cf(&ttl_cf, key.to_be_bytes());
+        }
+
+        let _guards = self.lock_keys(unique.iter().copied());
+        let now = Self::now_millis();
+        let values = self.db.multi_get(unique.iter().map(|key| key.to_be_bytes()));
+        let expiries = self.db.multi_get_cf(unique.iter().map(|key| (&ttl_cf, key.to_be_bytes())));
+        let mut present = Vec::new();
+        let mut live = 0u64;
+        for ((key, value), expiry) in unique.iter().zip(values).zip(expiries) {
+            if value?.is_some() {
+                present.push(*key);
+                if !expiry_passed(expiry?.as_deref(), now)? {
+                    live += 1;
+                }
+            }
+        }
+
+        self.write(batch)?;
+        self.entries.fetch_sub(present.len() as u64, Ordering::SeqCst);
+        for key in present {
+            self.publish(key, ChangeOp::Delete);
+        }
+        Ok(live)
+    }
+
+    // Pins the value in the block cache instead of copying it out, so the
+    // check costs the same however large the value is
     pub fn contains_key(&self, key: &u64) -> Result<bool> {
         let key_bytes = key.to_be_bytes();
-        Ok(self.db.get(key_bytes)?.is_some())
+        Ok(self.db.get_pinned(key_bytes)?.is_some() && !self.is_expired(*key)?)
+    }
+
+    // Presence of each of `keys`, in order, without decoding any values
+    pub fn contains_keys(&self, keys: &[u64]) -> Result<Vec<bool>> {
+        let ttl_cf = self.ttl_cf()?;
+        let now = Self::now_millis();
+        let values = self.db.multi_get(keys.iter().map(|key| key.to_be_bytes()));
+        let expiries = self.db.multi_get_cf(keys.iter().map(|key| (&ttl_cf, key.to_be_bytes())));
+        values
+            .into_iter()
+            .zip(expiries)
+            .map(|(value, expiry)| Ok(value?.is_some() && !expiry_passed(expiry?.as_deref(), now)?))
+            .collect()
     }
 
     pub fn len(&self) -> Result<usize> {
-        let mut count = 0;
-        let iter = self.db.iterator(rocksdb::IteratorMode::Start);
-        for _ in iter {
-            count += 1;
-        }
-        Ok(count)
+        Ok(self.entries.load(Ordering::SeqCst) as usize)
     }
 
     pub fn is_empty(&self) -> Result<bool> {
@@ -95,165 +990,3436 @@ impl RocksDBStore {
     }
 
     pub fn keys(&self) -> Result<Vec<u64>> {
+        self.keys_with_deadline(None)
+    }
+
+    // Like `keys`, but aborts with StoreError::Timeout once `deadline` passes
+    pub fn keys_with_deadline(&self, deadline: Option<Instant>) -> Result<Vec<u64>> {
         let mut keys = Vec::new();
         let iter = self.db.iterator(rocksdb::IteratorMode::Start);
         
-        for result in iter {
+        for (visited, result) in iter.enumerate() {
+            check_deadline(deadline, visited as u64)?;
             let (key_bytes, _) = result?;
             if key_bytes.len() == 8 { // u64 is 8 bytes
                 let key = u64::from_be_bytes(key_bytes.as_ref().try_into()?);
-                keys.push(key);
+                if !self.is_expired(key)? {
+                    keys.push(key);
+                }
             }
         }
         
         Ok(keys)
     }
 
-    pub fn clear(&self) -> Result<()> {
-        let iter = self.db.iterator(rocksdb::IteratorMode::Start);
-        let mut batch = WriteBatch::default();
-        
-        for result in iter {
-            let (key_bytes, _) = result?;
-            batch.delete(key_bytes);
-        }
-        
-        self.db.write(batch)?;
-        Ok(())
+    // Up to `limit` keys in ascending order, starting after `start_after` or
+    // at the smallest key. Keys are stored big-endian, so RocksDB's byte order
+    // is numeric order and each page starts with a seek. Also returns the
+    // cursor to pass as `start_after` for the next page, None on the last.
+    pub fn keys_page(&self, start_after: Option<u64>, limit: usize) -> Result<(Vec<u64>, Option<u64>)> {
+        self.keys_page_with_deadline(start_after, limit, None)
     }
 
-    pub fn compact(&self) -> Result<()> {
+    // Like `keys_page`, but aborts with StoreError::Timeout once `deadline` passes
+    pub fn keys_page_with_deadline(&self, start_after: Option<u64>, limit: usize, deadline: Option<Instant>) -> Result<(Vec<u64>, Option<u64>)> {
+        let Some(start) = page_start(start_after, limit)? else {
+            return Ok((Vec::new(), None));
+        };
+        let start_bytes = start.to_be_bytes();
+        let mut iter = self.db.iterator(rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward));
+        next_page(&mut iter, limit, self.live_until(deadline))
+    }
+
+    // Returns every entry with a key in the half-open range [start, end), in
+    // ascending key order. Keys are stored big-endian, so byte order matches
+    // numeric order and the scan can seek straight to `start`.
+    pub fn range(&self, start: u64, end: u64) -> Result<Vec<(u64, Value)>> {
+        self.range_with_deadline(start, end, None)
+    }
+
+    // Like `range`, but aborts with StoreError::Timeout once `deadline` passes
+    pub fn range_with_deadline(&self, start: u64, end: u64, deadline: Option<Instant>) -> Result<Vec<(u64, Value)>> {
+        let mut entries = Vec::new();
+        if start >= end {
+            return Ok(entries);
+        }
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_iterate_upper_bound(end.to_be_bytes());
+        let start_bytes = start.to_be_bytes();
+        let mut iter = self.db.iterator_opt(
+            rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward),
+            read_opts,
+        );
+
+        let mut live = self.live_until(deadline);
+        while let Some((key, value_bytes)) = next_entry_where(&mut iter, &mut live)? {
+            entries.push((key, codec::decode_value(value_bytes.as_ref())?));
+        }
+
+        Ok(entries)
+    }
+
+    // Returns every entry whose key shares its top `prefix_bits` bits with
+    // `prefix`, in ascending key order; the low bits of `prefix` are ignored.
+    // Matching keys form one contiguous run of the big-endian keyspace, so the
+    // scan seeks to the first one and stops at the upper bound. 0 bits
+    // matches every key and 64 bits at most the one key equal to `prefix`.
+    pub fn scan_prefix(&self, prefix: u64, prefix_bits: u32) -> Result<Vec<(u64, Value)>> {
+        self.scan_prefix_with_deadline(prefix, prefix_bits, None)
+    }
+
+    // Like `scan_prefix`, but aborts with StoreError::Timeout once `deadline` passes
+    pub fn scan_prefix_with_deadline(&self, prefix: u64, prefix_bits: u32, deadline: Option<Instant>) -> Result<Vec<(u64, Value)>> {
+        if prefix_bits > 64 {
+            return Err(StoreError::InvalidArgument(format!("prefix_bits is {}, the maximum is 64", prefix_bits)).into());
+        }
+        let suffix_mask = u64::MAX.checked_shr(prefix_bits).unwrap_or(0);
+        let first = prefix & !suffix_mask;
+        let last = prefix | suffix_mask;
+
+        let mut read_opts = ReadOptions::default();
+        // Past the last key of the keyspace there's nothing to bound
+        if let Some(end) = last.checked_add(1) {
+            read_opts.set_iterate_upper_bound(end.to_be_bytes());
+        }
+        let first_bytes = first.to_be_bytes();
+        let mut iter = self.db.iterator_opt(
+            rocksdb::IteratorMode::From(&first_bytes, rocksdb::Direction::Forward),
+            read_opts,
+        );
+
+        let mut entries = Vec::new();
+        let mut live = self.live_until(deadline);
+        while let Some((key, value_bytes)) = next_entry_where(&mut iter, &mut live)? {
+            entries.push((key, codec::decode_value(value_bytes.as_ref())?));
+        }
+        Ok(entries)
+    }
+
+    // Lazily decodes entries in key order starting at `start`. The RocksDB
+    // iterator lives as long as the returned iterator, so dropping it early
+    // stops the scan without visiting the rest of the store.
+    pub fn iter_from(&self, start: u64) -> impl Iterator<Item = Result<(u64, Value)>> + '_ {
+        let start_bytes = start.to_be_bytes();
+        self.db
+            .iterator(rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward))
+            .filter_map(|result| match result {
+                Ok((key_bytes, value_bytes)) if key_bytes.len() == 8 => (|| {
+                    let key = u64::from_be_bytes(key_bytes.as_ref().try_into()?);
+                    if self.is_expired(key)? {
+                        return Ok(None);
+                    }
+                    Ok(Some((key, codec::decode_value(value_bytes.as_ref())?)))
+                })().transpose(),
+                Ok(_) => None,
+                Err(e) => Some(Err(e.into())),
+            })
+    }
+
+    // `iter_from` within a namespace. Fails up front if the namespace
+    // doesn't exist.
+    pub fn iter_from_cf(&self, namespace: &str, start: u64) -> Result<impl Iterator<Item = Result<(u64, Value)>> + '_> {
+        let cf = self.namespace_cf(namespace)?;
+        let start_bytes = start.to_be_bytes();
+        Ok(self.db
+            .iterator_cf(&cf, rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward))
+            .filter_map(|result| match result {
+                Ok((key_bytes, value_bytes)) if key_bytes.len() == 8 => Some((|| {
+                    let key = u64::from_be_bytes(key_bytes.as_ref().try_into()?);
+                    Ok((key, codec::decode_value(value_bytes.as_ref())?))
+                })()),
+                Ok(_) => None,
+                Err(e) => Some(Err(e.into())),
+            }))
+    }
+
+    // Lazily decodes every entry in key order; see `iter_from`
+    pub fn iter(&self) -> impl Iterator<Item = Result<(u64, Value)>> + '_ {
+        self.iter_from(0)
+    }
+
+    // Lazily yields every key in order without decoding any values.
+    // Like `keys`, anything in the user keyspace that isn't 8 bytes long is
+    // skipped.
+    pub fn keys_iter(&self) -> impl Iterator<Item = Result<u64>> + '_ {
+        self.db
+            .iterator(rocksdb::IteratorMode::Start)
+            .filter_map(|result| match result {
+                Ok((key_bytes, _)) => {
+                    let key = u64::from_be_bytes(key_bytes.as_ref().try_into().ok()?);
+                    match self.is_expired(key) {
+                        Ok(true) => None,
+                        Ok(false) => Some(Ok(key)),
+                        Err(e) => Some(Err(e)),
+                    }
+                }
+                Err(e) => Some(Err(e.into())),
+            })
+    }
+
+    // Subscribes to changes: a put (including batched puts, compare_and_swap,
+    // merge_add and imports) or a delete (including delete_range, clear and
+    // TTL sweeps) of a key that was present. Events carry the namespace of
+    // their key, or an empty `store_name` for the default store. They are
+    // best-effort. A watcher that falls WATCH_CHANNEL_CAPACITY events behind
+    // loses the oldest, and `recv` reports how many with RecvError::Lagged.
+    // String keys aren't watched.
+    pub fn watch(&self) -> tokio::sync::broadcast::Receiver<WatchEvent> {
+        self.changes.subscribe()
+    }
+
+    // Takes a point-in-time view of the store. Reads through it see exactly
+    // the data committed before this call, however the store changes after.
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot {
+            store: self,
+            snapshot: self.db.snapshot(),
+        }
+    }
+
+    // Deletes every key in the half-open range [start, end), and their
+    // expiries, in one write. The range can hold keys of any stripe, so this
+    // takes every key lock for as long as it counts the keys it removes.
+    pub fn delete_range(&self, start: u64, end: u64) -> Result<()> {
+        if start >= end {
+            return Ok(());
+        }
+        let _guards = self.lock_keys(0..KEY_LOCK_STRIPES as u64);
+        let (start_bytes, end_bytes) = (start.to_be_bytes(), end.to_be_bytes());
+
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_iterate_upper_bound(end_bytes);
+        let mut iter = self.db.iterator_opt(
+            rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward),
+            read_opts,
+        );
+        let mut removed = Vec::new();
+        while let Some((key, _)) = next_user_entry(&mut iter)? {
+            removed.push(key);
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.delete_range(start_bytes, end_bytes);
+        batch.delete_range_cf(&self.ttl_cf()?, start_bytes, end_bytes);
+        self.write(batch)?;
+        self.entries.fetch_sub(removed.len() as u64, Ordering::SeqCst);
+        for key in removed {
+            self.publish(key, ChangeOp::Delete);
+        }
+        Ok(())
+    }
+
+    // Deletes every key, string keys included. The deletes are committed
+    // CLEAR_BATCH_SIZE at a time so memory use stays flat however big the
+    // store is; the flip side is that a crash part way through leaves the
+    // store partly cleared.
+    pub fn clear(&self) -> Result<()> {
+        self.clear_in_batches(CLEAR_BATCH_SIZE).map(|_| ())
+    }
+
+    // Returns the number of batches written
+    fn clear_in_batches(&self, batch_size: usize) -> Result<usize> {
+        let _guards = self.lock_keys(0..KEY_LOCK_STRIPES as u64);
+        let default_cf = self.db.cf_handle(DEFAULT_COLUMN_FAMILY_NAME)
+            .ok_or_else(|| anyhow::anyhow!("Missing column family '{}'", DEFAULT_COLUMN_FAMILY_NAME))?;
+        let mut batches = 0;
+        // Expiries go first, so a partial clear can't leave expiries behind
+        // for keys that are gone, only keys that no longer expire
+        for (cf, counted) in [(self.ttl_cf()?, false), (self.str_keys_cf()?, false), (default_cf, true)] {
+            let mut batch = WriteBatch::default();
+            let mut removed = Vec::new();
+            let commit = |batch: WriteBatch, removed: Vec<u64>| -> Result<()> {
+                self.write(batch)?;
+                self.entries.fetch_sub(removed.len() as u64, Ordering::SeqCst);
+                for key in removed {
+                    self.publish(key, ChangeOp::Delete);
+                }
+                Ok(())
+            };
+            for result in self.db.iterator_cf(&cf, rocksdb::IteratorMode::Start) {
+                let (key_bytes, _) = result?;
+                if counted {
+                    if let Ok(key) = <[u8; 8]>::try_from(key_bytes.as_ref()) {
+                        removed.push(u64::from_be_bytes(key));
+                    }
+                }
+                batch.delete_cf(&cf, key_bytes);
+                if batch.len() >= batch_size {
+                    commit(std::mem::take(&mut batch), std::mem::take(&mut removed))?;
+                    batches += 1;
+                }
+            }
+            if !batch.is_empty() {
+                commit(batch, removed)?;
+                batches += 1;
+            }
+        }
+        Ok(batches)
+    }
+
+    // Creates an empty namespace: an independent u64 -> Value map sharing the
+    // store's database. Creating a namespace that already exists is a no-op.
+    pub fn create_namespace(&self, namespace: &str) -> Result<()> {
+        let cf_name = Self::namespace_cf_name(namespace)?;
+        if self.db.cf_handle(&cf_name).is_none() {
+            self.db.create_cf(&cf_name, &Self::user_cf_options(&self.cf_tuning))?;
+            let cf = self.namespace_cf(namespace)?;
+            Self::set_mutable_cf_options(&self.db, &cf, &self.cf_tuning)?;
+        }
+        Ok(())
+    }
+
+    pub fn namespaces(&self) -> Result<Vec<String>> {
+        let mut namespaces: Vec<String> = Db::list_cf(&Options::default(), self.db.path())?
+            .into_iter()
+            .filter_map(|cf| cf.strip_prefix(NAMESPACE_CF_PREFIX).map(str::to_string))
+            .collect();
+        namespaces.sort();
+        Ok(namespaces)
+    }
+
+    // Namespaced counterparts of put/get/delete/keys. They fail with
+    // StoreError::NotFound if the namespace hasn't been created. TTLs, merges
+    // and the O(1) `len` only apply to the default keyspace.
+    pub fn put_cf(&self, namespace: &str, key: u64, value: Value) -> Result<Option<Value>> {
+        self.put_cf_entry(namespace, key, value, self.sync_writes)
+    }
+
+    pub fn put_sync_cf(&self, namespace: &str, key: u64, value: Value, sync: bool) -> Result<Option<Value>> {
+        self.put_cf_entry(namespace, key, value, sync)
+    }
+
+    fn put_cf_entry(&self, namespace: &str, key: u64, value: Value, sync: bool) -> Result<Option<Value>> {
+        Self::validate_metadata(&value)?;
+        let cf = self.namespace_cf(namespace)?;
+        let key_bytes = key.to_be_bytes();
+        let _guard = self.lock_key(key);
+
+        let old_value = match self.db.get_cf(&cf, key_bytes)? {
+            Some(bytes) => Some(codec::decode_value(bytes.as_slice())?),
+            None => None,
+        };
+        self.db.put_cf_opt(&cf, key_bytes, self.encode_value(&value), &Self::write_options(sync)).map_err(map_rocksdb_error)?;
+        self.publish_cf(namespace, key, ChangeOp::Put);
+        Ok(old_value)
+    }
+
+    // `compare_and_swap` within a namespace, under the same key lock
+    pub fn compare_and_swap_cf(&self, namespace: &str, key: u64, expected: Option<Value>, new: Value) -> Result<bool> {
+        Self::validate_metadata(&new)?;
+        let cf = self.namespace_cf(namespace)?;
+        let key_bytes = key.to_be_bytes();
+        let _guard = self.lock_key(key);
+
+        let current = match self.db.get_cf(&cf, key_bytes)? {
+            Some(bytes) => Some(codec::value_payload(&bytes)?.into_owned()),
+            None => None,
+        };
+        if current != expected.map(|value| value.encode_to_vec()) {
+            return Ok(false);
+        }
+        self.db.put_cf_opt(&cf, key_bytes, self.encode_value(&new), &Self::write_options(self.sync_writes))
+            .map_err(map_rocksdb_error)?;
+        self.publish_cf(namespace, key, ChangeOp::Put);
+        Ok(true)
+    }
+
+    pub fn get_cf(&self, namespace: &str, key: &u64) -> Result<Option<Value>> {
+        let cf = self.namespace_cf(namespace)?;
+        match self.db.get_cf(&cf, key.to_be_bytes())? {
+            Some(bytes) => Ok(Some(codec::decode_value(bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains_key_cf(&self, namespace: &str, key: &u64) -> Result<bool> {
+        let cf = self.namespace_cf(namespace)?;
+        Ok(self.db.get_pinned_cf(&cf, key.to_be_bytes())?.is_some())
+    }
+
+    pub fn contains_keys_cf(&self, namespace: &str, keys: &[u64]) -> Result<Vec<bool>> {
+        let cf = self.namespace_cf(namespace)?;
+        self.db
+            .multi_get_cf(keys.iter().map(|key| (&cf, key.to_be_bytes())))
+            .into_iter()
+            .map(|value| Ok(value?.is_some()))
+            .collect()
+    }
+
+    pub fn get_without_data_cf(&self, namespace: &str, key: &u64) -> Result<Option<Value>> {
+        let cf = self.namespace_cf(namespace)?;
+        match self.db.get_cf(&cf, key.to_be_bytes())? {
+            Some(bytes) => Ok(Some(codec::decode_value_without_data(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_meta_cf(&self, namespace: &str, key: &u64) -> Result<Option<ValueHeader>> {
+        let cf = self.namespace_cf(namespace)?;
+        match self.db.get_cf(&cf, key.to_be_bytes())? {
+            Some(bytes) => Ok(Some(codec::decode_value_header(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn multi_get_cf(&self, namespace: &str, keys: &[u64]) -> Result<Vec<Option<Value>>> {
+        let cf = self.namespace_cf(namespace)?;
+        self.db
+            .multi_get_cf(keys.iter().map(|key| (&cf, key.to_be_bytes())))
+            .into_iter()
+            .map(|value| match value? {
+                Some(bytes) => Ok(Some(codec::decode_value(bytes.as_slice())?)),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    // Like `delete_range`, it finds the keys it removes under every key
+    // lock, so watchers hear about each of them
+    pub fn delete_range_cf(&self, namespace: &str, start: u64, end: u64) -> Result<()> {
+        let cf = self.namespace_cf(namespace)?;
+        if start >= end {
+            return Ok(());
+        }
+        let _guards = self.lock_keys(0..KEY_LOCK_STRIPES as u64);
+        let (start_bytes, end_bytes) = (start.to_be_bytes(), end.to_be_bytes());
+
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_iterate_upper_bound(end_bytes);
+        let mut iter = self.db.iterator_cf_opt(
+            &cf,
+            read_opts,
+            rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward),
+        );
+        let mut removed = Vec::new();
+        while let Some((key, _)) = next_user_entry(&mut iter)? {
+            removed.push(key);
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(&cf, start_bytes, end_bytes);
+        self.write(batch)?;
+        for key in removed {
+            self.publish_cf(namespace, key, ChangeOp::Delete);
+        }
+        Ok(())
+    }
+
+    pub fn delete_cf(&self, namespace: &str, key: &u64) -> Result<Option<Value>> {
+        self.delete_cf_with(namespace, key, codec::decode_value)
+    }
+
+    pub fn remove_cf(&self, namespace: &str, key: &u64) -> Result<bool> {
+        Ok(self.delete_cf_with(namespace, key, |_| Ok(()))?.is_some())
+    }
+
+    fn delete_cf_with<T>(&self, namespace: &str, key: &u64, read: impl FnOnce(&[u8]) -> Result<T>) -> Result<Option<T>> {
+        let cf = self.namespace_cf(namespace)?;
+        let key_bytes = key.to_be_bytes();
+        let _guard = self.lock_key(*key);
+
+        let value = match self.db.get_cf(&cf, key_bytes)? {
+            Some(bytes) => Some(read(bytes.as_slice())?),
+            None => None,
+        };
+        self.db.delete_cf_opt(&cf, key_bytes, &Self::write_options(self.sync_writes)).map_err(map_rocksdb_error)?;
+        if value.is_some() {
+            self.publish_cf(namespace, *key, ChangeOp::Delete);
+        }
+        Ok(value)
+    }
+
+    // `delete_batch` within a namespace: one WriteBatch, and the count of
+    // distinct keys that were present
+    pub fn delete_batch_cf(&self, namespace: &str, keys: &[u64]) -> Result<u64> {
+        let cf = self.namespace_cf(namespace)?;
+        let mut unique = keys.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+
+        let mut batch = WriteBatch::default();
+        for key in &unique {
+            batch.delete_cf(&cf, key.to_be_bytes());
+        }
+
+        let _guards = self.lock_keys(unique.iter().copied());
+        let mut present = Vec::new();
+        for (key, value) in unique.iter().zip(self.db.multi_get_cf(unique.iter().map(|key| (&cf, key.to_be_bytes())))) {
+            if value?.is_some() {
+                present.push(*key);
+            }
+        }
+        self.write(batch)?;
+        for key in &present {
+            self.publish_cf(namespace, *key, ChangeOp::Delete);
+        }
+        Ok(present.len() as u64)
+    }
+
+    // Unlike `len` this walks the namespace, but it never holds its keys
+    pub fn len_cf(&self, namespace: &str) -> Result<usize> {
+        let cf = self.namespace_cf(namespace)?;
+        let mut count = 0;
+        let mut iter = self.db.iterator_cf(&cf, rocksdb::IteratorMode::Start);
+        while next_user_entry(&mut iter)?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn write_batch_cf(&self, namespace: &str, ops: Vec<WriteOp>) -> Result<()> {
+        let cf = self.namespace_cf(namespace)?;
+        let mut batch = WriteBatch::default();
+        for op in &ops {
+            match op {
+                WriteOp::Put(key, value) => {
+                    Self::validate_metadata(value)?;
+                    batch.put_cf(&cf, key.to_be_bytes(), self.encode_value(value));
+                }
+                WriteOp::Delete(key) => batch.delete_cf(&cf, key.to_be_bytes()),
+            }
+        }
+        let _guards = self.lock_keys(ops.iter().map(WriteOp::key));
+        let keys: Vec<u64> = ops.iter().map(WriteOp::key).collect();
+        let mut present = std::collections::HashMap::new();
+        for (key, result) in keys.iter().zip(self.db.multi_get_cf(keys.iter().map(|key| (&cf, key.to_be_bytes())))) {
+            present.insert(*key, result?.is_some());
+        }
+        let events = replay_ops(&ops, &mut present);
+        self.write(batch)?;
+        for (key, op) in events {
+            self.publish_cf(namespace, key, op);
+        }
+        Ok(())
+    }
+
+    pub fn keys_page_cf(&self, namespace: &str, start_after: Option<u64>, limit: usize) -> Result<(Vec<u64>, Option<u64>)> {
+        self.keys_page_cf_with_deadline(namespace, start_after, limit, None)
+    }
+
+    pub fn keys_page_cf_with_deadline(
+        &self,
+        namespace: &str,
+        start_after: Option<u64>,
+        limit: usize,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<u64>, Option<u64>)> {
+        let cf = self.namespace_cf(namespace)?;
+        let Some(start) = page_start(start_after, limit)? else {
+            return Ok((Vec::new(), None));
+        };
+        let start_bytes = start.to_be_bytes();
+        let mut iter = self.db.iterator_cf(&cf, rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward));
+        next_page(&mut iter, limit, until(deadline))
+    }
+
+    pub fn keys_cf(&self, namespace: &str) -> Result<Vec<u64>> {
+        self.keys_cf_with_deadline(namespace, None)
+    }
+
+    pub fn keys_cf_with_deadline(&self, namespace: &str, deadline: Option<Instant>) -> Result<Vec<u64>> {
+        let cf = self.namespace_cf(namespace)?;
+        let mut keys = Vec::new();
+        let mut iter = self.db.iterator_cf(&cf, rocksdb::IteratorMode::Start);
+        let mut visit = until(deadline);
+        while let Some((key, _)) = next_entry_where(&mut iter, &mut visit)? {
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    // Stores `value` under a string key, in STR_KEYS_CF; pass a &str or any
+    // byte slice. Returns the previous value, if any.
+    pub fn put_str<K: AsRef<[u8]>>(&self, key: K, value: Value) -> Result<Option<Value>> {
+        Self::validate_metadata(&value)?;
+        let key = key.as_ref();
+        let cf = self.str_keys_cf()?;
+        let _guard = self.lock_str_key(key);
+
+        let old_value = match self.db.get_cf(&cf, key)? {
+            Some(bytes) => Some(codec::decode_value(bytes.as_slice())?),
+            None => None,
+        };
+        self.db.put_cf_opt(&cf, key, self.encode_value(&value), &Self::write_options(self.sync_writes)).map_err(map_rocksdb_error)?;
+        Ok(old_value)
+    }
+
+    pub fn get_str<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Value>> {
+        match self.db.get_cf(&self.str_keys_cf()?, key.as_ref())? {
+            Some(bytes) => Ok(Some(codec::decode_value(bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn delete_str<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Value>> {
+        let key = key.as_ref();
+        let cf = self.str_keys_cf()?;
+        let _guard = self.lock_str_key(key);
+
+        let value = match self.db.get_cf(&cf, key)? {
+            Some(bytes) => Some(codec::decode_value(bytes.as_slice())?),
+            None => None,
+        };
+        self.db.delete_cf_opt(&cf, key, &Self::write_options(self.sync_writes)).map_err(map_rocksdb_error)?;
+        Ok(value)
+    }
+
+    // All string keys in bytewise order. They come back as bytes since a key
+    // need not be UTF-8; `String::from_utf8` recovers keys stored as &str.
+    pub fn keys_str(&self) -> Result<Vec<Vec<u8>>> {
+        let cf = self.str_keys_cf()?;
+        self.db.iterator_cf(&cf, rocksdb::IteratorMode::Start)
+            .map(|result| Ok(result?.0.into_vec()))
+            .collect()
+    }
+
+    // Fsyncs the write-ahead log. Every write so far then survives a crash
+    // or power loss, but only by replaying the log on the next open; the
+    // data itself stays in the memtables. Cheap compared to `flush`.
+    pub fn sync_wal(&self) -> Result<()> {
+        self.db.flush_wal(true)?;
+        Ok(())
+    }
+
+    // Syncs the write-ahead log and writes every memtable, of the default
+    // store, internal column families and namespaces alike, out to SST files.
+    // Afterwards nothing written so far depends on replaying the log, which
+    // shortens the next open and is what checkpoints and backups want.
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush_wal(true)?;
+        for cf_name in Db::list_cf(&Options::default(), self.db.path())? {
+            if let Some(cf) = self.db.cf_handle(&cf_name) {
+                self.db.flush_cf(&cf)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Backs up the store into `backup_dir` while it keeps serving reads and
+    // writes. Memtables are flushed first so the backup holds every write
+    // made before the call. SST files already present in `backup_dir` from
+    // an earlier backup are shared rather than copied again, so repeated
+    // backups to the same directory only copy what changed.
+    pub fn create_backup(&self, backup_dir: &Path) -> Result<()> {
+        let mut engine = BackupEngine::open(&BackupEngineOptions::new(backup_dir)?, &Env::new()?)?;
+        engine.create_new_backup_flush(&self.db, true)?;
+        Ok(())
+    }
+
+    // Writes a point-in-time copy of the store, every column family included,
+    // to `dir`, which must not exist yet. SST files are hard-linked when
+    // `dir` is on the same filesystem, so this is fast and takes little space
+    // until the original compacts them away. Memtables are flushed first, so
+    // the copy holds every write acknowledged before the call and none made
+    // after it; writes racing with the call are either wholly in it or not.
+    // The copy opens as an independent store.
+    pub fn checkpoint(&self, dir: &Path) -> Result<()> {
+        self.flush()?;
+        Checkpoint::new(&self.db)?.create_checkpoint(dir)?;
+        Ok(())
+    }
+
+    // Writes every live entry of the default store to `w` as a single-file
+    // dump (see dump.rs for the format) and returns how many were written.
+    // Memtables are flushed first, as for `checkpoint`, and the entries are
+    // then read from one snapshot: the dump holds every write acknowledged
+    // before the call and none made after it. Namespaces, string keys and
+    // TTLs aren't exported; keys whose TTL has passed are left out.
+    pub fn export(&self, w: impl Write) -> Result<u64> {
+        self.flush()?;
+        let ttl_cf = self.ttl_cf()?;
+        let now = Self::now_millis();
+        let snapshot = self.db.snapshot();
+        let mut iter = snapshot.iterator(rocksdb::IteratorMode::Start);
+        let mut writer = dump::DumpWriter::new(BufWriter::new(w))?;
+        while let Some((key, value_bytes)) = next_user_entry(&mut iter)? {
+            if expiry_passed(snapshot.get_cf(&ttl_cf, key.to_be_bytes())?.as_deref(), now)? {
+                continue;
+            }
+            writer.write_entry(key, &codec::value_payload(&value_bytes)?)?;
+        }
+        writer.finish()
+    }
+
+    // Reads a dump written by `export` and stores its entries, resolving
+    // keys that already hold a live value by `policy`. Returns how many
+    // entries were written, so skipped ones aren't counted.
+    //
+    // With Overwrite and SkipExisting entries are written IMPORT_BATCH_SIZE
+    // at a time. Each batch is atomic but the import as a whole isn't: if the
+    // dump turns out to be truncated or corrupt, the batches before the bad
+    // record stay written. With Error the whole dump is read into memory
+    // first and written in one batch, so a collision or a bad record leaves
+    // the store untouched.
+    pub fn import(&self, r: impl Read, policy: ImportPolicy) -> Result<u64> {
+        let mut reader = dump::DumpReader::new(BufReader::new(r))?;
+        let batch_size = match policy {
+            ImportPolicy::Error => usize::MAX,
+            ImportPolicy::Overwrite | ImportPolicy::SkipExisting => IMPORT_BATCH_SIZE,
+        };
+        let mut imported = 0;
+        let mut batch = Vec::new();
+        while let Some(entry) = reader.next_entry()? {
+            batch.push(entry);
+            if batch.len() == batch_size {
+                imported += self.import_batch(std::mem::take(&mut batch), policy)?;
+            }
+        }
+        imported += self.import_batch(batch, policy)?;
+        Ok(imported)
+    }
+
+    // Checks which keys are taken and writes the entries `policy` lets
+    // through, holding the keys' locks throughout so no writer can slip a
+    // value in between
+    fn import_batch(&self, entries: Vec<(u64, Value)>, policy: ImportPolicy) -> Result<u64> {
+        let keys: Vec<u64> = entries.iter().map(|(key, _)| *key).collect();
+        let _guards = self.lock_keys(keys.iter().copied());
+        let taken = match policy {
+            ImportPolicy::Overwrite => vec![false; keys.len()],
+            ImportPolicy::SkipExisting | ImportPolicy::Error => self.contains_keys(&keys)?,
+        };
+        if policy == ImportPolicy::Error {
+            if let Some(i) = taken.iter().position(|&taken| taken) {
+                return Err(StoreError::AlreadyExists(format!("key {}", keys[i])).into());
+            }
+        }
+        let ops: Vec<WriteOp> = entries
+            .into_iter()
+            .zip(taken)
+            .filter(|(_, taken)| !taken)
+            .map(|((key, value), _)| WriteOp::Put(key, value))
+            .collect();
+        self.commit_ops(self.ops_batch(&ops)?, &ops)?;
+        Ok(ops.len() as u64)
+    }
+
+    // Rebuilds a store in `db_dir` from the latest backup in `backup_dir`.
+    // Whatever `db_dir` held before is replaced, so no store may have it open.
+    pub fn restore_from_backup(backup_dir: &Path, db_dir: &Path) -> Result<()> {
+        let mut engine = BackupEngine::open(&BackupEngineOptions::new(backup_dir)?, &Env::new()?)?;
+        engine.restore_from_latest_backup(db_dir, db_dir, &RestoreOptions::default())?;
+        Ok(())
+    }
+
+    // Deletes every key whose TTL has passed and returns how many were
+    // removed. Until a key is swept it is still counted by `len`, even though
+    // point reads, `keys` and scans already treat it as absent.
+    pub fn sweep_expired(&self) -> Result<usize> {
+        let ttl_cf = self.ttl_cf()?;
+        let now = Self::now_millis();
+        let mut expired = Vec::new();
+        for result in self.db.iterator_cf(&ttl_cf, rocksdb::IteratorMode::Start) {
+            let (key_bytes, expiry_bytes) = result?;
+            if u64::from_be_bytes(expiry_bytes.as_ref().try_into()?) <= now {
+                expired.push(u64::from_be_bytes(key_bytes.as_ref().try_into()?));
+            }
+        }
+
+        let mut removed = 0;
+        for key in expired {
+            let key_bytes = key.to_be_bytes();
+            let _guard = self.lock_key(key);
+            // The key may have been rewritten since the scan above
+            if !self.is_expired(key)? {
+                continue;
+            }
+            let existed = self.db.get_pinned(key_bytes)?.is_some();
+            let mut batch = WriteBatch::default();
+            batch.delete(key_bytes);
+            batch.delete_cf(&ttl_cf, key_bytes);
+            self.write(batch)?;
+            if existed {
+                self.entries.fetch_sub(1, Ordering::SeqCst);
+                self.publish(key, ChangeOp::Delete);
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    pub fn compact(&self) -> Result<()> {
         self.db.compact_range(None::<&[u8]>, None::<&[u8]>);
         Ok(())
     }
 
-    pub fn get_db_size(&self) -> Result<u64> {
-        let mut size = 0;
-        let iter = self.db.iterator(rocksdb::IteratorMode::Start);
-        
-        for result in iter {
-            let (key_bytes, value_bytes) = result?;
-            size += key_bytes.len() as u64 + value_bytes.len() as u64;
-        }
-        
-        Ok(size)
+    // Storage used by the default store, read from RocksDB's counters in
+    // constant time:
+    //   rocksdb.total-sst-files-size  every SST file, including ones that are
+    //                                 obsolete but not yet deleted
+    //   rocksdb.cur-size-all-mem-tables  memtables not yet flushed, whose
+    //                                 data sits in the write-ahead log
+    // Compression and stale versions awaiting compaction make this differ
+    // from the bytes of the live entries; see `logical_size` for those.
+    pub fn get_db_size(&self) -> Result<u64> {
+        let cf = self.db.cf_handle(DEFAULT_COLUMN_FAMILY_NAME)
+            .ok_or_else(|| anyhow::anyhow!("Missing column family '{}'", DEFAULT_COLUMN_FAMILY_NAME))?;
+        self.storage_size(&cf)
+    }
+
+    pub fn get_db_size_cf(&self, namespace: &str) -> Result<u64> {
+        self.storage_size(&self.namespace_cf(namespace)?)
+    }
+
+    // Estimated size of the live data in the default store's SST files
+    // (rocksdb.estimate-live-data-size): roughly what a full compaction
+    // would shrink the files to. Memtables aren't included.
+    pub fn estimate_live_data_size(&self) -> Result<u64> {
+        Ok(self.db.property_int_value(rocksdb::properties::ESTIMATE_LIVE_DATA_SIZE)?.unwrap_or(0))
+    }
+
+    // Size of the default store's SST files (rocksdb.total-sst-files-size),
+    // i.e. `get_db_size` without the memtables
+    pub fn sst_files_size(&self) -> Result<u64> {
+        Ok(self.db.property_int_value(rocksdb::properties::TOTAL_SST_FILES_SIZE)?.unwrap_or(0))
+    }
+
+    // Compactions running right now, across every column family
+    // (rocksdb.num-running-compactions)
+    pub fn running_compactions(&self) -> Result<u64> {
+        Ok(self.db.property_int_value(rocksdb::properties::NUM_RUNNING_COMPACTIONS)?.unwrap_or(0))
+    }
+
+    // Whether RocksDB has decided the default store needs compacting but
+    // hasn't got to it yet (rocksdb.compaction-pending)
+    pub fn compaction_pending(&self) -> Result<bool> {
+        Ok(self.db.property_int_value(rocksdb::properties::COMPACTION_PENDING)?.unwrap_or(0) != 0)
+    }
+
+    // Directory the store was opened in
+    pub fn path(&self) -> &Path {
+        self.db.path()
+    }
+
+    // Whether a write would go through right now. Fails if RocksDB has
+    // stopped writes (too many memtables or L0 files) or if a probe write to
+    // the meta column family doesn't succeed without waiting, e.g. because a
+    // background error left the database read-only.
+    pub fn accepts_writes(&self) -> Result<bool> {
+        if self.db.property_int_value(rocksdb::properties::IS_WRITE_STOPPED)?.unwrap_or(0) != 0 {
+            return Ok(false);
+        }
+        let mut options = WriteOptions::default();
+        options.set_no_slowdown(true);
+        Ok(self.db.put_cf_opt(&self.meta_cf()?, WRITE_PROBE_KEY.as_bytes(), [], &options).is_ok())
+    }
+
+    // RocksDB's statistics as formatted by RocksDB: one line per ticker
+    // ("rocksdb.block.cache.hit COUNT : 12") or histogram. Fails if the
+    // store was opened with statistics off.
+    pub fn statistics(&self) -> Result<String> {
+        self.statistics
+            .as_ref()
+            .and_then(|statistics| statistics.0.get_statistics())
+            .ok_or_else(|| StoreError::InvalidArgument("statistics are disabled for this store".to_string()).into())
+    }
+
+    // Whether the store collects statistics, i.e. whether the getters below
+    // can succeed
+    pub fn statistics_enabled(&self) -> bool {
+        self.statistics.is_some()
+    }
+
+    // Value of the ticker `name` in `statistics()`
+    fn ticker(&self, name: &str) -> Result<u64> {
+        let statistics = self.statistics()?;
+        statistics
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.trim_start().strip_prefix("COUNT :"))
+            .ok_or_else(|| anyhow::anyhow!("Statistics have no ticker '{}'", name))?
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid value for ticker '{}': {}", name, e))
+    }
+
+    // Share of block cache lookups that hit, or None before the first lookup
+    pub fn block_cache_hit_rate(&self) -> Result<Option<f64>> {
+        let hits = self.ticker("rocksdb.block.cache.hit")?;
+        let misses = self.ticker("rocksdb.block.cache.miss")?;
+        Ok((hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64))
+    }
+
+    // Bytes read and written by compactions since the store was opened
+    pub fn compaction_bytes(&self) -> Result<(u64, u64)> {
+        Ok((self.ticker("rocksdb.compact.read.bytes")?, self.ticker("rocksdb.compact.write.bytes")?))
+    }
+
+    fn storage_size(&self, cf: &Arc<BoundColumnFamily<'_>>) -> Result<u64> {
+        let sst = self.db.property_int_value_cf(cf, rocksdb::properties::TOTAL_SST_FILES_SIZE)?;
+        let memtables = self.db.property_int_value_cf(cf, rocksdb::properties::CUR_SIZE_ALL_MEM_TABLES)?;
+        Ok(sst.unwrap_or(0) + memtables.unwrap_or(0))
+    }
+
+    // Uncompressed bytes of every key and value in the default store. Scans
+    // the whole store, so it's O(n).
+    pub fn logical_size(&self) -> Result<u64> {
+        let mut size = 0;
+        for result in self.db.iterator(rocksdb::IteratorMode::Start) {
+            let (key_bytes, value_bytes) = result?;
+            size += key_bytes.len() as u64 + value_bytes.len() as u64;
+        }
+        Ok(size)
+    }
+
+    // Checks the CRC of every stored value and returns the keys whose value
+    // is corrupt, in ascending order. Reads everything, so it's meant for
+    // offline checks rather than the request path.
+    pub fn verify_integrity(&self) -> Result<Vec<u64>> {
+        let mut corrupt = Vec::new();
+        let mut iter = self.db.iterator(rocksdb::IteratorMode::Start);
+        while let Some((key, value_bytes)) = next_user_entry(&mut iter)? {
+            if codec::decode_value(&value_bytes).is_err() {
+                corrupt.push(key);
+            }
+        }
+        Ok(corrupt)
+    }
+
+    // Compares the user entries of two stores, each read from a snapshot so
+    // concurrent writes don't skew the report. Keys are big-endian encoded, so
+    // both iterators come back in ascending u64 order and can be merge-joined.
+    pub fn diff(&self, other: &RocksDBStore) -> Result<DiffReport> {
+        let self_snapshot = self.db.snapshot();
+        let other_snapshot = other.db.snapshot();
+        let mut self_iter = self_snapshot.iterator(rocksdb::IteratorMode::Start);
+        let mut other_iter = other_snapshot.iterator(rocksdb::IteratorMode::Start);
+
+        let mut report = DiffReport::default();
+        let mut self_next = next_user_entry(&mut self_iter)?;
+        let mut other_next = next_user_entry(&mut other_iter)?;
+
+        loop {
+            match (&self_next, &other_next) {
+                (None, None) => break,
+                (Some((key, _)), None) => {
+                    DiffReport::record(&mut report.only_in_self, &mut report.sample_only_in_self, *key);
+                    self_next = next_user_entry(&mut self_iter)?;
+                }
+                (None, Some((key, _))) => {
+                    DiffReport::record(&mut report.only_in_other, &mut report.sample_only_in_other, *key);
+                    other_next = next_user_entry(&mut other_iter)?;
+                }
+                (Some((self_key, self_value)), Some((other_key, other_value))) => {
+                    if self_key < other_key {
+                        DiffReport::record(&mut report.only_in_self, &mut report.sample_only_in_self, *self_key);
+                        self_next = next_user_entry(&mut self_iter)?;
+                    } else if other_key < self_key {
+                        DiffReport::record(&mut report.only_in_other, &mut report.sample_only_in_other, *other_key);
+                        other_next = next_user_entry(&mut other_iter)?;
+                    } else {
+                        if codec::value_payload(self_value)? == codec::value_payload(other_value)? {
+                            report.identical += 1;
+                        } else {
+                            DiffReport::record(&mut report.different, &mut report.sample_different, *self_key);
+                        }
+                        self_next = next_user_entry(&mut self_iter)?;
+                        other_next = next_user_entry(&mut other_iter)?;
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Aggregates the values whose keys fall in the half-open range [start, end).
+    // `TotalBytes` sums the tensor payload sizes and `Sum` adds up every element
+    // of INT8/16/32/64, FP32 and FP64 values (little-endian), failing on
+    // FP1/2/4/8, BF16, FP16, INT1/2/4 and BOOL values.
+    pub fn aggregate_range(&self, start: u64, end: u64, kind: AggKind) -> Result<AggResult> {
+        self.aggregate(start, Some(end), kind, None)
+    }
+
+    // Like `aggregate_range`, but aborts with StoreError::Timeout once `deadline` passes
+    pub fn aggregate_range_with_deadline(&self, start: u64, end: u64, kind: AggKind, deadline: Option<Instant>) -> Result<AggResult> {
+        self.aggregate(start, Some(end), kind, deadline)
+    }
+
+    pub(crate) fn aggregate(&self, start: u64, end: Option<u64>, kind: AggKind, deadline: Option<Instant>) -> Result<AggResult> {
+        let start_bytes = start.to_be_bytes();
+        let iter = self.db.iterator_opt(
+            rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward),
+            Self::aggregate_read_options(end),
+        );
+        aggregate_entries(iter, kind, deadline, |key| Ok(!self.is_expired(key)?))
+    }
+
+    // `aggregate` over a namespace
+    pub(crate) fn aggregate_cf(&self, namespace: &str, start: u64, end: Option<u64>, kind: AggKind, deadline: Option<Instant>) -> Result<AggResult> {
+        let cf = self.namespace_cf(namespace)?;
+        let start_bytes = start.to_be_bytes();
+        let iter = self.db.iterator_cf_opt(
+            &cf,
+            Self::aggregate_read_options(end),
+            rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward),
+        );
+        aggregate_entries(iter, kind, deadline, |_| Ok(true))
+    }
+
+    fn aggregate_read_options(end: Option<u64>) -> ReadOptions {
+        let mut read_opts = ReadOptions::default();
+        // Analytics scans shouldn't evict the hot working set from the block cache
+        read_opts.fill_cache(false);
+        if let Some(end) = end {
+            read_opts.set_iterate_upper_bound(end.to_be_bytes());
+        }
+        read_opts
+    }
+}
+
+// Folds the entries of a raw iterator whose key `keep` accepts into `kind`'s
+// result, failing with StoreError::Timeout once `deadline` passes
+fn aggregate_entries<I, F>(iter: I, kind: AggKind, deadline: Option<Instant>, mut keep: F) -> Result<AggResult>
+where
+    I: Iterator<Item = std::result::Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>>,
+    F: FnMut(u64) -> Result<bool>,
+{
+    let mut count = 0u64;
+    let mut total_bytes = 0u64;
+    let mut sum = 0f64;
+    for (visited, result) in iter.enumerate() {
+        check_deadline(deadline, visited as u64)?;
+        let (key_bytes, value_bytes) = result?;
+        if key_bytes.len() != 8 {
+            continue;
+        }
+        let key = u64::from_be_bytes(key_bytes.as_ref().try_into()?);
+        if !keep(key)? {
+            continue;
+        }
+        count += 1;
+        match kind {
+            AggKind::Count => {}
+            AggKind::TotalBytes => {
+                let value = codec::decode_value(value_bytes.as_ref())?;
+                total_bytes += value.data.iter().map(|d| d.len() as u64).sum::<u64>();
+            }
+            AggKind::Sum => {
+                let value = codec::decode_value(value_bytes.as_ref())?;
+                let dtype = DataType::try_from(value.dtype)
+                    .map_err(|_| anyhow::anyhow!("Cannot sum key {}: unknown dtype {}", key, value.dtype))?;
+                let elements = dtype.decode_f64(&value.data.concat())
+                    .map_err(|e| anyhow::anyhow!("Cannot sum key {}: {}", key, e))?;
+                sum += elements.iter().sum::<f64>();
+            }
+        }
+    }
+
+    Ok(match kind {
+        AggKind::Count => AggResult::Count(count),
+        AggKind::TotalBytes => AggResult::TotalBytes(total_bytes),
+        AggKind::Sum => AggResult::Sum(sum),
+    })
+}
+
+// Applies `ops` in order to `present`, each key's presence before the batch,
+// and returns the changes they make. Deletes of keys that are already gone
+// aren't changes.
+fn replay_ops(ops: &[WriteOp], present: &mut std::collections::HashMap<u64, bool>) -> Vec<(u64, ChangeOp)> {
+    let mut events = Vec::with_capacity(ops.len());
+    for op in ops {
+        let was_present = present.insert(op.key(), matches!(op, WriteOp::Put(..))) == Some(true);
+        match op {
+            WriteOp::Put(key, _) => events.push((*key, ChangeOp::Put)),
+            WriteOp::Delete(key) if was_present => events.push((*key, ChangeOp::Delete)),
+            WriteOp::Delete(_) => {}
+        }
+    }
+    events
+}
+
+// Advances a raw iterator to the next u64 user key, skipping anything else
+fn next_user_entry<I>(iter: &mut I) -> Result<Option<(u64, Box<[u8]>)>>
+where
+    I: Iterator<Item = std::result::Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>>,
+{
+    next_entry_where(iter, |_| Ok(true))
+}
+
+// Like `next_user_entry`, skipping entries whose key `keep` rejects
+fn next_entry_where<I, F>(iter: &mut I, mut keep: F) -> Result<Option<(u64, Box<[u8]>)>>
+where
+    I: Iterator<Item = std::result::Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>>,
+    F: FnMut(u64) -> Result<bool>,
+{
+    for result in iter.by_ref() {
+        let (key_bytes, value_bytes) = result?;
+        if key_bytes.len() == 8 {
+            let key = u64::from_be_bytes(key_bytes.as_ref().try_into()?);
+            if keep(key)? {
+                return Ok(Some((key, value_bytes)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// Key filter that keeps every key but fails with StoreError::Timeout once
+// `deadline` passes
+fn until(deadline: Option<Instant>) -> impl FnMut(u64) -> Result<bool> {
+    let mut visited = 0u64;
+    move |_| {
+        check_deadline(deadline, visited)?;
+        visited += 1;
+        Ok(true)
+    }
+}
+
+// First key of the page after `start_after`, or None when it was the last
+// possible key
+fn page_start(start_after: Option<u64>, limit: usize) -> Result<Option<u64>> {
+    if limit == 0 {
+        return Err(StoreError::InvalidArgument("page limit must be positive".to_string()).into());
+    }
+    Ok(match start_after {
+        Some(key) => key.checked_add(1),
+        None => Some(0),
+    })
+}
+
+// Reads up to `limit` keys, plus the cursor for the next page if any key
+// follows them
+fn next_page<I, F>(iter: &mut I, limit: usize, mut keep: F) -> Result<(Vec<u64>, Option<u64>)>
+where
+    I: Iterator<Item = std::result::Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>>,
+    F: FnMut(u64) -> Result<bool>,
+{
+    let mut keys = Vec::new();
+    while keys.len() < limit {
+        match next_entry_where(iter, &mut keep)? {
+            Some((key, _)) => keys.push(key),
+            None => return Ok((keys, None)),
+        }
+    }
+    let cursor = match next_entry_where(iter, &mut keep)? {
+        Some(_) => keys.last().copied(),
+        None => None,
+    };
+    Ok((keys, cursor))
+}
+
+impl WriteOp {
+    pub fn key(&self) -> u64 {
+        match self {
+            WriteOp::Put(key, _) | WriteOp::Delete(key) => *key,
+        }
+    }
+}
+
+fn expiry_passed(expiry: Option<&[u8]>, now: u64) -> Result<bool> {
+    match expiry {
+        Some(bytes) => Ok(u64::from_be_bytes(bytes.try_into()?) <= now),
+        None => Ok(false),
+    }
+}
+
+// Read-only view of a RocksDBStore as of the moment `snapshot` was called.
+// Values and expiry times are both read at the snapshot, but whether a TTL
+// has passed is judged against the current time, as for the store itself.
+pub struct Snapshot<'a> {
+    store: &'a RocksDBStore,
+    snapshot: rocksdb::SnapshotWithThreadMode<'a, Db>,
+}
+
+impl Snapshot<'_> {
+    pub fn get(&self, key: &u64) -> Result<Option<Value>> {
+        Ok(self.multi_get(std::slice::from_ref(key))?.pop().flatten())
+    }
+
+    pub fn multi_get(&self, keys: &[u64]) -> Result<Vec<Option<Value>>> {
+        let ttl_cf = self.store.ttl_cf()?;
+        let now = RocksDBStore::now_millis();
+        let values = self.snapshot.multi_get(keys.iter().map(|key| key.to_be_bytes()));
+        let expiries = self.snapshot.multi_get_cf(keys.iter().map(|key| (&ttl_cf, key.to_be_bytes())));
+        values
+            .into_iter()
+            .zip(expiries)
+            .map(|(value, expiry)| match value? {
+                Some(bytes) if !expiry_passed(expiry?.as_deref(), now)? => Ok(Some(codec::decode_value(bytes.as_slice())?)),
+                _ => Ok(None),
+            })
+            .collect()
+    }
+
+    // Same half-open [start, end) scan as RocksDBStore::range
+    pub fn range(&self, start: u64, end: u64) -> Result<Vec<(u64, Value)>> {
+        let mut entries = Vec::new();
+        if start >= end {
+            return Ok(entries);
+        }
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_iterate_upper_bound(end.to_be_bytes());
+        let start_bytes = start.to_be_bytes();
+        let mut iter = self.snapshot.iterator_opt(
+            rocksdb::IteratorMode::From(&start_bytes, rocksdb::Direction::Forward),
+            read_opts,
+        );
+
+        let ttl_cf = self.store.ttl_cf()?;
+        let now = RocksDBStore::now_millis();
+        let live = |key: u64| Ok(!expiry_passed(self.snapshot.get_cf(&ttl_cf, key.to_be_bytes())?.as_deref(), now)?);
+        while let Some((key, value_bytes)) = next_entry_where(&mut iter, live)? {
+            entries.push((key, codec::decode_value(value_bytes.as_ref())?));
+        }
+
+        Ok(entries)
+    }
+}
+
+impl Drop for RocksDBStore {
+    fn drop(&mut self) {
+        // RocksDB will be automatically closed when the Arc is dropped
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KVStore {
+    store: Arc<RocksDBStore>,
+    _sweeper: Arc<TtlSweeper>,
+}
+
+// Background thread that periodically calls `sweep_expired`. It only holds a
+// weak reference to the store, and dropping the last KVStore stops and joins
+// it, so the database is closed once the KVStore is gone.
+#[derive(Debug)]
+struct TtlSweeper {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TtlSweeper {
+    fn spawn(store: Weak<RocksDBStore>) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = std::thread::Builder::new()
+            .name("kvstore-ttl-sweeper".to_string())
+            .spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(TTL_SWEEP_INTERVAL) {
+                    let Some(store) = store.upgrade() else { break };
+                    if let Err(e) = store.sweep_expired() {
+                        tracing::warn!("TTL sweep failed: {}", e);
+                    }
+                }
+            })
+            .expect("Failed to spawn TTL sweeper thread");
+        Self { stop: Some(stop), handle: Some(handle) }
+    }
+}
+
+impl Drop for TtlSweeper {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up immediately
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl KVStore {
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let store = RocksDBStore::new(path)?;
+        Ok(store.into())
+    }
+
+    // Like `new`, also creating any of `namespaces` the store doesn't have yet
+    pub fn with_namespaces<P: AsRef<std::path::Path>>(path: P, namespaces: &[&str]) -> Result<Self> {
+        let store = RocksDBStore::builder().namespaces(namespaces.iter().copied()).open(path)?;
+        Ok(store.into())
+    }
+
+    pub fn put(&self, key: u64, value: Value) -> Result<Option<Value>> {
+        self.store.put(key, value)
+    }
+
+    pub fn put_sync(&self, key: u64, value: Value, sync: bool) -> Result<Option<Value>> {
+        self.store.put_sync(key, value, sync)
+    }
+
+    pub fn put_validated(&self, key: u64, value: Value) -> Result<Option<Value>> {
+        self.store.put_validated(key, value)
+    }
+
+    pub fn put_batch(&self, items: Vec<(u64, Value)>) -> Result<()> {
+        self.store.put_batch(items)
+    }
+
+    pub fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        self.store.write_batch(ops)
+    }
+
+    pub fn put_with_ttl(&self, key: u64, value: Value, ttl: Duration) -> Result<Option<Value>> {
+        self.store.put_with_ttl(key, value, ttl)
+    }
+
+    pub fn get_or_insert_with(&self, key: u64, f: impl FnOnce() -> Value) -> Result<Value> {
+        self.store.get_or_insert_with(key, f)
+    }
+
+    pub fn compare_and_swap(&self, key: u64, expected: Option<Value>, new: Value) -> Result<bool> {
+        self.store.compare_and_swap(key, expected, new)
+    }
+
+    pub fn merge_add(&self, key: u64, delta: Value) -> Result<()> {
+        self.store.merge_add(key, delta)
+    }
+
+    pub fn get(&self, key: &u64) -> Result<Option<Value>> {
+        self.store.get(key)
+    }
+
+    pub fn multi_get(&self, keys: &[u64]) -> Result<Vec<Option<Value>>> {
+        self.store.multi_get(keys)
+    }
+
+    pub fn delete(&self, key: &u64) -> Result<Option<Value>> {
+        self.store.delete(key)
+    }
+
+    pub fn remove(&self, key: &u64) -> Result<bool> {
+        self.store.remove(key)
+    }
+
+    pub fn get_without_data(&self, key: &u64) -> Result<Option<Value>> {
+        self.store.get_without_data(key)
+    }
+
+    pub fn get_metadata(&self, key: &u64) -> Result<Option<BTreeMap<String, String>>> {
+        self.store.get_metadata(key)
+    }
+
+    // Shape, dtype, checks, descriptor and metadata of the value under
+    // `key`, without its data
+    pub fn get_meta(&self, key: &u64) -> Result<Option<ValueHeader>> {
+        self.store.get_meta(key)
+    }
+
+    pub fn delete_batch(&self, keys: &[u64]) -> Result<u64> {
+        self.store.delete_batch(keys)
+    }
+
+    pub fn contains_key(&self, key: &u64) -> Result<bool> {
+        self.store.contains_key(key)
+    }
+
+    pub fn contains_keys(&self, keys: &[u64]) -> Result<Vec<bool>> {
+        self.store.contains_keys(keys)
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        self.store.is_empty()
+    }
+
+    pub fn keys(&self) -> Result<Vec<u64>> {
+        self.store.keys()
+    }
+
+    pub fn keys_with_deadline(&self, deadline: Option<Instant>) -> Result<Vec<u64>> {
+        self.store.keys_with_deadline(deadline)
+    }
+
+    pub fn range(&self, start: u64, end: u64) -> Result<Vec<(u64, Value)>> {
+        self.store.range(start, end)
+    }
+
+    pub fn range_with_deadline(&self, start: u64, end: u64, deadline: Option<Instant>) -> Result<Vec<(u64, Value)>> {
+        self.store.range_with_deadline(start, end, deadline)
+    }
+
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        self.store.snapshot()
+    }
+
+    pub fn watch(&self) -> tokio::sync::broadcast::Receiver<WatchEvent> {
+        self.store.watch()
+    }
+
+    pub fn iter_from(&self, start: u64) -> impl Iterator<Item = Result<(u64, Value)>> + '_ {
+        self.store.iter_from(start)
+    }
+
+    pub fn iter_from_cf(&self, namespace: &str, start: u64) -> Result<impl Iterator<Item = Result<(u64, Value)>> + '_> {
+        self.store.iter_from_cf(namespace, start)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<(u64, Value)>> + '_ {
+        self.store.iter()
+    }
+
+    pub fn scan_prefix(&self, prefix: u64, prefix_bits: u32) -> Result<Vec<(u64, Value)>> {
+        self.store.scan_prefix(prefix, prefix_bits)
+    }
+
+    pub fn scan_prefix_with_deadline(&self, prefix: u64, prefix_bits: u32, deadline: Option<Instant>) -> Result<Vec<(u64, Value)>> {
+        self.store.scan_prefix_with_deadline(prefix, prefix_bits, deadline)
+    }
+
+    pub fn keys_iter(&self) -> impl Iterator<Item = Result<u64>> + '_ {
+        self.store.keys_iter()
+    }
+
+    pub fn create_backup(&self, backup_dir: &Path) -> Result<()> {
+        self.store.create_backup(backup_dir)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.store.flush()
+    }
+
+    pub fn sync_wal(&self) -> Result<()> {
+        self.store.sync_wal()
+    }
+
+    pub fn checkpoint(&self, dir: &Path) -> Result<()> {
+        self.store.checkpoint(dir)
+    }
+
+    pub fn export(&self, w: impl Write) -> Result<u64> {
+        self.store.export(w)
+    }
+
+    pub fn import(&self, r: impl Read, policy: ImportPolicy) -> Result<u64> {
+        self.store.import(r, policy)
+    }
+
+    pub fn create_namespace(&self, namespace: &str) -> Result<()> {
+        self.store.create_namespace(namespace)
+    }
+
+    pub fn namespaces(&self) -> Result<Vec<String>> {
+        self.store.namespaces()
+    }
+
+    pub fn put_cf(&self, namespace: &str, key: u64, value: Value) -> Result<Option<Value>> {
+        self.store.put_cf(namespace, key, value)
+    }
+
+    pub fn put_sync_cf(&self, namespace: &str, key: u64, value: Value, sync: bool) -> Result<Option<Value>> {
+        self.store.put_sync_cf(namespace, key, value, sync)
+    }
+
+    pub fn get_cf(&self, namespace: &str, key: &u64) -> Result<Option<Value>> {
+        self.store.get_cf(namespace, key)
+    }
+
+    pub fn compare_and_swap_cf(&self, namespace: &str, key: u64, expected: Option<Value>, new: Value) -> Result<bool> {
+        self.store.compare_and_swap_cf(namespace, key, expected, new)
+    }
+
+    pub fn get_without_data_cf(&self, namespace: &str, key: &u64) -> Result<Option<Value>> {
+        self.store.get_without_data_cf(namespace, key)
+    }
+
+    pub fn contains_key_cf(&self, namespace: &str, key: &u64) -> Result<bool> {
+        self.store.contains_key_cf(namespace, key)
+    }
+
+    pub fn contains_keys_cf(&self, namespace: &str, keys: &[u64]) -> Result<Vec<bool>> {
+        self.store.contains_keys_cf(namespace, keys)
+    }
+
+    pub fn get_meta_cf(&self, namespace: &str, key: &u64) -> Result<Option<ValueHeader>> {
+        self.store.get_meta_cf(namespace, key)
+    }
+
+    pub fn multi_get_cf(&self, namespace: &str, keys: &[u64]) -> Result<Vec<Option<Value>>> {
+        self.store.multi_get_cf(namespace, keys)
+    }
+
+    pub fn delete_cf(&self, namespace: &str, key: &u64) -> Result<Option<Value>> {
+        self.store.delete_cf(namespace, key)
+    }
+
+    pub fn remove_cf(&self, namespace: &str, key: &u64) -> Result<bool> {
+        self.store.remove_cf(namespace, key)
+    }
+
+    pub fn delete_batch_cf(&self, namespace: &str, keys: &[u64]) -> Result<u64> {
+        self.store.delete_batch_cf(namespace, keys)
+    }
+
+    pub fn delete_range(&self, start: u64, end: u64) -> Result<()> {
+        self.store.delete_range(start, end)
+    }
+
+    pub fn delete_range_cf(&self, namespace: &str, start: u64, end: u64) -> Result<()> {
+        self.store.delete_range_cf(namespace, start, end)
+    }
+
+    pub fn len_cf(&self, namespace: &str) -> Result<usize> {
+        self.store.len_cf(namespace)
+    }
+
+    pub fn put_str<K: AsRef<[u8]>>(&self, key: K, value: Value) -> Result<Option<Value>> {
+        self.store.put_str(key, value)
+    }
+
+    pub fn get_str<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Value>> {
+        self.store.get_str(key)
+    }
+
+    pub fn delete_str<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Value>> {
+        self.store.delete_str(key)
+    }
+
+    pub fn keys_str(&self) -> Result<Vec<Vec<u8>>> {
+        self.store.keys_str()
+    }
+
+    pub fn write_batch_cf(&self, namespace: &str, ops: Vec<WriteOp>) -> Result<()> {
+        self.store.write_batch_cf(namespace, ops)
+    }
+
+    pub fn keys_cf(&self, namespace: &str) -> Result<Vec<u64>> {
+        self.store.keys_cf(namespace)
+    }
+
+    pub fn keys_cf_with_deadline(&self, namespace: &str, deadline: Option<Instant>) -> Result<Vec<u64>> {
+        self.store.keys_cf_with_deadline(namespace, deadline)
+    }
+
+    pub fn keys_page(&self, start_after: Option<u64>, limit: usize) -> Result<(Vec<u64>, Option<u64>)> {
+        self.store.keys_page(start_after, limit)
+    }
+
+    pub fn keys_page_with_deadline(&self, start_after: Option<u64>, limit: usize, deadline: Option<Instant>) -> Result<(Vec<u64>, Option<u64>)> {
+        self.store.keys_page_with_deadline(start_after, limit, deadline)
+    }
+
+    pub fn keys_page_cf(&self, namespace: &str, start_after: Option<u64>, limit: usize) -> Result<(Vec<u64>, Option<u64>)> {
+        self.store.keys_page_cf(namespace, start_after, limit)
+    }
+
+    pub fn keys_page_cf_with_deadline(
+        &self,
+        namespace: &str,
+        start_after: Option<u64>,
+        limit: usize,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<u64>, Option<u64>)> {
+        self.store.keys_page_cf_with_deadline(namespace, start_after, limit, deadline)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.store.clear()
+    }
+
+    pub fn sweep_expired(&self) -> Result<usize> {
+        self.store.sweep_expired()
+    }
+
+    pub fn compact(&self) -> Result<()> {
+        self.store.compact()
+    }
+
+    pub fn get_db_size(&self) -> Result<u64> {
+        self.store.get_db_size()
+    }
+
+    pub fn get_db_size_cf(&self, namespace: &str) -> Result<u64> {
+        self.store.get_db_size_cf(namespace)
+    }
+
+    pub fn estimate_live_data_size(&self) -> Result<u64> {
+        self.store.estimate_live_data_size()
+    }
+
+    pub fn sst_files_size(&self) -> Result<u64> {
+        self.store.sst_files_size()
+    }
+
+    pub fn running_compactions(&self) -> Result<u64> {
+        self.store.running_compactions()
+    }
+
+    pub fn compaction_pending(&self) -> Result<bool> {
+        self.store.compaction_pending()
+    }
+
+    pub fn path(&self) -> &Path {
+        self.store.path()
+    }
+
+    pub fn accepts_writes(&self) -> Result<bool> {
+        self.store.accepts_writes()
+    }
+
+    pub fn statistics(&self) -> Result<String> {
+        self.store.statistics()
+    }
+
+    pub fn statistics_enabled(&self) -> bool {
+        self.store.statistics_enabled()
+    }
+
+    pub fn block_cache_hit_rate(&self) -> Result<Option<f64>> {
+        self.store.block_cache_hit_rate()
+    }
+
+    pub fn compaction_bytes(&self) -> Result<(u64, u64)> {
+        self.store.compaction_bytes()
+    }
+
+    pub fn logical_size(&self) -> Result<u64> {
+        self.store.logical_size()
+    }
+
+    pub fn verify_integrity(&self) -> Result<Vec<u64>> {
+        self.store.verify_integrity()
+    }
+
+    pub fn diff(&self, other: &KVStore) -> Result<DiffReport> {
+        self.store.diff(&other.store)
+    }
+
+    pub fn aggregate_range(&self, start: u64, end: u64, kind: AggKind) -> Result<AggResult> {
+        self.store.aggregate_range(start, end, kind)
+    }
+
+    pub fn aggregate_range_with_deadline(&self, start: u64, end: u64, kind: AggKind, deadline: Option<Instant>) -> Result<AggResult> {
+        self.store.aggregate_range_with_deadline(start, end, kind, deadline)
+    }
+
+    pub(crate) fn aggregate(&self, start: u64, end: Option<u64>, kind: AggKind, deadline: Option<Instant>) -> Result<AggResult> {
+        self.store.aggregate(start, end, kind, deadline)
+    }
+
+    pub(crate) fn aggregate_cf(&self, namespace: &str, start: u64, end: Option<u64>, kind: AggKind, deadline: Option<Instant>) -> Result<AggResult> {
+        self.store.aggregate_cf(namespace, start, end, kind, deadline)
+    }
+
+    // Runs `f` on tokio's blocking thread pool, so RocksDB calls that hit
+    // disk, wait on a key lock or stall behind compaction don't hold up the
+    // async tasks sharing the runtime. Must be called within a tokio runtime.
+    pub async fn run_blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&KVStore) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || f(&store)).await?
+    }
+
+    pub async fn put_async(&self, key: u64, value: Value) -> Result<Option<Value>> {
+        self.run_blocking(move |store| store.put(key, value)).await
+    }
+
+    pub async fn get_async(&self, key: u64) -> Result<Option<Value>> {
+        self.run_blocking(move |store| store.get(&key)).await
+    }
+
+    pub async fn multi_get_async(&self, keys: Vec<u64>) -> Result<Vec<Option<Value>>> {
+        self.run_blocking(move |store| store.multi_get(&keys)).await
+    }
+
+    pub async fn delete_async(&self, key: u64) -> Result<Option<Value>> {
+        self.run_blocking(move |store| store.delete(&key)).await
+    }
+
+    pub async fn write_batch_async(&self, ops: Vec<WriteOp>) -> Result<()> {
+        self.run_blocking(move |store| store.write_batch(ops)).await
+    }
+
+    pub async fn keys_async(&self) -> Result<Vec<u64>> {
+        self.run_blocking(|store| store.keys()).await
+    }
+
+    pub async fn range_async(&self, start: u64, end: u64) -> Result<Vec<(u64, Value)>> {
+        self.run_blocking(move |store| store.range(start, end)).await
+    }
+
+    pub async fn delete_range_async(&self, start: u64, end: u64) -> Result<()> {
+        self.run_blocking(move |store| store.delete_range(start, end)).await
+    }
+
+    pub async fn compact_async(&self) -> Result<()> {
+        self.run_blocking(|store| store.compact()).await
+    }
+}
+
+impl From<RocksDBStore> for KVStore {
+    fn from(store: RocksDBStore) -> Self {
+        let store = Arc::new(store);
+        Self {
+            _sweeper: Arc::new(TtlSweeper::spawn(Arc::downgrade(&store))),
+            store,
+        }
+    }
+}
+
+impl Default for KVStore {
+    fn default() -> Self {
+        // Create a default RocksDB store in a temporary directory
+        let temp_dir = std::env::temp_dir().join("kvstore_default");
+        Self::new(temp_dir).expect("Failed to create default KVStore")
+    }
+}
+
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod test_support;
+#[cfg(test)]
+use test_support::{test_value, TempDir};
+
+#[test]
+fn test_kv_store_operations() {
+    use rand::Rng;
+    use sha2::{Digest};
+    use grpc_server::kvstore::DataType;
+    use std::collections::HashSet;
+    let temp_dir = TempDir::new("kvstore_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let mut rng = rand::thread_rng();
+    let mut keys_and_hashes = Vec::new();
+    let mut keys_so_far = HashSet::new();
+    for i in 0..100000 {
+        let mut key = rng.gen_range(0..1000000);
+        while keys_so_far.contains(&key) {
+            key = rng.gen_range(0..1000000);
+        }
+        keys_so_far.insert(key);
+        let shape = vec![rng.gen_range(1..10), rng.gen_range(1..10)];
+        let dtype = DataType::Fp64; 
+        let size_check = shape.iter().product::<u64>() * 8; // FP64 is 8 bytes
+        let key_check = key;
+        let data = vec![vec![rng.gen_range(0..1000000) as u8; 8]]; // Create proper data structure
+        let data_hash: [u8; 32] = sha2::Sha256::digest(&data[0]).into();
+       
+        
+        let value = Value {
+            shape,
+            dtype: dtype as i32,
+            size_check,
+            key_check,
+            data,
+            descriptor: None,
+            metadata: Default::default(),
+        };
+        
+        store.put(key, value).unwrap();
+        keys_and_hashes.push((key, data_hash));
+        let data_hash_str = hex::encode(data_hash);
+        println!("iter: {}, key: {}, size_check: {}, key_check: {}, data_hash: {:?}", i, key, size_check, key_check, data_hash_str);
+    }
+    for (key, data_hash) in keys_and_hashes {
+        let value = store.get(&key).unwrap();
+        assert!(value.is_some());
+        let value = value.unwrap();
+        let got_data_hash: [u8; 32] = sha2::Sha256::digest(&value.data[0]).into();
+        assert_eq!(data_hash, got_data_hash, "key: {}, data_hash: {:?}, got_data_hash: {:?}", key, data_hash, got_data_hash);
+    }
+    store.clear().unwrap();
+    assert!(store.is_empty().unwrap());
+}
+
+
+#[test]
+fn test_meta_keys_are_hidden() {
+    let temp_dir = TempDir::new("kvstore_meta_test");
+    let store = RocksDBStore::new(&temp_dir).unwrap();
+
+    // The schema version is written on open
+    let version = store.read_meta_key(SCHEMA_VERSION_KEY).unwrap().unwrap();
+    assert_eq!(version, SCHEMA_VERSION.to_be_bytes());
+
+    // An 8-byte metadata name would look exactly like a u64 user key
+    store.write_meta_key("counter\0", &42u64.to_be_bytes()).unwrap();
+    assert!(store.keys().unwrap().is_empty());
+    assert!(store.is_empty().unwrap());
+
+    let value = Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: 7,
+        data: vec![vec![0u8; 8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    store.put(7, value).unwrap();
+    assert_eq!(store.keys().unwrap(), vec![7]);
+    assert_eq!(store.len().unwrap(), 1);
+
+    store.clear().unwrap();
+    assert!(store.read_meta_key("counter\0").unwrap().is_some());
+}
+
+#[test]
+fn test_diff_stores() {
+    let make_value = |key: u64, fill: u8| Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: key,
+        data: vec![vec![fill; 8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    let dir_a = TempDir::new("kvstore_diff_a");
+    let dir_b = TempDir::new("kvstore_diff_b");
+    let a = KVStore::new(&dir_a).unwrap();
+    let b = KVStore::new(&dir_b).unwrap();
+
+    // 0..10 shared and identical, 10..15 shared but different,
+    // 15..20 only in a, 20..23 only in b
+    for key in 0..20 {
+        a.put(key, make_value(key, 1)).unwrap();
+    }
+    for key in 0..10 {
+        b.put(key, make_value(key, 1)).unwrap();
+    }
+    for key in 10..15 {
+        b.put(key, make_value(key, 2)).unwrap();
+    }
+    for key in 20..23 {
+        b.put(key, make_value(key, 1)).unwrap();
+    }
+
+    let report = a.diff(&b).unwrap();
+    assert!(!report.is_identical());
+    assert_eq!(report.identical, 10);
+    assert_eq!(report.different, 5);
+    assert_eq!(report.only_in_self, 5);
+    assert_eq!(report.only_in_other, 3);
+    assert_eq!(report.sample_different, (10..15).collect::<Vec<u64>>());
+    assert_eq!(report.sample_only_in_self, (15..20).collect::<Vec<u64>>());
+    assert_eq!(report.sample_only_in_other, (20..23).collect::<Vec<u64>>());
+
+    let reverse = b.diff(&a).unwrap();
+    assert_eq!(reverse.only_in_self, 3);
+    assert_eq!(reverse.only_in_other, 5);
+
+    assert!(a.diff(&a).unwrap().is_identical());
+}
+
+#[test]
+fn test_aggregate_range() {
+    let temp_dir = TempDir::new("kvstore_agg_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+
+    // Key k holds the FP64 vector [k, 2k, 0.5]
+    for key in 0..100u64 {
+        let elements = [key as f64, 2.0 * key as f64, 0.5];
+        let data: Vec<u8> = elements.iter().flat_map(|e| e.to_le_bytes()).collect();
+        let value = Value {
+            shape: vec![3],
+            dtype: DataType::Fp64 as i32,
+            size_check: 24,
+            key_check: key,
+            data: vec![data],
+            descriptor: None,
+            metadata: Default::default(),
+        };
+        store.put(key, value).unwrap();
+    }
+
+    // Keys 10..20: sum of 3k + 0.5 = 3 * 145 + 5
+    assert_eq!(store.aggregate_range(10, 20, AggKind::Count).unwrap(), AggResult::Count(10));
+    assert_eq!(store.aggregate_range(10, 20, AggKind::TotalBytes).unwrap(), AggResult::TotalBytes(240));
+    assert_eq!(store.aggregate_range(10, 20, AggKind::Sum).unwrap(), AggResult::Sum(440.0));
+
+    // Empty and open-ended ranges
+    assert_eq!(store.aggregate_range(50, 50, AggKind::Count).unwrap(), AggResult::Count(0));
+    assert_eq!(store.aggregate(90, None, AggKind::Count, None).unwrap(), AggResult::Count(10));
+
+    // Integer values are summed too; FP16 has no native decoding and data
+    // that isn't a whole number of elements is rejected
+    let mut int_value = Value {
+        shape: vec![2],
+        dtype: DataType::Int32 as i32,
+        size_check: 8,
+        key_check: 100,
+        data: vec![[5i32, -2].iter().flat_map(|e| e.to_le_bytes()).collect()],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    store.put(100, int_value.clone()).unwrap();
+    assert_eq!(store.aggregate_range(100, 101, AggKind::Sum).unwrap(), AggResult::Sum(3.0));
+    int_value.data[0].pop();
+    store.put(100, int_value.clone()).unwrap();
+    assert!(store.aggregate_range(100, 101, AggKind::Sum).is_err());
+    int_value.dtype = DataType::Fp16 as i32;
+    int_value.data = vec![vec![0u8; 4]];
+    store.put(100, int_value).unwrap();
+    assert!(store.aggregate_range(100, 101, AggKind::Sum).is_err());
+}
+
+#[test]
+fn test_scan_deadline() {
+    use std::time::Duration;
+    let temp_dir = TempDir::new("kvstore_deadline_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    for key in 0..5000u64 {
+        let value = Value {
+            shape: vec![1],
+            dtype: DataType::Fp64 as i32,
+            size_check: 8,
+            key_check: key,
+            data: vec![vec![0u8; 8]],
+            descriptor: None,
+            metadata: Default::default(),
+        };
+        store.put(key, value).unwrap();
+    }
+
+    // An already-expired deadline aborts before the scan completes
+    let expired = Some(Instant::now());
+    let err = store.keys_with_deadline(expired).unwrap_err();
+    assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Timeout));
+    let err = store.aggregate_range_with_deadline(0, u64::MAX, AggKind::Sum, expired).unwrap_err();
+    assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Timeout));
+    let err = store.keys_page_with_deadline(None, 1000, expired).unwrap_err();
+    assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Timeout));
+    let err = store.range_with_deadline(0, u64::MAX, expired).unwrap_err();
+    assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Timeout));
+    let err = store.scan_prefix_with_deadline(0, 0, expired).unwrap_err();
+    assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Timeout));
+
+    // A generous deadline lets the scan finish
+    let generous = Some(Instant::now() + Duration::from_secs(60));
+    assert_eq!(store.keys_with_deadline(generous).unwrap().len(), 5000);
+}
+
+#[test]
+fn test_value_descriptor() {
+    let temp_dir = TempDir::new("kvstore_descriptor_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let mut value = Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: 1,
+        data: vec![vec![0u8; 8]],
+        descriptor: Some("embedding model=resnet50 layer=fc".to_string()),
+        metadata: Default::default(),
+    };
+    store.put(1, value.clone()).unwrap();
+    assert_eq!(store.get(&1).unwrap().unwrap().descriptor, value.descriptor);
+
+    value.descriptor = Some("x".repeat(MAX_DESCRIPTOR_LEN + 1));
+    let err = store.put(2, value.clone()).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StoreError>(), Some(StoreError::InvalidArgument(_))));
+
+    value.descriptor = Some(String::new());
+    assert!(store.put(2, value).is_err());
+    assert!(!store.contains_key(&2).unwrap());
+}
+
+#[test]
+fn test_with_retry_under_contention() {
+    use std::sync::Mutex;
+    use std::time::Duration;
+    let temp_dir = TempDir::new("kvstore_retry_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let critical_section = Arc::new(Mutex::new(()));
+    let writers = 16u64;
+
+    // Each writer bumps a shared counter, bailing out with a conflict whenever
+    // another writer is inside the read-modify-write section. The conflicts
+    // are made up here: this exercises with_retry's retries and backoff, and
+    // test_map_rocksdb_error covers which RocksDB errors become conflicts.
+    let policy = RetryPolicy {
+        max_attempts: 1000,
+        initial_backoff: Duration::from_micros(50),
+        max_backoff: Duration::from_millis(5),
+    };
+    let handles: Vec<_> = (0..writers).map(|_| {
+        let store = store.clone();
+        let critical_section = critical_section.clone();
+        std::thread::spawn(move || {
+            with_retry(policy, || {
+                let _guard = critical_section.try_lock()
+                    .map_err(|_| StoreError::Conflict("counter is being updated".to_string()))?;
+                let current = store.get(&0)?.map(|v| v.key_check).unwrap_or(0);
+                std::thread::sleep(Duration::from_millis(1));
+                store.put(0, Value {
+                    shape: vec![],
+                    dtype: DataType::Int64 as i32,
+                    size_check: 0,
+                    key_check: current + 1,
+                    data: vec![],
+                    descriptor: None,
+                    metadata: Default::default(),
+                })?;
+                Ok(())
+            })
+        })
+    }).collect();
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+    assert_eq!(store.get(&0).unwrap().unwrap().key_check, writers);
+
+    // Non-conflict errors are not retried, and retries stop at max_attempts
+    let mut attempts = 0;
+    let result: Result<()> = with_retry(policy, || {
+        attempts += 1;
+        anyhow::bail!("permanent failure")
+    });
+    assert!(result.is_err());
+    assert_eq!(attempts, 1);
+
+    let mut attempts = 0;
+    let result: Result<()> = with_retry(RetryPolicy { max_attempts: 3, ..policy }, || {
+        attempts += 1;
+        Err(StoreError::Conflict("always busy".to_string()).into())
+    });
+    assert!(result.is_err());
+    assert_eq!(attempts, 3);
+}
+
+#[test]
+fn test_periodic_compaction_option() {
+    let temp_dir = TempDir::new("kvstore_periodic_test");
+    let store = RocksDBStore::builder()
+        .periodic_compaction(Duration::from_secs(1))
+        .namespaces(["other"])
+        .open(&temp_dir)
+        .unwrap();
+    store.create_namespace("later").unwrap();
+
+    // RocksDB persists the effective options in an OPTIONS-* file, with a
+    // section per column family
+    let mut options_files: Vec<_> = std::fs::read_dir(&temp_dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().unwrap().to_string_lossy().starts_with("OPTIONS-"))
+        .collect();
+    options_files.sort();
+    let latest = std::fs::read_to_string(options_files.last().unwrap()).unwrap();
+    for cf in [DEFAULT_COLUMN_FAMILY_NAME, STR_KEYS_CF, "ns/other", "ns/later"] {
+        let section = latest.split("[CFOptions \"").find(|section| section.starts_with(&format!("{}\"", cf))).unwrap();
+        let section = section.split("[TableOptions").next().unwrap();
+        assert!(section.contains("periodic_compaction_seconds=1"), "{} misses the interval", cf);
+    }
+
+    // Compaction priority can only be chosen when a column family is opened
+    assert!(store.db.set_options(&[("compaction_pri", "kOldestSmallestSeqFirst")]).is_err());
+
+    // Values a snapshot kept alive through a flush sit in the same SST file
+    // as the deletes the sweeper wrote for them. Once the snapshot is gone
+    // only a compaction of that file reclaims them. With older data under
+    // the file nothing else picks it, but with the option RocksDB
+    // recompacts it in the background once it's older than the interval.
+    let value = |key: u64| Value {
+        shape: vec![4096],
+        dtype: DataType::Int8 as i32,
+        size_check: 4096,
+        key_check: key,
+        data: vec![(0..4096).map(|_| rand::random::<u8>()).collect()],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    for key in [0, 1000] {
+        store.put(key, value(key)).unwrap();
+    }
+    store.flush().unwrap();
+    let before = store.sst_files_size().unwrap();
+    for key in 1..=500 {
+        store.put_with_ttl(key, value(key), Duration::from_millis(50)).unwrap();
+    }
+    let snapshot = store.snapshot();
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(store.sweep_expired().unwrap(), 500);
+    store.flush().unwrap();
+    drop(snapshot);
+    let written = store.sst_files_size().unwrap() - before;
+    assert!(written > 500 * 1024, "only {} bytes of SST files", written);
+
+    // File ages are counted in whole seconds. RocksDB looks for files due
+    // for compaction when the LSM tree changes, here on an unrelated flush.
+    std::thread::sleep(Duration::from_millis(2500));
+    store.put(1 << 40, value(1 << 40)).unwrap();
+    store.flush().unwrap();
+    let started = Instant::now();
+    let mut compacted = store.sst_files_size().unwrap().saturating_sub(before);
+    while compacted >= written / 10 && started.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(50));
+        compacted = store.sst_files_size().unwrap().saturating_sub(before);
+    }
+    assert!(compacted < written / 10, "{} bytes of SST files left of {}", compacted, written);
+    drop(store);
+}
+
+#[test]
+fn test_put_batch() {
+    let temp_dir = TempDir::new("kvstore_batch_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+
+    store.put_batch((0..1000).map(|key| (key, test_value(key))).collect()).unwrap();
+    assert_eq!(store.len().unwrap(), 1000);
+    for key in [0, 500, 999] {
+        assert_eq!(store.get(&key).unwrap(), Some(test_value(key)));
+    }
+
+    // One invalid item in the middle means nothing from the batch is written
+    let mut items: Vec<(u64, Value)> = (1000..1010).map(|key| (key, test_value(key))).collect();
+    items[5].1.descriptor = Some(String::new());
+    assert!(store.put_batch(items).is_err());
+    assert_eq!(store.len().unwrap(), 1000);
+    assert!(!store.contains_key(&1000).unwrap());
+}
+
+#[test]
+fn test_multi_get() {
+    let temp_dir = TempDir::new("kvstore_multi_get_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    store.put_batch((0..100).step_by(2).map(|key| (key, test_value(key))).collect()).unwrap();
+
+    // Order follows the input, including duplicates and missing keys
+    let keys = [42, 7, 0, 98, 42, 1000];
+    let values = store.multi_get(&keys).unwrap();
+    assert_eq!(values, vec![
+        Some(test_value(42)),
+        None,
+        Some(test_value(0)),
+        Some(test_value(98)),
+        Some(test_value(42)),
+        None,
+    ]);
+    assert!(store.multi_get(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn test_range_scan() {
+    let temp_dir = TempDir::new("kvstore_range_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    // Keys that straddle byte boundaries to check numeric ordering
+    let keys = [1u64, 255, 256, 1000, 65535, 65536, u64::MAX - 1, u64::MAX];
+    store.put_batch(keys.iter().map(|key| (*key, test_value(*key))).collect()).unwrap();
+
+    let range: Vec<u64> = store.range(255, 65536).unwrap().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(range, vec![255, 256, 1000, 65535]);
+
+    let (key, value) = &store.range(1000, 1001).unwrap()[0];
+    assert_eq!((*key, value), (1000, &test_value(1000)));
+
+    assert!(store.range(2, 255).unwrap().is_empty());
+    assert!(store.range(500, 500).unwrap().is_empty());
+    assert!(store.range(500, 100).unwrap().is_empty());
+    // The end bound is exclusive, so u64::MAX itself is unreachable
+    assert_eq!(store.range(65536, u64::MAX).unwrap().len(), 2);
+}
+
+#[test]
+fn test_len_counter_under_concurrency() {
+    let temp_dir = TempDir::new("kvstore_len_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+
+    // Threads race on a small, overlapping key space so the same key is
+    // concurrently inserted, overwritten and deleted
+    let handles: Vec<_> = (0..8u64).map(|thread| {
+        let store = store.clone();
+        std::thread::spawn(move || {
+            use rand::Rng;
+            let mut rng = rand::thread_rng();
+            for i in 0..500u64 {
+                let key = rng.gen_range(0..200u64);
+                match (thread + i) % 4 {
+                    0 | 1 => { store.put(key, test_value(key)).unwrap(); }
+                    2 => { store.delete(&key).unwrap(); }
+                    _ => {
+                        let batch = (key..key + 3).map(|k| (k, test_value(k))).collect();
+                        store.put_batch(batch).unwrap();
+                    }
+                }
+            }
+        })
+    }).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let ground_truth = store.keys().unwrap().len();
+    assert_eq!(store.len().unwrap(), ground_truth);
+
+    // A batch repeating a new key counts it once
+    store.clear().unwrap();
+    assert_eq!(store.len().unwrap(), 0);
+    store.put_batch(vec![(5, test_value(5)), (5, test_value(5)), (6, test_value(6))]).unwrap();
+    assert_eq!(store.len().unwrap(), 2);
+
+    // The counter is rebuilt on reopen
+    drop(store);
+    let reopened = KVStore::new(&temp_dir).unwrap();
+    assert_eq!(reopened.len().unwrap(), 2);
+}
+
+#[test]
+fn test_compare_and_swap() {
+    let temp_dir = TempDir::new("kvstore_cas_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let make_value = |version: u64| Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: 1,
+        data: vec![version.to_le_bytes().to_vec()],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+
+    // Insert-if-absent, then a stale expectation fails
+    assert!(store.compare_and_swap(1, None, make_value(0)).unwrap());
+    assert!(!store.compare_and_swap(1, None, make_value(1)).unwrap());
+    assert!(!store.compare_and_swap(1, Some(make_value(7)), make_value(1)).unwrap());
+    assert_eq!(store.get(&1).unwrap(), Some(make_value(0)));
+    assert_eq!(store.len().unwrap(), 1);
+
+    // Threads racing from the same expected value: exactly one wins each round
+    for round in 0..20u64 {
+        let handles: Vec<_> = (0..8u64).map(|thread| {
+            let store = store.clone();
+            let expected = make_value(round);
+            std::thread::spawn(move || {
+                store.compare_and_swap(1, Some(expected), make_value(round + 1 + thread * 1000)).unwrap()
+            })
+        }).collect();
+        let winners = handles.into_iter().map(|h| h.join().unwrap()).filter(|&won| won).count();
+        assert_eq!(winners, 1);
+        // Reset to a known value for the next round
+        let current = store.get(&1).unwrap();
+        assert!(store.compare_and_swap(1, current, make_value(round + 1)).unwrap());
+    }
+    assert_eq!(store.len().unwrap(), 1);
+}
+
+#[test]
+fn test_put_with_ttl() {
+    let temp_dir = TempDir::new("kvstore_ttl_test");
+    let store = RocksDBStore::new(&temp_dir).unwrap();
+
+    store.put_with_ttl(1, test_value(1), Duration::from_millis(50)).unwrap();
+    store.put_with_ttl(2, test_value(2), Duration::from_millis(50)).unwrap();
+    store.put_with_ttl(3, test_value(3), Duration::from_secs(3600)).unwrap();
+    store.put(4, test_value(4)).unwrap();
+    assert_eq!(store.get(&1).unwrap(), Some(test_value(1)));
+
+    // A plain put clears the expiry set earlier
+    store.put(2, test_value(2)).unwrap();
+
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(store.get(&1).unwrap(), None);
+    assert!(!store.contains_key(&1).unwrap());
+    assert_eq!(store.multi_get(&[1, 2, 3, 4]).unwrap(), vec![None, Some(test_value(2)), Some(test_value(3)), Some(test_value(4))]);
+    // Expired but not yet swept: still counted, but skipped by scans
+    assert_eq!(store.len().unwrap(), 4);
+    assert_eq!(store.keys().unwrap(), vec![2, 3, 4]);
+    assert_eq!(store.keys_page(None, 2).unwrap(), (vec![2, 3], Some(3)));
+    assert_eq!(store.keys_iter().collect::<Result<Vec<_>>>().unwrap(), vec![2, 3, 4]);
+    let live = vec![(2, test_value(2)), (3, test_value(3)), (4, test_value(4))];
+    assert_eq!(store.range(0, 10).unwrap(), live);
+    assert_eq!(store.scan_prefix(0, 0).unwrap(), live);
+    assert_eq!(store.iter().collect::<Result<Vec<_>>>().unwrap(), live);
+    assert_eq!(store.snapshot().range(0, 10).unwrap(), live);
+    assert_eq!(store.aggregate_range(0, 10, AggKind::Count).unwrap(), AggResult::Count(3));
+
+    assert_eq!(store.sweep_expired().unwrap(), 1);
+    assert_eq!(store.keys().unwrap(), vec![2, 3, 4]);
+    assert_eq!(store.len().unwrap(), 3);
+    assert_eq!(store.sweep_expired().unwrap(), 0);
+
+    // Re-putting an expired key reports no previous value
+    store.put_with_ttl(5, test_value(5), Duration::from_millis(1)).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(store.put(5, test_value(5)).unwrap(), None);
+    assert_eq!(store.sweep_expired().unwrap(), 0);
+    assert_eq!(store.len().unwrap(), 4);
+    drop(store);
+
+    // KVStore sweeps in the background
+    let kv = KVStore::new(&temp_dir).unwrap();
+    kv.put_with_ttl(6, test_value(6), Duration::from_millis(10)).unwrap();
+    assert_eq!(kv.len().unwrap(), 5);
+    std::thread::sleep(TTL_SWEEP_INTERVAL * 3);
+    assert_eq!(kv.len().unwrap(), 4);
+    assert_eq!(kv.keys().unwrap(), vec![2, 3, 4, 5]);
+}
+
+#[test]
+fn test_merge_add() {
+    let temp_dir = TempDir::new("kvstore_merge_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let fp64 = |elements: &[f64]| Value {
+        shape: vec![elements.len() as u64],
+        dtype: DataType::Fp64 as i32,
+        size_check: elements.len() as u64 * 8,
+        key_check: 1,
+        data: vec![elements.iter().flat_map(|e| e.to_le_bytes()).collect()],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    let elements = |value: Value| -> Vec<f64> {
+        value.data.concat().chunks_exact(8).map(|c| f64::from_le_bytes(c.try_into().unwrap())).collect()
+    };
+
+    // The first delta initializes the key
+    store.merge_add(1, fp64(&[1.0, 2.0, 3.0])).unwrap();
+    assert_eq!(store.len().unwrap(), 1);
+
+    let handles: Vec<_> = (0..4).map(|_| {
+        let store = store.clone();
+        std::thread::spawn(move || {
+            for _ in 0..25 {
+                store.merge_add(1, fp64(&[1.0, 0.5, -1.0])).unwrap();
+            }
+        })
+    }).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(elements(store.get(&1).unwrap().unwrap()), vec![101.0, 52.0, -97.0]);
+
+    // Mismatched shapes and dtypes are rejected and leave the value alone
+    let err = store.merge_add(1, fp64(&[1.0, 2.0])).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StoreError>(), Some(StoreError::InvalidArgument(_))));
+    let mut ints = fp64(&[1.0, 2.0, 3.0]);
+    ints.dtype = DataType::Int64 as i32;
+    assert!(store.merge_add(1, ints).is_err());
+    assert_eq!(elements(store.get(&1).unwrap().unwrap()), vec![101.0, 52.0, -97.0]);
+
+    // A shape whose size overflows is rejected rather than wrapping around
+    let mut huge = fp64(&[1.0]);
+    huge.shape = vec![u64::MAX, 2];
+    store.merge_add(2, huge.clone()).unwrap();
+    let err = store.merge_add(2, huge).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StoreError>(), Some(StoreError::InvalidArgument(_))));
+
+    // Merged values survive compaction and reopen
+    store.compact().unwrap();
+    drop(store);
+    let store = KVStore::new(&temp_dir).unwrap();
+    assert_eq!(elements(store.get(&1).unwrap().unwrap()), vec![101.0, 52.0, -97.0]);
+}
+
+#[test]
+fn test_snapshot_isolation() {
+    let temp_dir = TempDir::new("kvstore_snapshot_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let make_value = |key: u64, version: u64| Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: key,
+        data: vec![version.to_le_bytes().to_vec()],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+
+    for key in 0..10 {
+        store.put(key, make_value(key, 0)).unwrap();
+    }
+    let snapshot = store.snapshot();
+
+    // Writes after the snapshot: new keys, overwrites and deletes
+    for key in 10..20 {
+        store.put(key, make_value(key, 0)).unwrap();
     }
+    store.put(3, make_value(3, 1)).unwrap();
+    store.delete(&4).unwrap();
+    store.merge_add(5, make_value(5, 0)).unwrap();
+
+    assert_eq!(snapshot.get(&15).unwrap(), None);
+    assert_eq!(snapshot.get(&3).unwrap(), Some(make_value(3, 0)));
+    assert_eq!(snapshot.get(&4).unwrap(), Some(make_value(4, 0)));
+    assert_eq!(
+        snapshot.multi_get(&[2, 4, 12]).unwrap(),
+        vec![Some(make_value(2, 0)), Some(make_value(4, 0)), None]
+    );
+    let keys: Vec<u64> = snapshot.range(0, u64::MAX).unwrap().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, (0..10).collect::<Vec<_>>());
+
+    // The live store sees the new state
+    assert_eq!(store.get(&3).unwrap(), Some(make_value(3, 1)));
+    assert_eq!(store.get(&4).unwrap(), None);
+    assert_eq!(store.range(0, u64::MAX).unwrap().len(), 19);
 }
 
-impl Drop for RocksDBStore {
-    fn drop(&mut self) {
-        // RocksDB will be automatically closed when the Arc is dropped
+#[test]
+fn test_backup_and_restore() {
+    let db_dir = TempDir::new("kvstore_backup_db");
+    let backup_dir = TempDir::new("kvstore_backup");
+    let store = KVStore::new(&db_dir).unwrap();
+    let make_value = |key: u64| Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: key,
+        data: vec![(key as f64).to_le_bytes().to_vec()],
+        descriptor: Some(format!("key {}", key)),
+        metadata: Default::default(),
+    };
+
+    for key in 0..50 {
+        store.put(key, make_value(key)).unwrap();
+    }
+    store.create_backup(&backup_dir).unwrap();
+    // A second, incremental backup to the same directory; restore uses the latest
+    for key in 50..100 {
+        store.put(key, make_value(key)).unwrap();
+    }
+    store.create_backup(&backup_dir).unwrap();
+
+    store.clear().unwrap();
+    assert!(store.is_empty().unwrap());
+    drop(store);
+
+    RocksDBStore::restore_from_backup(&backup_dir, &db_dir).unwrap();
+    let restored = KVStore::new(&db_dir).unwrap();
+    assert_eq!(restored.len().unwrap(), 100);
+    for key in 0..100 {
+        assert_eq!(restored.get(&key).unwrap(), Some(make_value(key)));
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct KVStore {
-    store: Arc<RocksDBStore>,
+#[test]
+fn test_namespaces() {
+    let temp_dir = TempDir::new("kvstore_namespace_test");
+    let store = KVStore::with_namespaces(&temp_dir, &["weights", "grads"]).unwrap();
+    let make_value = |fill: u8| Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: 1,
+        data: vec![vec![fill; 8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+
+    // The same key lives independently in each namespace and the default keyspace
+    store.put(1, make_value(0)).unwrap();
+    store.put_cf("weights", 1, make_value(1)).unwrap();
+    assert_eq!(store.put_cf("grads", 1, make_value(2)).unwrap(), None);
+    assert_eq!(store.get(&1).unwrap(), Some(make_value(0)));
+    assert_eq!(store.get_cf("weights", &1).unwrap(), Some(make_value(1)));
+    assert_eq!(store.get_cf("grads", &1).unwrap(), Some(make_value(2)));
+    assert_eq!(store.len().unwrap(), 1);
+
+    store.put_cf("weights", 2, make_value(1)).unwrap();
+    assert_eq!(store.keys_cf("weights").unwrap(), vec![1, 2]);
+    let entries: Vec<_> = store.iter_from_cf("weights", 2).unwrap().map(|entry| entry.unwrap()).collect();
+    assert_eq!(entries, vec![(2, make_value(1))]);
+    assert_eq!(store.multi_get_cf("weights", &[2, 3, 1]).unwrap(), vec![Some(make_value(1)), None, Some(make_value(1))]);
+    assert_eq!(store.aggregate_cf("weights", 0, None, AggKind::Count, None).unwrap(), AggResult::Count(2));
+    assert_eq!(store.aggregate_cf("weights", 2, None, AggKind::TotalBytes, None).unwrap(), AggResult::TotalBytes(8));
+    assert!(!store.compare_and_swap_cf("weights", 2, None, make_value(4)).unwrap());
+    assert!(store.compare_and_swap_cf("weights", 2, Some(make_value(1)), make_value(4)).unwrap());
+    assert_eq!(store.get_cf("weights", &2).unwrap(), Some(make_value(4)));
+    assert_eq!(store.get(&2).unwrap(), None);
+    store.put(2, make_value(0)).unwrap();
+    assert_eq!(store.delete_batch_cf("weights", &[2, 2, 3]).unwrap(), 1);
+    assert_eq!(store.keys_cf("weights").unwrap(), vec![1]);
+    assert!(store.contains_key(&2).unwrap());
+    assert!(!store.contains_key_cf("weights", &2).unwrap());
+    assert_eq!(store.contains_keys_cf("weights", &[1, 2]).unwrap(), vec![true, false]);
+    assert_eq!(store.delete(&2).unwrap(), Some(make_value(0)));
+    assert_eq!(store.delete_cf("grads", &1).unwrap(), Some(make_value(2)));
+    assert!(store.keys_cf("grads").unwrap().is_empty());
+    assert_eq!(store.keys().unwrap(), vec![1]);
+
+    let err = store.get_cf("missing", &1).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StoreError>(), Some(StoreError::NotFound(_))));
+
+    // Namespaces created at runtime are reopened without being listed again
+    store.create_namespace("optimizer").unwrap();
+    store.create_namespace("optimizer").unwrap();
+    store.put_cf("optimizer", 7, make_value(3)).unwrap();
+    drop(store);
+    let store = KVStore::new(&temp_dir).unwrap();
+    assert_eq!(store.namespaces().unwrap(), vec!["grads", "optimizer", "weights"]);
+    assert_eq!(store.get_cf("optimizer", &7).unwrap(), Some(make_value(3)));
 }
 
-impl KVStore {
-    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
-        let store = RocksDBStore::new(path)?;
-        Ok(Self {
-            store: Arc::new(store),
-        })
+#[test]
+fn test_write_batch() {
+    let temp_dir = TempDir::new("kvstore_write_batch_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    store.put(1, test_value(1)).unwrap();
+    store.put(2, test_value(2)).unwrap();
+
+    // Ops apply in order: 3 is put then deleted, 4 is deleted then put
+    store.write_batch(vec![
+        WriteOp::Delete(1),
+        WriteOp::Put(3, test_value(3)),
+        WriteOp::Delete(3),
+        WriteOp::Delete(4),
+        WriteOp::Put(4, test_value(4)),
+        WriteOp::Put(2, test_value(20)),
+    ]).unwrap();
+    assert_eq!(store.keys().unwrap(), vec![2, 4]);
+    assert_eq!(store.get(&2).unwrap(), Some(test_value(20)));
+    assert_eq!(store.len().unwrap(), 2);
+
+    // One invalid value rejects the whole batch
+    let mut invalid = test_value(6);
+    invalid.descriptor = Some(String::new());
+    let err = store.write_batch(vec![
+        WriteOp::Put(5, test_value(5)),
+        WriteOp::Put(6, invalid),
+        WriteOp::Delete(2),
+    ]).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StoreError>(), Some(StoreError::InvalidArgument(_))));
+    assert_eq!(store.keys().unwrap(), vec![2, 4]);
+    assert_eq!(store.len().unwrap(), 2);
+}
+
+#[test]
+fn test_put_validated() {
+    let temp_dir = TempDir::new("kvstore_validate_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let make_value = |shape: Vec<u64>, dtype: DataType, size_check: u64, key_check: u64| Value {
+        shape,
+        dtype: dtype as i32,
+        size_check,
+        key_check,
+        data: vec![vec![0u8; size_check as usize]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    let is_invalid = |err: anyhow::Error| matches!(err.downcast_ref::<StoreError>(), Some(StoreError::InvalidArgument(_)));
+
+    store.put_validated(1, make_value(vec![2, 3], DataType::Fp64, 48, 1)).unwrap();
+    store.put_validated(2, make_value(vec![2, 3], DataType::Fp32, 24, 2)).unwrap();
+    // Packed sub-byte types round up to whole bytes
+    store.put_validated(3, make_value(vec![3], DataType::Int4, 2, 3)).unwrap();
+
+    // Shape/size mismatch
+    assert!(is_invalid(store.put_validated(4, make_value(vec![2, 2], DataType::Fp64, 16, 4)).unwrap_err()));
+    assert!(is_invalid(store.put_validated(4, make_value(vec![u64::MAX, 2], DataType::Fp64, 16, 4)).unwrap_err()));
+    // Key/key_check mismatch
+    assert!(is_invalid(store.put_validated(5, make_value(vec![1], DataType::Fp64, 8, 6)).unwrap_err()));
+
+    assert_eq!(store.keys().unwrap(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_value_checksums() {
+    let temp_dir = TempDir::new("kvstore_crc_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let value = Value {
+        shape: vec![2],
+        dtype: DataType::Fp32 as i32,
+        size_check: 8,
+        key_check: 0,
+        data: vec![vec![1, 2, 3, 4, 5, 6, 7, 8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    store.put(1, value.clone()).unwrap();
+    store.put(2, value.clone()).unwrap();
+    assert!(store.verify_integrity().unwrap().is_empty());
+
+    // Flip the last data byte of key 2 behind the store's back
+    let mut raw = store.store.db.get(2u64.to_be_bytes()).unwrap().unwrap();
+    *raw.last_mut().unwrap() ^= 0xff;
+    store.store.db.put(2u64.to_be_bytes(), raw).unwrap();
+
+    let err = store.get(&2).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StoreError>(), Some(StoreError::Corruption(_))));
+    assert_eq!(store.get(&1).unwrap(), Some(value.clone()));
+    assert_eq!(store.verify_integrity().unwrap(), vec![2]);
+
+    // Values written before checksums were added still read
+    store.store.db.put(3u64.to_be_bytes(), value.encode_to_vec()).unwrap();
+    assert_eq!(store.get(&3).unwrap(), Some(value));
+    assert_eq!(store.verify_integrity().unwrap(), vec![2]);
+}
+
+#[test]
+fn test_builder_tuning() {
+    let temp_dir = TempDir::new("kvstore_tuning_test");
+    let open = || {
+        RocksDBStore::builder()
+            .max_open_files(64)
+            .use_fsync(false)
+            .write_buffer_size(4 * 1024 * 1024)
+            .compression(DBCompressionType::None)
+            .block_cache_size(16 * 1024 * 1024)
+            .namespaces(["tuned"])
+            .open(&temp_dir)
+            .unwrap()
+    };
+    let value = Value {
+        shape: vec![4],
+        dtype: DataType::Int8 as i32,
+        size_check: 4,
+        key_check: 0,
+        data: vec![vec![1, 2, 3, 4]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+
+    let store = open();
+    store.put(1, value.clone()).unwrap();
+    store.put_cf("tuned", 1, value.clone()).unwrap();
+    store.create_namespace("later").unwrap();
+    store.merge_add(1, value.clone()).unwrap();
+    store.compact().unwrap();
+    drop(store);
+
+    let store = open();
+    assert_eq!(store.get(&1).unwrap().unwrap().data.concat(), vec![2, 4, 6, 8]);
+    assert_eq!(store.get_cf("tuned", &1).unwrap(), Some(value));
+    assert_eq!(store.namespaces().unwrap(), vec!["later".to_string(), "tuned".to_string()]);
+}
+
+#[test]
+fn test_compression_round_trip() {
+    // Large and repetitive, so every codec actually compresses it
+    let data: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let value = Value {
+        shape: vec![data.len() as u64],
+        dtype: DataType::Int8 as i32,
+        size_check: data.len() as u64,
+        key_check: 0,
+        data: vec![data],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    let builders = [
+        RocksDBStore::builder(),
+        RocksDBStore::builder().compression(DBCompressionType::None),
+        RocksDBStore::builder().compression(DBCompressionType::Snappy),
+        RocksDBStore::builder().compression(DBCompressionType::Zstd),
+        RocksDBStore::builder().compression(DBCompressionType::Zstd).zstd_level(19),
+    ];
+    for builder in builders {
+        let temp_dir = TempDir::new("kvstore_compression_test");
+        let store = builder.open(&temp_dir).unwrap();
+        store.put(1, value.clone()).unwrap();
+        // Push the value out of the memtable into a compressed SST file
+        store.db.flush().unwrap();
+        store.compact().unwrap();
+        assert_eq!(store.get(&1).unwrap(), Some(value.clone()));
     }
+}
 
-    pub fn put(&self, key: u64, value: Value) -> Result<Option<Value>> {
-        self.store.put(key, value)
+#[test]
+fn test_db_size() {
+    let temp_dir = TempDir::new("kvstore_size_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let value = Value {
+        shape: vec![4096],
+        dtype: DataType::Int8 as i32,
+        size_check: 4096,
+        key_check: 0,
+        data: vec![vec![7u8; 4096]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    for key in 0..100 {
+        store.put(key, value.clone()).unwrap();
     }
+    assert!(store.get_db_size().unwrap() > 0);
+    assert!(store.logical_size().unwrap() > 100 * 4096);
+    assert_eq!(store.sst_files_size().unwrap(), 0);
+    assert_eq!(store.path(), temp_dir.as_path());
 
-    pub fn get(&self, key: &u64) -> Result<Option<Value>> {
-        self.store.get(key)
+    // Once flushed the data lives in SST files, compressed
+    store.store.db.flush().unwrap();
+    let on_disk = store.get_db_size().unwrap();
+    assert!(on_disk > 0);
+    assert!(on_disk < store.logical_size().unwrap());
+    assert!(store.sst_files_size().unwrap() > 0 && store.sst_files_size().unwrap() <= on_disk);
+    // One SST file is nowhere near enough to trigger a compaction
+    assert_eq!(store.running_compactions().unwrap(), 0);
+    assert!(!store.compaction_pending().unwrap());
+    assert!(store.estimate_live_data_size().unwrap() > 0);
+    assert!(store.accepts_writes().unwrap());
+}
+
+#[test]
+fn test_lazy_iterators() {
+    let temp_dir = TempDir::new("kvstore_iter_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let value = |key: u64| Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: key,
+        data: vec![vec![key as u8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    for key in [5, 1, 9, 3] {
+        store.put(key, value(key)).unwrap();
     }
+    // Stray keys of the wrong length are skipped rather than failing the scan
+    store.store.db.put(b"odd", b"not a value").unwrap();
+    store.store.db.put([0u8; 9], b"not a value").unwrap();
 
-    pub fn delete(&self, key: &u64) -> Result<Option<Value>> {
-        self.store.delete(key)
+    let keys: Vec<u64> = store.keys_iter().collect::<Result<_>>().unwrap();
+    assert_eq!(keys, vec![1, 3, 5, 9]);
+    let first_two: Vec<u64> = store.keys_iter().take(2).collect::<Result<_>>().unwrap();
+    assert_eq!(first_two, vec![1, 3]);
+
+    let entries: Vec<(u64, Value)> = store.iter().collect::<Result<_>>().unwrap();
+    assert_eq!(entries, [1, 3, 5, 9].map(|key| (key, value(key))).to_vec());
+    let (key, first) = store.iter().next().unwrap().unwrap();
+    assert_eq!((key, first), (1, value(1)));
+}
+
+#[test]
+fn test_scan_prefix() {
+    let temp_dir = TempDir::new("kvstore_prefix_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let value = Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: 0,
+        data: vec![vec![0]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    // Type tag in the top 4 bits
+    let tagged = |tag: u64, id: u64| (tag << 60) | id;
+    let all_keys = [tagged(0, 7), tagged(1, 0), tagged(1, 5), tagged(1, (1 << 60) - 1), tagged(2, 0), tagged(15, 3), u64::MAX];
+    for key in all_keys {
+        store.put(key, value.clone()).unwrap();
     }
+    let scan_keys = |prefix: u64, bits: u32| -> Vec<u64> {
+        store.scan_prefix(prefix, bits).unwrap().into_iter().map(|(key, _)| key).collect()
+    };
 
-    pub fn contains_key(&self, key: &u64) -> Result<bool> {
-        self.store.contains_key(key)
+    assert_eq!(scan_keys(tagged(1, 0), 4), vec![tagged(1, 0), tagged(1, 5), tagged(1, (1 << 60) - 1)]);
+    // Low bits of the prefix don't matter
+    assert_eq!(scan_keys(tagged(2, 12345), 4), vec![tagged(2, 0)]);
+    // The last prefix runs to the end of the keyspace
+    assert_eq!(scan_keys(tagged(15, 0), 4), vec![tagged(15, 3), u64::MAX]);
+    assert_eq!(scan_keys(tagged(3, 0), 4), Vec::<u64>::new());
+    // Not a multiple of 8 bits, and not byte aligned
+    assert_eq!(scan_keys(tagged(1, 0), 3), vec![tagged(0, 7), tagged(1, 0), tagged(1, 5), tagged(1, (1 << 60) - 1)]);
+
+    assert_eq!(scan_keys(0, 0), all_keys.to_vec());
+    assert_eq!(scan_keys(tagged(1, 5), 64), vec![tagged(1, 5)]);
+    assert_eq!(scan_keys(u64::MAX, 64), vec![u64::MAX]);
+    assert_eq!(scan_keys(tagged(1, 6), 64), Vec::<u64>::new());
+    assert!(store.scan_prefix(0, 65).is_err());
+}
+
+#[test]
+fn test_delete_range() {
+    let temp_dir = TempDir::new("kvstore_delete_range_test");
+    let store = KVStore::with_namespaces(&temp_dir, &["other"]).unwrap();
+    let value = Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: 0,
+        data: vec![vec![0]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    for key in 0..10 {
+        store.put(key, value.clone()).unwrap();
+        store.put_cf("other", key, value.clone()).unwrap();
     }
+    store.put_with_ttl(5, value.clone(), Duration::from_secs(3600)).unwrap();
 
-    pub fn len(&self) -> Result<usize> {
-        self.store.len()
+    store.delete_range(2, 6).unwrap();
+    assert_eq!(store.keys().unwrap(), vec![0, 1, 6, 7, 8, 9]);
+    assert_eq!(store.len().unwrap(), 6);
+    // The expiry went with the key, so a plain put makes it permanent
+    store.put(5, value.clone()).unwrap();
+    assert_eq!(store.store.db.get_cf(&store.store.ttl_cf().unwrap(), 5u64.to_be_bytes()).unwrap(), None);
+    assert_eq!(store.len().unwrap(), 7);
+
+    store.delete_range(6, 6).unwrap();
+    store.delete_range(9, 0).unwrap();
+    store.delete_range(100, 200).unwrap();
+    assert_eq!(store.len().unwrap(), 7);
+
+    // Namespaces are untouched by the default store's range, and vice versa
+    assert_eq!(store.len_cf("other").unwrap(), 10);
+    store.delete_range_cf("other", 0, 5).unwrap();
+    assert_eq!(store.keys_cf("other").unwrap(), vec![5, 6, 7, 8, 9]);
+    assert_eq!(store.len().unwrap(), 7);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_async_wrappers() {
+    let temp_dir = TempDir::new("kvstore_async_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let value = Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: 0,
+        data: vec![vec![0]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    assert_eq!(store.put_async(1, value.clone()).await.unwrap(), None);
+    assert_eq!(store.get_async(1).await.unwrap(), Some(value.clone()));
+    store.write_batch_async(vec![WriteOp::Put(2, value.clone()), WriteOp::Delete(1)]).await.unwrap();
+    assert_eq!(store.keys_async().await.unwrap(), vec![2]);
+    assert_eq!(store.delete_async(2).await.unwrap(), Some(value));
+
+    // On a single-threaded runtime a blocking call made inline would stop
+    // every other task; run through run_blocking, a slow call (standing in
+    // for a compaction stall) leaves the runtime free to make progress
+    let slow = store.run_blocking(|store| {
+        std::thread::sleep(Duration::from_millis(500));
+        store.compact()
+    });
+    let ticks = async {
+        let started = Instant::now();
+        let mut ticks = 0;
+        while started.elapsed() < Duration::from_millis(300) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            ticks += 1;
+        }
+        ticks
+    };
+    let (slow, ticks) = tokio::join!(slow, ticks);
+    slow.unwrap();
+    assert!(ticks >= 10, "runtime made only {} ticks during the slow call", ticks);
+}
+
+#[test]
+fn test_get_or_insert_with() {
+    use std::sync::atomic::AtomicUsize;
+
+    let temp_dir = TempDir::new("kvstore_get_or_insert_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let make_value = |n: u8| Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: 0,
+        data: vec![vec![n]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+
+    store.put(1, make_value(1)).unwrap();
+    let value = store.get_or_insert_with(1, || panic!("key 1 is present")).unwrap();
+    assert_eq!(value, make_value(1));
+
+    // Only one of the racing callers computes the value, and all see it
+    let calls = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..8u8)
+        .map(|n| {
+            let store = store.clone();
+            let calls = calls.clone();
+            std::thread::spawn(move || {
+                store.get_or_insert_with(2, || {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(20));
+                    make_value(n)
+                }).unwrap()
+            })
+        })
+        .collect();
+    let results: Vec<Value> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert!(results.iter().all(|value| *value == results[0]));
+    assert_eq!(store.get(&2).unwrap(), Some(results[0].clone()));
+    assert_eq!(store.len().unwrap(), 2);
+
+    // An expired value is replaced
+    store.put_with_ttl(3, make_value(3), Duration::from_millis(1)).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(store.get_or_insert_with(3, || make_value(4)).unwrap(), make_value(4));
+    assert_eq!(store.get(&3).unwrap(), Some(make_value(4)));
+    assert_eq!(store.len().unwrap(), 3);
+}
+
+#[test]
+fn test_contains_keys() {
+    let temp_dir = TempDir::new("kvstore_contains_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let value = Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: 0,
+        data: vec![vec![0]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    store.put(1, value.clone()).unwrap();
+    store.put(3, value.clone()).unwrap();
+    store.put_with_ttl(4, value, Duration::from_millis(1)).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    assert!(store.contains_key(&1).unwrap());
+    assert!(!store.contains_key(&2).unwrap());
+    // Expired but not yet swept
+    assert!(!store.contains_key(&4).unwrap());
+    assert_eq!(store.contains_keys(&[4, 3, 2, 1]).unwrap(), vec![false, true, false, true]);
+    assert!(store.contains_keys(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn test_export_import() {
+    let source_dir = TempDir::new("kvstore_export_test");
+    let source = KVStore::from(RocksDBStore::builder().value_zstd_level(3).open(&source_dir).unwrap());
+    let value = |key: u64| Value {
+        shape: vec![256],
+        dtype: DataType::Int8 as i32,
+        size_check: 256,
+        key_check: key,
+        data: vec![vec![key as u8; 256]],
+        descriptor: None,
+        metadata: [("origin".to_string(), "source".to_string())].into(),
+    };
+    for key in 0..(IMPORT_BATCH_SIZE as u64 + 10) {
+        source.put(key, value(key)).unwrap();
     }
+    source.put_with_ttl(1 << 40, value(0), Duration::ZERO).unwrap();
+    // Still in the memtable when the export starts
+    source.put(u64::MAX, value(7)).unwrap();
 
-    pub fn is_empty(&self) -> Result<bool> {
-        self.store.is_empty()
+    let mut dump = Vec::new();
+    let exported = source.export(&mut dump).unwrap();
+    assert_eq!(exported, IMPORT_BATCH_SIZE as u64 + 11);
+
+    // Into a store in another directory, which keeps values uncompressed
+    let target_dir = TempDir::new("kvstore_import_test");
+    let target = KVStore::new(&target_dir).unwrap();
+    target.put(5, value(6)).unwrap();
+    assert_eq!(target.import(dump.as_slice(), ImportPolicy::Overwrite).unwrap(), exported);
+    assert_eq!(target.len().unwrap() as u64, exported);
+    assert_eq!(target.get(&u64::MAX).unwrap(), Some(value(7)));
+    assert_eq!(target.get(&5).unwrap(), Some(value(5)));
+    assert_eq!(target.get(&(1 << 40)).unwrap(), None);
+    let report = source.store.diff(&target.store).unwrap();
+    assert_eq!(report.identical, exported);
+    assert_eq!(report.different + report.only_in_other, 0);
+
+    // Anything but a complete dump is refused
+    let error = target.import(&b"not a dump"[..], ImportPolicy::Overwrite).unwrap_err();
+    assert!(matches!(error.downcast_ref::<StoreError>(), Some(StoreError::InvalidArgument(_))));
+    let error = target.import(&dump[..dump.len() - 3], ImportPolicy::Overwrite).unwrap_err();
+    assert!(matches!(error.downcast_ref::<StoreError>(), Some(StoreError::Corruption(_))));
+}
+
+#[test]
+fn test_import_policies() {
+    let value = |key: u64, origin: &str| Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: key,
+        data: vec![vec![key as u8]],
+        descriptor: None,
+        metadata: [("origin".to_string(), origin.to_string())].into(),
+    };
+    let source_dir = TempDir::new("kvstore_import_policy_source");
+    let source = RocksDBStore::new(&source_dir).unwrap();
+    for key in 0..(IMPORT_BATCH_SIZE as u64 + 10) {
+        source.put(key, value(key, "dump")).unwrap();
     }
+    let mut dump = Vec::new();
+    source.export(&mut dump).unwrap();
 
-    pub fn keys(&self) -> Result<Vec<u64>> {
-        self.store.keys()
+    // Keys 3 and the last one collide with the dump, 1 << 40 doesn't
+    let last = IMPORT_BATCH_SIZE as u64 + 9;
+    let prepopulated = || {
+        let dir = TempDir::new("kvstore_import_policy_target");
+        let store = RocksDBStore::new(&dir).unwrap();
+        for key in [3, last, 1 << 40] {
+            store.put(key, value(key, "target")).unwrap();
+        }
+        // The directory goes once the store is done with it
+        (store, dir)
+    };
+
+    let (store, _dir) = prepopulated();
+    assert_eq!(store.import(dump.as_slice(), ImportPolicy::Overwrite).unwrap(), last + 1);
+    assert_eq!(store.len().unwrap() as u64, last + 2);
+    assert_eq!(store.get(&3).unwrap(), Some(value(3, "dump")));
+    assert_eq!(store.get(&last).unwrap(), Some(value(last, "dump")));
+    assert_eq!(store.get(&(1 << 40)).unwrap(), Some(value(1 << 40, "target")));
+
+    let (store, _dir) = prepopulated();
+    assert_eq!(store.import(dump.as_slice(), ImportPolicy::SkipExisting).unwrap(), last - 1);
+    assert_eq!(store.len().unwrap() as u64, last + 2);
+    assert_eq!(store.get(&3).unwrap(), Some(value(3, "target")));
+    assert_eq!(store.get(&4).unwrap(), Some(value(4, "dump")));
+    assert_eq!(store.get(&last).unwrap(), Some(value(last, "target")));
+
+    // The collision at the very end still stops every earlier write
+    let (store, _dir) = prepopulated();
+    store.delete(&3).unwrap();
+    let error = store.import(dump.as_slice(), ImportPolicy::Error).unwrap_err();
+    assert_eq!(error.downcast_ref::<StoreError>(), Some(&StoreError::AlreadyExists(format!("key {}", last))));
+    assert_eq!(store.len().unwrap(), 2);
+    assert_eq!(store.get(&0).unwrap(), None);
+    // And without collisions it imports everything
+    store.delete(&last).unwrap();
+    assert_eq!(store.import(dump.as_slice(), ImportPolicy::Error).unwrap(), last + 1);
+    assert_eq!(store.len().unwrap() as u64, last + 2);
+}
+
+#[test]
+fn test_watch() {
+    use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+    let temp_dir = TempDir::new("kvstore_watch_test");
+    let store = RocksDBStore::new(&temp_dir).unwrap();
+    let value = |key: u64| Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: key,
+        data: vec![vec![key as u8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    // Writes before subscribing aren't reported
+    store.put(1, value(1)).unwrap();
+    let mut changes = store.watch();
+    let mut drain = || {
+        let mut events = Vec::new();
+        loop {
+            match changes.try_recv() {
+                Ok(event) => events.push((event.key, ChangeOp::try_from(event.op).unwrap())),
+                Err(TryRecvError::Empty) => return events,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+    };
+    assert_eq!(drain(), []);
+
+    store.put(2, value(2)).unwrap();
+    store.delete(&1).unwrap();
+    // Deleting a missing key changes nothing
+    store.delete(&1).unwrap();
+    store.write_batch(vec![WriteOp::Put(3, value(3)), WriteOp::Delete(3), WriteOp::Delete(4)]).unwrap();
+    store.compare_and_swap(2, Some(value(2)), value(4)).unwrap();
+    store.compare_and_swap(2, None, value(4)).unwrap();
+    store.merge_add(2, value(1)).unwrap();
+    assert_eq!(drain(), [
+        (2, ChangeOp::Put),
+        (1, ChangeOp::Delete),
+        (3, ChangeOp::Put),
+        (3, ChangeOp::Delete),
+        (2, ChangeOp::Put),
+        (2, ChangeOp::Put),
+    ]);
+
+    store.put_batch((10..15).map(|key| (key, value(key))).collect()).unwrap();
+    store.delete_batch(&[10, 11, 99]).unwrap();
+    store.delete_range(12, 14).unwrap();
+    store.clear().unwrap();
+    assert_eq!(drain(), [
+        (10, ChangeOp::Put), (11, ChangeOp::Put), (12, ChangeOp::Put), (13, ChangeOp::Put), (14, ChangeOp::Put),
+        (10, ChangeOp::Delete), (11, ChangeOp::Delete),
+        (12, ChangeOp::Delete), (13, ChangeOp::Delete),
+        (2, ChangeOp::Delete), (14, ChangeOp::Delete),
+    ]);
+
+    store.put_with_ttl(5, value(5), Duration::ZERO).unwrap();
+    assert_eq!(store.sweep_expired().unwrap(), 1);
+    assert_eq!(drain(), [(5, ChangeOp::Put), (5, ChangeOp::Delete)]);
+
+    // Namespace changes come tagged with their namespace
+    store.create_namespace("other").unwrap();
+    store.put_cf("other", 7, value(7)).unwrap();
+    store.compare_and_swap_cf("other", 7, Some(value(7)), value(8)).unwrap();
+    store.write_batch_cf("other", vec![WriteOp::Put(8, value(8)), WriteOp::Delete(9)]).unwrap();
+    store.delete_batch_cf("other", &[8, 9]).unwrap();
+    store.delete_range_cf("other", 0, 100).unwrap();
+    store.delete_cf("other", &7).unwrap();
+    let events: Vec<_> = std::iter::from_fn(|| changes.try_recv().ok())
+        .map(|event| (event.store_name, event.key, ChangeOp::try_from(event.op).unwrap()))
+        .collect();
+    let other = |key, op| ("other".to_string(), key, op);
+    assert_eq!(events, [
+        other(7, ChangeOp::Put), other(7, ChangeOp::Put), other(8, ChangeOp::Put),
+        other(8, ChangeOp::Delete), other(7, ChangeOp::Delete),
+    ]);
+
+    // A watcher that falls behind loses the oldest events and is told so
+    for key in 0..(WATCH_CHANNEL_CAPACITY as u64 + 5) {
+        store.put(key, value(key)).unwrap();
     }
+    assert!(matches!(changes.blocking_recv(), Err(RecvError::Lagged(5))));
+    assert_eq!(changes.blocking_recv().unwrap().key, 5);
+}
 
-    pub fn clear(&self) -> Result<()> {
-        self.store.clear()
+#[test]
+fn test_put_sync() {
+    let temp_dir = TempDir::new("kvstore_put_sync_test");
+    let store = KVStore::from(RocksDBStore::builder().statistics(true).namespaces(["other"]).open(&temp_dir).unwrap());
+    let value = |key: u64| Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: key,
+        data: vec![vec![key as u8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    let wal_syncs = |store: &KVStore| store.store.ticker("rocksdb.wal.synced").unwrap();
+
+    // Writes don't sync by default
+    store.put(1, value(1)).unwrap();
+    assert_eq!(store.put_sync(1, value(2), false).unwrap(), Some(value(1)));
+    store.put_cf("other", 1, value(1)).unwrap();
+    assert_eq!(wal_syncs(&store), 0);
+
+    assert_eq!(store.put_sync(1, value(3), true).unwrap(), Some(value(2)));
+    assert_eq!(wal_syncs(&store), 1);
+    assert_eq!(store.put_sync_cf("other", 1, value(2), true).unwrap(), Some(value(1)));
+    assert_eq!(wal_syncs(&store), 2);
+    assert_eq!(store.get(&1).unwrap(), Some(value(3)));
+    assert_eq!(store.len().unwrap(), 1);
+
+    // sync_writes makes syncing the default, which put_sync can still skip
+    let temp_dir = TempDir::new("kvstore_sync_writes_test");
+    let store = KVStore::from(RocksDBStore::builder().statistics(true).sync_writes(true).namespaces(["other"]).open(&temp_dir).unwrap());
+    store.put(1, value(1)).unwrap();
+    store.put_batch(vec![(2, value(2)), (3, value(3))]).unwrap();
+    store.delete(&3).unwrap();
+    assert_eq!(wal_syncs(&store), 3);
+    store.put_sync(4, value(4), false).unwrap();
+    assert_eq!(wal_syncs(&store), 3);
+    store.merge_add(4, value(4)).unwrap();
+    assert_eq!(wal_syncs(&store), 4);
+    store.delete_range_cf("other", 0, 10).unwrap();
+    assert_eq!(wal_syncs(&store), 5);
+}
+
+#[test]
+fn test_statistics() {
+    let temp_dir = TempDir::new("kvstore_statistics_test");
+    let store = KVStore::from(RocksDBStore::builder().statistics(true).open(&temp_dir).unwrap());
+    assert!(store.statistics_enabled());
+    assert_eq!(store.compaction_bytes().unwrap(), (0, 0));
+
+    let value = Value {
+        shape: vec![1024],
+        dtype: DataType::Int8 as i32,
+        size_check: 1024,
+        key_check: 0,
+        data: vec![vec![3u8; 1024]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    // Two overlapping SST files, so compacting has to merge them rather
+    // than just move one down a level
+    for _ in 0..2 {
+        for key in 0..100 {
+            store.put(key, value.clone()).unwrap();
+        }
+        store.flush().unwrap();
+    }
+    // Reads from SST files go through the block cache
+    for key in 0..100 {
+        store.get(&key).unwrap();
     }
+    store.compact().unwrap();
 
-    pub fn compact(&self) -> Result<()> {
-        self.store.compact()
+    assert!(store.statistics().unwrap().contains("rocksdb.block.cache.hit COUNT"));
+    let hit_rate = store.block_cache_hit_rate().unwrap().unwrap();
+    assert!((0.0..=1.0).contains(&hit_rate));
+    let (read, written) = store.compaction_bytes().unwrap();
+    assert!(read > 0 && written > 0);
+
+    let temp_dir = TempDir::new("kvstore_statistics_off_test");
+    let store = RocksDBStore::new(&temp_dir).unwrap();
+    assert!(!store.statistics_enabled());
+    assert!(matches!(store.statistics().unwrap_err().downcast_ref::<StoreError>(), Some(StoreError::InvalidArgument(_))));
+}
+
+#[test]
+fn test_checkpoint() {
+    use std::sync::atomic::AtomicBool;
+
+    let make_value = |key: u64, n: u8| Value {
+        shape: vec![4],
+        dtype: DataType::Int8 as i32,
+        size_check: 4,
+        key_check: key,
+        data: vec![vec![n; 4]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    let temp_dir = TempDir::new("kvstore_checkpoint_test");
+    let checkpoint_dir = TempDir::new("kvstore_checkpoint_copy");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    for key in 0..100 {
+        store.put(key, make_value(key, 1)).unwrap();
     }
+    // Still only in the memtable when the checkpoint starts
+    store.put(100, make_value(100, 1)).unwrap();
 
-    pub fn get_db_size(&self) -> Result<u64> {
-        self.store.get_db_size()
+    // A writer keeps going while the checkpoint is taken, always appending
+    // the next key in order
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let (store, stop) = (store.clone(), stop.clone());
+        std::thread::spawn(move || {
+            let mut key = 1000;
+            while !stop.load(Ordering::SeqCst) {
+                store.put(key, make_value(key, 1)).unwrap();
+                key += 1;
+            }
+        })
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    store.checkpoint(&checkpoint_dir).unwrap();
+    stop.store(true, Ordering::SeqCst);
+    writer.join().unwrap();
+
+    // The original carries on independently
+    store.put(0, make_value(0, 2)).unwrap();
+    store.delete(&1).unwrap();
+
+    let copy = RocksDBStore::new(&checkpoint_dir).unwrap();
+    for key in 0..=100 {
+        assert_eq!(copy.get(&key).unwrap(), Some(make_value(key, 1)));
     }
+    // The concurrent writes it holds are a gap-free prefix of the writer's
+    let appended: Vec<u64> = copy.keys().unwrap().into_iter().filter(|key| *key >= 1000).collect();
+    assert!(!appended.is_empty());
+    assert_eq!(appended, (1000..1000 + appended.len() as u64).collect::<Vec<_>>());
+    assert_eq!(copy.len().unwrap(), 101 + appended.len());
+    assert_eq!(store.get(&0).unwrap(), Some(make_value(0, 2)));
+
+    // Writes to the copy don't reach the original
+    copy.put(5, make_value(5, 3)).unwrap();
+    assert_eq!(store.get(&5).unwrap(), Some(make_value(5, 1)));
+    assert!(store.checkpoint(&checkpoint_dir).is_err());
 }
 
-impl Default for KVStore {
-    fn default() -> Self {
-        // Create a default RocksDB store in a temporary directory
-        let temp_dir = std::env::temp_dir().join("kvstore_default");
-        Self::new(temp_dir).expect("Failed to create default KVStore")
+#[test]
+fn test_delete_batch() {
+    let temp_dir = TempDir::new("kvstore_delete_batch_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    for key in 1..=4 {
+        store.put(key, test_value(key)).unwrap();
     }
+    store.put_with_ttl(5, test_value(5), Duration::from_millis(1)).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    // Duplicates count once, missing and expired keys not at all
+    assert_eq!(store.delete_batch(&[2, 9, 2, 3, 5]).unwrap(), 2);
+    assert_eq!(store.keys().unwrap(), vec![1, 4]);
+    assert_eq!(store.len().unwrap(), 2);
+    assert_eq!(store.delete_batch(&[2, 3]).unwrap(), 0);
+    assert_eq!(store.delete_batch(&[]).unwrap(), 0);
+    assert_eq!(store.len().unwrap(), 2);
 }
 
 #[test]
-fn test_kv_store_operations() {
-    use rand::Rng;
-    use sha2::{Digest};
-    use grpc_server::kvstore::DataType;
-    use std::collections::HashSet;
-    let temp_dir = std::env::temp_dir().join(format!("kvstore_test_{}", uuid::Uuid::new_v4()));
+fn test_str_keys() {
+    let temp_dir = TempDir::new("kvstore_str_keys_test");
+    let make_value = |n: u8| Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: 0,
+        data: vec![vec![n]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    {
+        let store = KVStore::new(&temp_dir).unwrap();
+        assert_eq!(store.put_str("resnet50", make_value(1)).unwrap(), None);
+        assert_eq!(store.put_str("resnet50", make_value(2)).unwrap(), Some(make_value(1)));
+        store.put_str("bert", make_value(3)).unwrap();
+        store.put_str([0xff, 0x00], make_value(4)).unwrap();
+
+        // An 8-byte string key doesn't alias the u64 with the same bytes
+        store.put_str(7u64.to_be_bytes(), make_value(5)).unwrap();
+        assert_eq!(store.get(&7).unwrap(), None);
+        store.put(7, make_value(6)).unwrap();
+        assert_eq!(store.get_str(7u64.to_be_bytes()).unwrap(), Some(make_value(5)));
+        assert_eq!(store.keys().unwrap(), vec![7]);
+        assert_eq!(store.len().unwrap(), 1);
+
+        assert_eq!(store.get_str("resnet50").unwrap(), Some(make_value(2)));
+        assert_eq!(store.get_str("gpt").unwrap(), None);
+        assert_eq!(store.delete_str("bert").unwrap(), Some(make_value(3)));
+        assert_eq!(store.delete_str("bert").unwrap(), None);
+    }
+
+    // String keys survive a reopen and enumerate in byte order
     let store = KVStore::new(&temp_dir).unwrap();
-    let mut rng = rand::thread_rng();
-    let mut keys_and_hashes = Vec::new();
-    let mut keys_so_far = HashSet::new();
-    for i in 0..100000 {
-        let mut key = rng.gen_range(0..1000000);
-        while keys_so_far.contains(&key) {
-            key = rng.gen_range(0..1000000);
-        }
-        keys_so_far.insert(key);
-        let shape = vec![rng.gen_range(1..10), rng.gen_range(1..10)];
-        let dtype = DataType::Fp64; 
-        let size_check = shape.iter().product::<u64>() * 8; // FP64 is 8 bytes
-        let key_check = key;
-        let data = vec![vec![rng.gen_range(0..1000000) as u8; 8]]; // Create proper data structure
-        let data_hash: [u8; 32] = sha2::Sha256::digest(&data[0]).into();
-       
-        
-        let value = Value {
-            shape,
-            dtype: dtype as i32,
-            size_check,
-            key_check,
-            data,
-        };
-        
-        store.put(key, value).unwrap();
-        keys_and_hashes.push((key, data_hash));
-        let data_hash_str = hex::encode(data_hash);
-        println!("iter: {}, key: {}, size_check: {}, key_check: {}, data_hash: {:?}", i, key, size_check, key_check, data_hash_str);
+    assert_eq!(store.keys_str().unwrap(), vec![7u64.to_be_bytes().to_vec(), b"resnet50".to_vec(), vec![0xff, 0x00]]);
+    assert_eq!(String::from_utf8(store.keys_str().unwrap()[1].clone()).unwrap(), "resnet50");
+}
+
+#[test]
+fn test_clear_in_batches() {
+    let temp_dir = TempDir::new("kvstore_clear_batches_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let value = Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: 0,
+        data: vec![vec![0]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    store.write_batch((0..2_500).map(|key| WriteOp::Put(key, value.clone())).collect()).unwrap();
+    for key in 0..150 {
+        store.put_with_ttl(key, value.clone(), Duration::from_secs(3600)).unwrap();
     }
-    for (key, data_hash) in keys_and_hashes {
-        let value = store.get(&key).unwrap();
-        assert!(value.is_some());
-        let value = value.unwrap();
-        let got_data_hash: [u8; 32] = sha2::Sha256::digest(&value.data[0]).into();
-        assert_eq!(data_hash, got_data_hash, "key: {}, data_hash: {:?}, got_data_hash: {:?}", key, data_hash, got_data_hash);
+    for key in 0..120 {
+        store.put_str(format!("str{}", key), value.clone()).unwrap();
     }
-    store.clear().unwrap();
+
+    // 2 batches of expiries, 2 of string keys, then 25 of keys
+    assert_eq!(store.store.clear_in_batches(100).unwrap(), 29);
     assert!(store.is_empty().unwrap());
+    assert!(store.keys().unwrap().is_empty());
+    assert!(store.keys_str().unwrap().is_empty());
+    assert_eq!(store.get_str("str0").unwrap(), None);
+    assert_eq!(store.store.clear_in_batches(100).unwrap(), 0);
+
+    // Nothing expires after a clear, even keys written again
+    store.put(5, value.clone()).unwrap();
+    assert_eq!(store.sweep_expired().unwrap(), 0);
+    store.clear().unwrap();
+    assert_eq!(store.len().unwrap(), 0);
+}
+
+#[test]
+fn test_value_compression() {
+    use sha2::Digest;
+
+    let temp_dir = TempDir::new("kvstore_value_compression_test");
+    let make_value = |key: u64, data: Vec<u8>| Value {
+        shape: vec![data.len() as u64],
+        dtype: DataType::Int8 as i32,
+        size_check: data.len() as u64,
+        key_check: key,
+        data: vec![data],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    let zeros = |key: u64| make_value(key, vec![0; 64 * 1024]);
+    // SHA-256 output doesn't compress
+    let noise = |key: u64| make_value(key, (0..128u32).flat_map(|i| sha2::Sha256::digest(i.to_le_bytes())).collect());
+    // Flags byte of the stored frame
+    let stored_flags = |store: &RocksDBStore, key: u64| store.db.get(key.to_be_bytes()).unwrap().unwrap()[1];
+
+    {
+        let store = RocksDBStore::new(&temp_dir).unwrap();
+        store.put(1, zeros(1)).unwrap();
+        assert_eq!(stored_flags(&store, 1), 0);
+    }
+    {
+        let store = RocksDBStore::builder().value_zstd_level(3).open(&temp_dir).unwrap();
+        store.put(2, zeros(2)).unwrap();
+        // Noise doesn't shrink, so it's stored as-is
+        store.put(3, noise(3)).unwrap();
+        assert_eq!(stored_flags(&store, 2), 1);
+        assert!(store.db.get(2u64.to_be_bytes()).unwrap().unwrap().len() < 1024);
+        assert_eq!(stored_flags(&store, 3), 0);
+
+        // Old and new entries read alike, one by one and in batches
+        assert_eq!(store.get(&1).unwrap(), Some(zeros(1)));
+        assert_eq!(store.get(&2).unwrap(), Some(zeros(2)));
+        assert_eq!(store.multi_get(&[1, 2, 3]).unwrap(), vec![Some(zeros(1)), Some(zeros(2)), Some(noise(3))]);
+        assert!(store.compare_and_swap(2, Some(zeros(2)), zeros(2)).unwrap());
+    }
+
+    // Compressed entries stay readable with compression off again
+    let store = RocksDBStore::new(&temp_dir).unwrap();
+    assert_eq!(store.get(&2).unwrap(), Some(zeros(2)));
+    assert_eq!(store.get(&3).unwrap(), Some(noise(3)));
+    assert_eq!(store.len().unwrap(), 3);
+}
+
+#[test]
+fn test_value_metadata() {
+    let temp_dir = TempDir::new("kvstore_metadata_test");
+    let store = KVStore::new(&temp_dir).unwrap();
+    let tagged = |tags: &[(&str, &str)]| Value {
+        shape: vec![1024],
+        dtype: DataType::Int8 as i32,
+        size_check: 1024,
+        key_check: 1,
+        data: vec![vec![7; 1024]],
+        descriptor: Some("weights".to_string()),
+        metadata: tags.iter().map(|(name, tag)| (name.to_string(), tag.to_string())).collect(),
+    };
+    let value = tagged(&[("owner", "alice"), ("created_at", "2024-01-01")]);
+    store.put(1, value.clone()).unwrap();
+    assert_eq!(store.get(&1).unwrap(), Some(value.clone()));
+    assert_eq!(store.get_metadata(&1).unwrap(), Some(value.metadata.clone()));
+    assert_eq!(store.get_metadata(&2).unwrap(), None);
+
+    // Everything but the data
+    let header = store.get_without_data(&1).unwrap().unwrap();
+    assert!(header.data.is_empty());
+    assert_eq!(header, Value { data: Vec::new(), ..value.clone() });
+    let meta = store.get_meta(&1).unwrap().unwrap();
+    assert_eq!((meta.shape, meta.dtype, meta.size_check, meta.key_check), (vec![1024], DataType::Int8 as i32, 1024, 1));
+    assert_eq!(meta.metadata, value.metadata);
+    assert_eq!(store.get_meta(&2).unwrap(), None);
+
+    // The same tags added in another order encode alike, so they compare equal
+    let reordered = tagged(&[("created_at", "2024-01-01"), ("owner", "alice")]);
+    assert!(store.compare_and_swap(1, Some(reordered), tagged(&[])).unwrap());
+    assert_eq!(store.get_metadata(&1).unwrap(), Some(BTreeMap::new()));
+
+    let oversized = tagged(&[("notes", &"x".repeat(MAX_METADATA_LEN))]);
+    let err = store.put(3, oversized).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StoreError>(), Some(StoreError::InvalidArgument(_))));
 }
 
+#[test]
+fn test_value_helpers() {
+    let value = Value {
+        shape: vec![2, 2],
+        dtype: DataType::Int8 as i32,
+        size_check: 4,
+        key_check: 1,
+        data: vec![vec![1, 2, 3, 4]],
+        descriptor: None,
+        metadata: [("owner".to_string(), "alice".to_string())].into(),
+    };
+    assert_eq!(value.content_hash(), value.clone().content_hash());
+
+    // Any field changes the hash, not just the data
+    let mut other = value.clone();
+    other.metadata.insert("team".to_string(), "ml".to_string());
+    assert_ne!(other.content_hash(), value.content_hash());
+    other = Value { data: vec![vec![4, 3, 2, 1]], ..value.clone() };
+    assert_ne!(other.content_hash(), value.content_hash());
+
+    assert!(value.shape_matches(&other));
+    other.dtype = DataType::Bool as i32;
+    assert!(value.shape_matches(&other));
+    other.shape = vec![4];
+    assert!(!value.shape_matches(&other));
+}
+
+#[test]
+fn test_remove() {
+    let temp_dir = TempDir::new("kvstore_remove_test");
+    let store = KVStore::with_namespaces(&temp_dir, &["other"]).unwrap();
+    let value = Value {
+        shape: vec![1],
+        dtype: DataType::Int8 as i32,
+        size_check: 1,
+        key_check: 0,
+        data: vec![vec![0]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    store.put(1, value.clone()).unwrap();
+    store.put_with_ttl(2, value.clone(), Duration::from_millis(1)).unwrap();
+    store.put_cf("other", 1, value.clone()).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    assert!(store.remove(&1).unwrap());
+    assert!(!store.remove(&1).unwrap());
+    // An expired value is gone already, but its entry still gets cleaned up
+    assert!(!store.remove(&2).unwrap());
+    assert_eq!(store.len().unwrap(), 0);
+    assert!(store.remove_cf("other", &1).unwrap());
+    assert!(!store.remove_cf("other", &1).unwrap());
+    assert!(store.remove_cf("missing", &1).is_err());
+}
@@ -1,32 +1,151 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use rust_kv_store::config::ServerConfig;
+use rust_kv_store::grpc_server::{self, BearerAuth, KvStoreGrpcService};
+use rust_kv_store::metrics::{self, Metrics};
+use rust_kv_store::rate_limit::{self, RateLimit};
+use rust_kv_store::request_log;
 use rust_kv_store::KVStore;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::codegen::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Server, ServerTlsConfig};
 use tracing::info;
 
+fn run_diff(args: &[String]) -> Result<()> {
+    let (path_a, path_b) = match args {
+        [a, b] => (a, b),
+        _ => anyhow::bail!("Usage: rust-kv-store diff <store_path_a> <store_path_b>"),
+    };
+    for path in [path_a, path_b] {
+        if !std::path::Path::new(path).exists() {
+            anyhow::bail!("Store path '{}' does not exist", path);
+        }
+    }
+
+    let store_a = KVStore::new(path_a)?;
+    let store_b = KVStore::new(path_b)?;
+    let report = store_a.diff(&store_b)?;
+
+    println!("identical:          {}", report.identical);
+    println!("different:          {} {:?}", report.different, report.sample_different);
+    println!("only in {}: {} {:?}", path_a, report.only_in_self, report.sample_only_in_self);
+    println!("only in {}: {} {:?}", path_b, report.only_in_other, report.sample_only_in_other);
+
+    if !report.is_identical() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize tracing
     tracing_subscriber::fmt::init();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("diff") {
+        return run_diff(&args[2..]);
+    }
     
     info!("Starting Rust KV Store server...");
     
-    let prefix = std::env::var("DATA_DIR_PREFIX").unwrap_or_else(|_| "./data".to_string());
+    let config = ServerConfig::load()?;
 
-    // Create a new unique data directory under prefix
-    let data_dir = format!("{}/{}", prefix, uuid::Uuid::new_v4());
+    // Create a new unique data directory under the prefix
+    let data_dir = config.data_dir_prefix.join(uuid::Uuid::new_v4().to_string());
     std::fs::create_dir_all(&data_dir)?;
 
     // Create the KV store (RocksDB)
-    let _store = Arc::new(KVStore::new(&data_dir)?);
-    
-    // For now, just print that the store is created
-    // TODO: Add HTTP server implementation
-    info!("KV Store created successfully at {}", data_dir);
-    info!("Store is ready for use");
-    
-    // Keep the process running
-    tokio::signal::ctrl_c().await?;
+    let store = Arc::new(KVStore::from(config.store_builder()?.open(&data_dir)?));
+    info!("KV Store created successfully at {}", data_dir.display());
+
+    // gRPC and Prometheus metrics over HTTP, each on its own port
+    let metrics = Arc::new(Metrics::new());
+    // Both servers stop accepting connections once this fires, then finish
+    // the requests already in flight
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+    let shutdown_signal = move || {
+        let mut shutdown_rx = shutdown_rx.clone();
+        async move {
+            let _ = shutdown_rx.changed().await;
+        }
+    };
+    let mut servers = tokio::task::JoinSet::new();
+    if let Some(addr) = config.grpc_addr()? {
+        let listener = TcpListener::bind(addr).await.with_context(|| format!("Failed to bind gRPC address {}", addr))?;
+        info!("gRPC server listening on {}", listener.local_addr()?);
+        let service = KvStoreGrpcService::new(store.clone())
+            .metrics(metrics.clone())
+            .log_requests(config.log_requests)
+            .into_server();
+        // Lets grpcurl and similar tools discover the API
+        let reflection = if config.reflection { Some(grpc_server::reflection_service()?) } else { None };
+        let mut builder = Server::builder();
+        if let Some((cert, key)) = config.tls_paths()? {
+            let identity = grpc_server::load_identity(cert, key).context("Failed to load TLS certificate")?;
+            builder = builder.tls_config(ServerTlsConfig::new().identity(identity))?;
+        }
+        // Over the limit requests are turned away before their token is checked
+        let mut limit = config.rate_limit.map(RateLimit::new);
+        let mut auth = config.auth_token.as_deref().map(BearerAuth::new);
+        #[allow(clippy::result_large_err)] // tonic interceptors must return Status
+        let router = builder.add_service(InterceptedService::new(service, move |request| {
+            let request = match limit.as_mut() {
+                Some(limit) => limit.call(request)?,
+                None => request,
+            };
+            match auth.as_mut() {
+                Some(auth) => auth.call(request),
+                None => Ok(request),
+            }
+        }));
+        let shutdown = shutdown_signal();
+        servers.spawn(async move {
+            router
+                .add_optional_service(reflection)
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+                .await
+                .context("gRPC server failed")
+        });
+    }
+    if let Some(addr) = config.http_addr()? {
+        let listener = TcpListener::bind(addr).await.with_context(|| format!("Failed to bind HTTP address {}", addr))?;
+        info!("HTTP server listening on {} (GET /metrics, /stats)", listener.local_addr()?);
+        let mut router = metrics::router(metrics.clone(), store.clone());
+        if let Some(per_second) = config.rate_limit {
+            router = router.layer(axum::middleware::from_fn_with_state(RateLimit::new(per_second), rate_limit::limit_http_requests));
+        }
+        if config.log_requests {
+            router = router.layer(axum::middleware::from_fn(request_log::log_http_request));
+        }
+        let shutdown = shutdown_signal();
+        servers.spawn(async move {
+            axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown)
+                .await
+                .context("HTTP server failed")
+        });
+    }
+    if servers.is_empty() {
+        anyhow::bail!("The gRPC and HTTP addresses are both empty, nothing to serve");
+    }
+
+    // Run until Ctrl-C or until a server stops on its own
+    let stopped = tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map_err(anyhow::Error::from),
+        Some(result) = servers.join_next() => result.map_err(anyhow::Error::from).and_then(|result| result),
+    };
     info!("Shutting down...");
-    
-    Ok(())
+    let _ = shutdown_tx.send(());
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
+
+    // Every request has finished, so the flush covers all acknowledged writes
+    store.flush()?;
+    info!("Store flushed to {}", data_dir.display());
+    stopped
 } 
\ No newline at end of file
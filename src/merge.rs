@@ -0,0 +1,102 @@
+use anyhow::Result;
+use rocksdb::MergeOperands;
+
+use crate::codec;
+use crate::grpc_server::kvstore::{DataType, Value};
+use crate::StoreError;
+
+pub(crate) const TENSOR_ADD_MERGE: &str = "tensor_add";
+
+// Element size in bytes of the dtypes that can be summed; the sub-byte and
+// 16-bit float formats have no native Rust type to add them with
+fn summable_size(dtype: DataType) -> Option<usize> {
+    match dtype {
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64
+        | DataType::Fp32 | DataType::Fp64 => Some(dtype.byte_size()),
+        _ => None,
+    }
+}
+
+// Checks that `delta` can be added element-wise to `base`
+pub(crate) fn check_addable(base: &Value, delta: &Value) -> Result<()> {
+    let dtype = DataType::try_from(delta.dtype)
+        .map_err(|_| StoreError::InvalidArgument(format!("unknown dtype {}", delta.dtype)))?;
+    if summable_size(dtype).is_none() {
+        return Err(StoreError::InvalidArgument(format!("cannot add values of dtype {:?}", dtype)).into());
+    }
+    if base.dtype != delta.dtype {
+        return Err(StoreError::InvalidArgument(format!(
+            "dtype mismatch: stored {:?}, delta {:?}", base.dtype(), dtype
+        )).into());
+    }
+    if base.shape != delta.shape {
+        return Err(StoreError::InvalidArgument(format!(
+            "shape mismatch: stored {:?}, delta {:?}", base.shape, delta.shape
+        )).into());
+    }
+    let expected = delta.shape.iter()
+        .try_fold(1u64, |acc, &dim| acc.checked_mul(dim))
+        .and_then(|elements| dtype.payload_size(elements))
+        .ok_or_else(|| StoreError::InvalidArgument(format!("shape {:?} is too large", delta.shape)))?;
+    for (name, value) in [("stored", base), ("delta", delta)] {
+        let len: u64 = value.data.iter().map(|d| d.len() as u64).sum();
+        if len != expected {
+            return Err(StoreError::InvalidArgument(format!(
+                "{} value has {} data bytes, shape {:?} needs {}", name, len, value.shape, expected
+            )).into());
+        }
+    }
+    Ok(())
+}
+
+// Element-wise sum of two values that passed `check_addable`. The result
+// keeps `base`'s metadata and holds its data in a single chunk. Floats add
+// as IEEE floats, integers wrap on overflow.
+pub(crate) fn add_values(base: &Value, delta: &Value) -> Result<Value> {
+    check_addable(base, delta)?;
+    let a = base.data.concat();
+    let b = delta.data.concat();
+
+    macro_rules! sum {
+        ($ty:ty, $add:expr) => {{
+            const N: usize = std::mem::size_of::<$ty>();
+            a.chunks_exact(N)
+                .zip(b.chunks_exact(N))
+                .flat_map(|(x, y)| {
+                    let x = <$ty>::from_le_bytes(x.try_into().unwrap());
+                    let y = <$ty>::from_le_bytes(y.try_into().unwrap());
+                    $add(x, y).to_le_bytes()
+                })
+                .collect::<Vec<u8>>()
+        }};
+    }
+
+    let data = match base.dtype() {
+        DataType::Int8 => sum!(i8, i8::wrapping_add),
+        DataType::Int16 => sum!(i16, i16::wrapping_add),
+        DataType::Int32 => sum!(i32, i32::wrapping_add),
+        DataType::Int64 => sum!(i64, i64::wrapping_add),
+        DataType::Fp32 => sum!(f32, |x: f32, y: f32| x + y),
+        DataType::Fp64 => sum!(f64, |x: f64, y: f64| x + y),
+        _ => unreachable!("check_addable rejects other dtypes"),
+    };
+    Ok(Value { data: vec![data], ..base.clone() })
+}
+
+// Associative merge operator folding every operand into the existing value
+// (or into the first operand when RocksDB merges operands on their own).
+// Returning None fails the merge, which RocksDB reports as an error on the
+// read or compaction that triggered it rather than storing a corrupt value.
+pub(crate) fn tensor_add_merge(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    let mut operands = operands.iter();
+    let first = match existing {
+        Some(bytes) => bytes,
+        None => operands.next()?,
+    };
+    let mut acc = codec::decode_value(first).ok()?;
+    for operand in operands {
+        let delta = codec::decode_value(operand).ok()?;
+        acc = add_values(&acc, &delta).ok()?;
+    }
+    Some(codec::encode_value(&acc))
+}
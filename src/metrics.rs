@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use prometheus::{Encoder, Gauge, HistogramTimer, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use serde::Serialize;
+use tower_http::compression::CompressionLayer;
+
+use crate::{ErrorBody, KVStore};
+
+// Prometheus metrics for a served store. One instance is shared by the gRPC
+// service and the `/metrics` route; the gauges are read from the store when
+// scraped rather than kept up to date on every write.
+pub struct Metrics {
+    registry: Registry,
+    operations: IntCounterVec,
+    latency: HistogramVec,
+    entries: IntGauge,
+    db_size: IntGauge,
+    // From RocksDB's statistics, left at 0 unless the store collects them
+    block_cache_hit_ratio: Gauge,
+    compaction_read_bytes: IntGauge,
+    compaction_write_bytes: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let operations = IntCounterVec::new(
+            Opts::new("kvstore_operations_total", "Keys written, read or deleted, by operation"),
+            &["op"],
+        ).expect("valid metric");
+        let latency = HistogramVec::new(
+            HistogramOpts::new("kvstore_request_duration_seconds", "Time spent handling a request, by method"),
+            &["method"],
+        ).expect("valid metric");
+        let entries = IntGauge::new("kvstore_entries", "Entries in the default store").expect("valid metric");
+        let db_size = IntGauge::new("kvstore_db_size_bytes", "Storage used by the default store").expect("valid metric");
+        let block_cache_hit_ratio = Gauge::new("kvstore_rocksdb_block_cache_hit_ratio", "Share of RocksDB block cache lookups that hit").expect("valid metric");
+        let compaction_read_bytes = IntGauge::new("kvstore_rocksdb_compaction_read_bytes", "Bytes read by RocksDB compactions since the store was opened").expect("valid metric");
+        let compaction_write_bytes = IntGauge::new("kvstore_rocksdb_compaction_write_bytes", "Bytes written by RocksDB compactions since the store was opened").expect("valid metric");
+
+        // Names are fixed and distinct, so registering them can't fail
+        let registry = Registry::new();
+        registry.register(Box::new(operations.clone())).expect("metric registered once");
+        registry.register(Box::new(latency.clone())).expect("metric registered once");
+        registry.register(Box::new(entries.clone())).expect("metric registered once");
+        registry.register(Box::new(db_size.clone())).expect("metric registered once");
+        registry.register(Box::new(block_cache_hit_ratio.clone())).expect("metric registered once");
+        registry.register(Box::new(compaction_read_bytes.clone())).expect("metric registered once");
+        registry.register(Box::new(compaction_write_bytes.clone())).expect("metric registered once");
+
+        Self {
+            registry,
+            operations,
+            latency,
+            entries,
+            db_size,
+            block_cache_hit_ratio,
+            compaction_read_bytes,
+            compaction_write_bytes,
+        }
+    }
+
+    // The registry, for adding application metrics next to the store's
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    // Counts `keys` keys handled by `op` ("put", "get" or "delete")
+    pub(crate) fn record(&self, op: &str, keys: u64) {
+        self.operations.with_label_values(&[op]).inc_by(keys);
+    }
+
+    // Observes the time until the returned timer is dropped
+    pub(crate) fn time(&self, method: &str) -> HistogramTimer {
+        self.latency.with_label_values(&[method]).start_timer()
+    }
+
+    // Refreshes the gauges from `store` and renders every metric in the
+    // Prometheus text format
+    pub fn render(&self, store: &KVStore) -> Result<String> {
+        self.entries.set(store.len()? as i64);
+        self.db_size.set(store.get_db_size()? as i64);
+        if store.statistics_enabled() {
+            self.block_cache_hit_ratio.set(store.block_cache_hit_rate()?.unwrap_or(0.0));
+            let (read, written) = store.compaction_bytes()?;
+            self.compaction_read_bytes.set(read as i64);
+            self.compaction_write_bytes.set(written as i64);
+        }
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Snapshot of the default store served as JSON by `GET /stats`. Every
+// figure comes from RocksDB's counters except `len`, which is the store's
+// own entry count; none of them needs a scan.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub data_dir: String,
+    pub len: usize,
+    pub db_size_bytes: u64,
+    pub sst_files_size_bytes: u64,
+    pub compaction: CompactionStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompactionStats {
+    pub running: u64,
+    pub pending: bool,
+    // Only known when the store collects statistics, null otherwise
+    pub read_bytes: Option<u64>,
+    pub write_bytes: Option<u64>,
+}
+
+impl Stats {
+    pub fn collect(store: &KVStore) -> Result<Self> {
+        let (read_bytes, write_bytes) = if store.statistics_enabled() {
+            let (read, written) = store.compaction_bytes()?;
+            (Some(read), Some(written))
+        } else {
+            (None, None)
+        };
+        Ok(Self {
+            data_dir: store.path().display().to_string(),
+            len: store.len()?,
+            db_size_bytes: store.get_db_size()?,
+            sst_files_size_bytes: store.sst_files_size()?,
+            compaction: CompactionStats {
+                running: store.running_compactions()?,
+                pending: store.compaction_pending()?,
+                read_bytes,
+                write_bytes,
+            },
+        })
+    }
+}
+
+#[derive(Clone)]
+struct MetricsState {
+    metrics: Arc<Metrics>,
+    store: Arc<KVStore>,
+}
+
+// HTTP router serving `GET /metrics` for Prometheus to scrape and
+// `GET /stats`, a JSON `Stats` for people. Responses are gzip or brotli
+// compressed for clients that send Accept-Encoding, as Prometheus does.
+pub fn router(metrics: Arc<Metrics>, store: Arc<KVStore>) -> Router {
+    Router::new()
+        .route("/metrics", get(serve_metrics))
+        .route("/stats", get(serve_stats))
+        .layer(CompressionLayer::new())
+        .with_state(MetricsState { metrics, store })
+}
+
+async fn serve_metrics(State(state): State<MetricsState>) -> Response {
+    match state.metrics.render(&state.store) {
+        Ok(body) => ([(header::CONTENT_TYPE, TextEncoder::new().format_type().to_string())], body).into_response(),
+        Err(e) => ErrorBody::from(e).into_response(),
+    }
+}
+
+async fn serve_stats(State(state): State<MetricsState>) -> Response {
+    match Stats::collect(&state.store) {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => ErrorBody::from(e).into_response(),
+    }
+}
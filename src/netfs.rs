@@ -0,0 +1,117 @@
+use std::path::Path;
+use anyhow::Result;
+use tracing::warn;
+
+// RocksDB relies on POSIX file locking and fsync semantics that network
+// filesystems (NFS, SMB, ...) don't reliably provide, which can silently
+// corrupt the database. This decides what to do when the data directory
+// turns out to live on one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NetworkFsPolicy {
+    // Refuse to open the store (default)
+    #[default]
+    Refuse,
+    // Log a warning and open the store anyway
+    Warn,
+    // Skip the check entirely, for users who know their filesystem is safe
+    Allow,
+}
+
+// statfs(2) magic numbers of the network filesystems we know about
+#[cfg(target_os = "linux")]
+const NETWORK_FS_MAGICS: &[(u32, &str)] = &[
+    (0x6969, "nfs"),
+    (0x517B, "smb"),
+    (0xFE534D42, "smb2"),
+    (0xFF534D42, "cifs"),
+    (0x73757245, "coda"),
+    (0x5346414F, "afs"),
+    (0x00C36400, "ceph"),
+    (0x01021997, "9p"),
+    (0x0BD00BD0, "lustre"),
+];
+
+#[cfg(target_os = "linux")]
+fn network_fs_name(magic: u32) -> Option<&'static str> {
+    NETWORK_FS_MAGICS
+        .iter()
+        .find(|(m, _)| *m == magic)
+        .map(|(_, name)| *name)
+}
+
+// Returns the name of the network filesystem `path` lives on, if any. The
+// data directory may not exist yet, so the nearest existing ancestor is used.
+#[cfg(target_os = "linux")]
+pub fn detect_network_fs(path: &Path) -> Result<Option<&'static str>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let absolute = std::path::absolute(path)?;
+    let existing = absolute
+        .ancestors()
+        .find(|p| p.exists())
+        .ok_or_else(|| anyhow::anyhow!("No existing ancestor for '{}'", path.display()))?;
+
+    let c_path = CString::new(existing.as_os_str().as_bytes())?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(network_fs_name(stat.f_type as u32))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn detect_network_fs(_path: &Path) -> Result<Option<&'static str>> {
+    Ok(None)
+}
+
+// Applies `policy` to the filesystem type detected for `path`
+pub(crate) fn enforce_policy(path: &Path, fs_type: Option<&str>, policy: NetworkFsPolicy) -> Result<()> {
+    let fs_type = match fs_type {
+        Some(fs_type) => fs_type,
+        None => return Ok(()),
+    };
+    match policy {
+        NetworkFsPolicy::Refuse => anyhow::bail!(
+            "Refusing to open store at '{}': it is on a network filesystem ({}), which RocksDB does not support safely. \
+             Use NetworkFsPolicy::Allow to override.",
+            path.display(),
+            fs_type
+        ),
+        NetworkFsPolicy::Warn => {
+            warn!(
+                "Store at '{}' is on a network filesystem ({}); RocksDB may corrupt data on it",
+                path.display(),
+                fs_type
+            );
+            Ok(())
+        }
+        NetworkFsPolicy::Allow => Ok(()),
+    }
+}
+
+pub(crate) fn check(path: &Path, policy: NetworkFsPolicy) -> Result<()> {
+    if policy == NetworkFsPolicy::Allow {
+        return Ok(());
+    }
+    enforce_policy(path, detect_network_fs(path)?, policy)
+}
+
+#[test]
+fn test_network_fs_policy() {
+    let path = Path::new("/mnt/shared/kvstore");
+    assert!(enforce_policy(path, Some("nfs"), NetworkFsPolicy::Refuse).is_err());
+    assert!(enforce_policy(path, Some("nfs"), NetworkFsPolicy::Warn).is_ok());
+    assert!(enforce_policy(path, Some("nfs"), NetworkFsPolicy::Allow).is_ok());
+    assert!(enforce_policy(path, None, NetworkFsPolicy::Refuse).is_ok());
+
+    #[cfg(target_os = "linux")]
+    {
+        assert_eq!(network_fs_name(0x6969), Some("nfs"));
+        assert_eq!(network_fs_name(0xEF53), None); // ext4
+    }
+
+    // The temp dir used by the tests is local, and a missing leaf is fine
+    let missing = std::env::temp_dir().join(format!("kvstore_netfs_{}", uuid::Uuid::new_v4()));
+    assert!(check(&missing, NetworkFsPolicy::Refuse).is_ok());
+}
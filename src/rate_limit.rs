@@ -0,0 +1,97 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tonic::service::Interceptor;
+use tonic::Status;
+
+use crate::ErrorBody;
+
+// Token bucket shared by every connection to a server: it admits up to
+// `per_second` requests a second on average, and bursts of as many at once
+// after a quiet second. Clones share the bucket.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    per_second: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimit {
+    pub fn new(per_second: u32) -> Self {
+        let per_second = f64::from(per_second);
+        Self {
+            per_second,
+            bucket: Arc::new(Mutex::new(Bucket { tokens: per_second, refilled_at: Instant::now() })),
+        }
+    }
+
+    // Takes a token if there is one; false means the request should be
+    // turned away
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    // `try_acquire` as of `now`, which mustn't be earlier than the last call
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.per_second);
+        bucket.refilled_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+impl Interceptor for RateLimit {
+    fn call(&mut self, request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        if !self.try_acquire() {
+            return Err(Status::resource_exhausted("Rate limit exceeded"));
+        }
+        Ok(request)
+    }
+}
+
+// axum middleware answering 429 Too Many Requests once `limit` runs dry;
+// install with `axum::middleware::from_fn_with_state`
+pub async fn limit_http_requests(State(limit): State<RateLimit>, request: Request, next: Next) -> Response {
+    if !limit.try_acquire() {
+        return ErrorBody::new(StatusCode::TOO_MANY_REQUESTS, "resource_exhausted", "Rate limit exceeded").into_response();
+    }
+    next.run(request).await
+}
+
+#[test]
+fn test_rate_limit() {
+    use std::time::Duration;
+
+    let limit = RateLimit::new(4);
+    let start = limit.bucket.lock().unwrap().refilled_at;
+
+    // A full second's worth of requests passes at once, the next doesn't
+    assert!((0..4).all(|_| limit.try_acquire_at(start)));
+    assert!(!limit.try_acquire_at(start));
+    // Clones draw from the same bucket
+    assert!(!limit.clone().try_acquire_at(start));
+
+    // Tokens come back at the configured rate: a quarter second buys one
+    let later = start + Duration::from_millis(250);
+    assert!(limit.try_acquire_at(later));
+    assert!(!limit.try_acquire_at(later));
+
+    // A long quiet spell refills the bucket but doesn't overfill it
+    let much_later = later + Duration::from_secs(60);
+    assert!((0..4).all(|_| limit.try_acquire_at(much_later)));
+    assert!(!limit.try_acquire_at(much_later));
+}
@@ -0,0 +1,51 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use axum::extract::{ConnectInfo, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::{debug, field, info, info_span, Span};
+
+// Audit log of one gRPC request: a span describing it, opened when the
+// handler starts and closed with the handler's latency when dropped
+pub(crate) struct RequestLog {
+    span: Span,
+    started: Instant,
+}
+
+impl RequestLog {
+    pub(crate) fn start(method: &'static str, key: Option<u64>, payload_bytes: Option<usize>, peer: Option<SocketAddr>) -> Self {
+        let span = info_span!("grpc_request", method, key, payload_bytes, peer = peer.map(field::display));
+        debug!(parent: &span, "request started");
+        Self { span, started: Instant::now() }
+    }
+}
+
+impl Drop for RequestLog {
+    fn drop(&mut self) {
+        info!(parent: &self.span, latency_us = self.started.elapsed().as_micros() as u64, "request handled");
+    }
+}
+
+// axum middleware logging HTTP requests like RequestLog does gRPC ones. The
+// peer address is only known when the router is served with
+// `into_make_service_with_connect_info::<SocketAddr>()`.
+pub async fn log_http_request(request: Request, next: Next) -> Response {
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr);
+    let span = info_span!(
+        "http_request",
+        method = %request.method(),
+        path = request.uri().path(),
+        peer = peer.map(field::display),
+    );
+    debug!(parent: &span, "request started");
+    let started = Instant::now();
+    let response = next.run(request).await;
+    info!(
+        parent: &span,
+        status = response.status().as_u16(),
+        latency_us = started.elapsed().as_micros() as u64,
+        "request handled",
+    );
+    response
+}
@@ -0,0 +1,18 @@
+use prost::Message;
+use sha2::{Digest, Sha256};
+
+use crate::grpc_server::kvstore::Value;
+
+impl Value {
+    // SHA-256 of the protobuf encoding, so it covers the shape, dtype,
+    // checks, descriptor and metadata as well as the data. The encoding is
+    // deterministic (metadata is a BTreeMap), so equal values hash alike.
+    pub fn content_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.encode_to_vec()).into()
+    }
+
+    // Whether both values have the same shape, whatever their dtypes
+    pub fn shape_matches(&self, other: &Value) -> bool {
+        self.shape == other.shape
+    }
+}
@@ -0,0 +1,50 @@
+// Helpers shared by the integration tests and, through a #[path] module, the
+// unit tests in src/lib.rs. Types are reached through `super` so the same
+// file compiles on both sides of the crate boundary.
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use super::{DataType, Value};
+
+// A one-element INT64 value holding `key`, valid to store under `key`
+pub fn test_value(key: u64) -> Value {
+    Value {
+        shape: vec![1],
+        dtype: DataType::Int64 as i32,
+        size_check: 8,
+        key_check: key,
+        data: vec![key.to_le_bytes().to_vec()],
+        descriptor: None,
+        metadata: Default::default(),
+    }
+}
+
+// A fresh path under the system temp dir, removed with everything in it when
+// dropped. Nothing is created up front; opening a store there creates it.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(prefix: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("{}_{}", prefix, uuid::Uuid::new_v4())))
+    }
+}
+
+impl Deref for TempDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
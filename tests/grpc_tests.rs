@@ -2,35 +2,59 @@ use rust_kv_store::{KVStore, grpc_server, grpc_client};
 use std::sync::Arc;
 use rand::Rng;
 use sha2::Digest;
-use grpc_server::kvstore::DataType;
+use grpc_server::kvstore::{AggKind, DataType, Value};
 use std::collections::HashSet;
 use tonic::transport::Server;
 use std::net::SocketAddr;
-use std::str::FromStr;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::TcpListenerStream;
+
+mod common;
+use common::{test_value, TempDir};
+
+type ServerHandle = JoinHandle<Result<(), tonic::transport::Error>>;
+
+// Binds a free port on [::1]. Connections queue up until a server starts
+// accepting them, so clients can connect without waiting for it.
+async fn bind() -> (SocketAddr, TcpListenerStream) {
+    let listener = tokio::net::TcpListener::bind("[::1]:0").await.unwrap();
+    (listener.local_addr().unwrap(), TcpListenerStream::new(listener))
+}
+
+// Serves `service` on a free port and returns the URL to connect to
+async fn serve<S>(service: S) -> (String, ServerHandle)
+where
+    S: tower::Service<
+            tonic::codegen::http::Request<tonic::transport::Body>,
+            Response = tonic::codegen::http::Response<tonic::body::BoxBody>,
+            Error = std::convert::Infallible,
+        > + tonic::server::NamedService
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let (addr, incoming) = bind().await;
+    let handle = tokio::spawn(Server::builder().add_service(service).serve_with_incoming(incoming));
+    (format!("http://{}", addr), handle)
+}
+
+// Serves `store` with the default service settings; see `serve`
+async fn start_server(store: Arc<KVStore>) -> (String, ServerHandle) {
+    serve(grpc_server::create_grpc_server(store)).await
+}
 
 #[tokio::test]
 async fn test_grpc_operations() {
     // Create a temporary store
-    let temp_dir = std::env::temp_dir().join(format!("kvstore_grpc_test_{}", uuid::Uuid::new_v4()));
+    let temp_dir = TempDir::new("kvstore_grpc_test");
     let store = Arc::new(KVStore::new(&temp_dir).unwrap());
     
     // Create gRPC server
-    let grpc_service = grpc_server::create_grpc_server(store.clone());
-    let addr = SocketAddr::from_str("[::1]:50051").unwrap();
-    
-    // Start server in background
-    let server_handle = tokio::spawn(async move {
-        Server::builder()
-            .add_service(grpc_service)
-            .serve(addr)
-            .await
-    });
-    
-    // Wait a bit for server to start
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let (addr, server_handle) = start_server(store.clone()).await;
     
     // Create client
-    let mut client = grpc_client::KvStoreClient::connect("http://[::1]:50051".to_string()).await.unwrap();
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
     
     // Test health check
     let health_status = client.health().await.unwrap();
@@ -62,6 +86,8 @@ async fn test_grpc_operations() {
             size_check,
             key_check,
             data: data.clone(),
+            descriptor: None,
+            metadata: Default::default(),
         };
         
         // Test PUT
@@ -78,6 +104,48 @@ async fn test_grpc_operations() {
         assert!(keys.contains(key));
     }
     
+    // Test COUNT
+    assert_eq!(client.count().await.unwrap(), 10);
+    
+    // Test STATS from the health probe
+    let stats = client.stats().await.unwrap();
+    assert!(stats.healthy && stats.writable);
+    assert_eq!(stats.entries, 10);
+    assert!(stats.db_size_bytes > 0);
+    
+    // Test EXISTS
+    let missing = (0..).find(|k| !keys.contains(k)).unwrap();
+    assert!(client.exists(keys[0]).await.unwrap());
+    assert!(!client.exists(missing).await.unwrap());
+    assert_eq!(client.exists_batch(vec![keys[1], missing, keys[2]]).await.unwrap(), vec![true, false, true]);
+    assert!(client.exists_batch(vec![]).await.unwrap().is_empty());
+    
+    // Test PUT validation of the integrity fields
+    let (key, _, data) = &keys_and_hashes[0];
+    let mismatched = grpc_server::kvstore::Value {
+        shape: vec![2, 2],
+        dtype: DataType::Fp64 as i32,
+        size_check: 16,
+        key_check: *key,
+        data: data.clone(),
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    assert_eq!(client.put(*key, mismatched.clone()).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+    let wrong_key = grpc_server::kvstore::Value { size_check: 32, key_check: key + 1, ..mismatched };
+    assert_eq!(client.put(*key, wrong_key).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+    
+    // Test AGGREGATE over the whole key space
+    let aggregate = client.aggregate(None, None, AggKind::Count).await.unwrap();
+    assert_eq!(aggregate.count, 10);
+    
+    // Test LIST_STORES
+    let stores = client.list_stores().await.unwrap();
+    assert_eq!(stores.len(), 1);
+    assert_eq!(stores[0].name, grpc_server::DEFAULT_STORE_NAME);
+    assert_eq!(stores[0].count, 10);
+    assert!(stores[0].size_bytes > 0);
+    
     // Test GET operations
     for (key, expected_hash, expected_data) in &keys_and_hashes {
         let retrieved_value = client.get(*key).await.unwrap();
@@ -110,4 +178,1251 @@ async fn test_grpc_operations() {
     // Clean up
     store.clear().unwrap();
     server_handle.abort();
-} 
\ No newline at end of file
+} 
+#[tokio::test]
+#[allow(clippy::result_large_err)] // tonic interceptors must return Status
+async fn test_grpc_client_cache() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tonic::codegen::InterceptedService;
+
+    let temp_dir = TempDir::new("kvstore_grpc_cache_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+
+    // Count every request that reaches the server
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let grpc_service = InterceptedService::new(
+        grpc_server::KvStoreGrpcService::new(store.clone()).into_server(),
+        move |request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(request)
+        },
+    );
+    let (addr, server_handle) = serve(grpc_service).await;
+
+    let mut client = grpc_client::KvStoreClient::builder(addr.clone())
+        .cache(Duration::from_millis(300), 16)
+        .connect()
+        .await
+        .unwrap();
+
+    let value = grpc_server::kvstore::Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: 1,
+        data: vec![vec![1u8; 8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    client.put(1, value.clone()).await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    // First get goes to the server, the second is served from the cache
+    assert_eq!(client.get(1).await.unwrap(), Some(value.clone()));
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert_eq!(client.get(1).await.unwrap(), Some(value.clone()));
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    // Our own put invalidates the cached entry
+    let mut updated = value.clone();
+    updated.data = vec![vec![2u8; 8]];
+    client.put(1, updated.clone()).await.unwrap();
+    assert_eq!(client.get(1).await.unwrap(), Some(updated.clone()));
+    assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+    // Once the TTL has passed the server is asked again
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(client.get(1).await.unwrap(), Some(updated));
+    assert_eq!(requests.load(Ordering::SeqCst), 5);
+
+    // Deletes invalidate too
+    client.delete(1).await.unwrap();
+    assert_eq!(client.get(1).await.unwrap(), None);
+    assert_eq!(requests.load(Ordering::SeqCst), 7);
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_multiple_stores() {
+    let temp_dir = TempDir::new("kvstore_grpc_stores_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let make_value = |key: u64, fill: u8| grpc_server::kvstore::Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: key,
+        data: vec![vec![fill; 8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+
+    let mut default_client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    let mut other_client = grpc_client::KvStoreClient::builder(addr.clone())
+        .store("other")
+        .connect()
+        .await
+        .unwrap();
+
+    // Routing to a store that doesn't exist yet fails
+    let status = other_client.get(1).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    let status = default_client.create_store(grpc_server::DEFAULT_STORE_NAME).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::AlreadyExists);
+
+    default_client.create_store("other").await.unwrap();
+    default_client.put(1, make_value(1, 1)).await.unwrap();
+    default_client.put(2, make_value(2, 1)).await.unwrap();
+    other_client.put(1, make_value(1, 2)).await.unwrap();
+
+    // The same key holds a different value in each store
+    assert_eq!(default_client.get(1).await.unwrap(), Some(make_value(1, 1)));
+    assert_eq!(other_client.get(1).await.unwrap(), Some(make_value(1, 2)));
+    assert_eq!(default_client.list().await.unwrap(), vec![1, 2]);
+    assert_eq!(other_client.list().await.unwrap(), vec![1]);
+    assert_eq!(default_client.count().await.unwrap(), 2);
+    assert_eq!(other_client.count().await.unwrap(), 1);
+    assert!(!other_client.exists(2).await.unwrap());
+    assert_eq!(other_client.exists_batch(vec![1, 2]).await.unwrap(), vec![true, false]);
+    assert_eq!(default_client.exists_batch(vec![1, 2]).await.unwrap(), vec![true, true]);
+    assert_eq!(other_client.aggregate(None, None, AggKind::Count).await.unwrap().count, 1);
+    assert_eq!(default_client.aggregate(None, None, AggKind::Count).await.unwrap().count, 2);
+
+    let stores = default_client.list_stores().await.unwrap();
+    let summary: Vec<(String, u64)> = stores.iter().map(|s| (s.name.clone(), s.count)).collect();
+    assert_eq!(summary, vec![(grpc_server::DEFAULT_STORE_NAME.to_string(), 2), ("other".to_string(), 1)]);
+    assert!(stores.iter().all(|s| s.size_bytes > 0));
+
+    other_client.delete(1).await.unwrap();
+    assert_eq!(other_client.get(1).await.unwrap(), None);
+    assert_eq!(default_client.get(1).await.unwrap(), Some(make_value(1, 1)));
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_batch_is_atomic() {
+    use grpc_server::kvstore::{batch_op, BatchDelete, BatchOp, BatchPut};
+
+    let temp_dir = TempDir::new("kvstore_grpc_batch_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    let put = |key: u64, value| BatchOp { op: Some(batch_op::Op::Put(BatchPut { key, value })) };
+    let delete = |key: u64| BatchOp { op: Some(batch_op::Op::Delete(BatchDelete { key })) };
+
+    client.put(1, test_value(1)).await.unwrap();
+
+    // The malformed op in the middle rejects the whole batch
+    let mut malformed = test_value(3);
+    malformed.descriptor = Some(String::new());
+    let status = client
+        .batch(vec![put(2, Some(test_value(2))), put(3, Some(malformed)), delete(1)])
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    let status = client
+        .batch(vec![put(2, Some(test_value(2))), put(3, None), delete(1)])
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(client.list().await.unwrap(), vec![1]);
+    assert_eq!(client.get(1).await.unwrap(), Some(test_value(1)));
+
+    client
+        .batch(vec![put(2, Some(test_value(2))), put(3, Some(test_value(3))), delete(1)])
+        .await
+        .unwrap();
+    assert_eq!(client.list().await.unwrap(), vec![2, 3]);
+    assert_eq!(client.get(3).await.unwrap(), Some(test_value(3)));
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_scan_stream() {
+    use futures_util::StreamExt;
+
+    let temp_dir = TempDir::new("kvstore_grpc_scan_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    // More entries than fit in a couple of pages
+    let count = grpc_server::DEFAULT_SCAN_PAGE_SIZE as u64 * 2 + 50;
+    let items = (0..count)
+        .map(|key| (key * 3, grpc_server::kvstore::Value {
+            shape: vec![1],
+            dtype: DataType::Fp64 as i32,
+            size_check: 8,
+            key_check: key * 3,
+            data: vec![key.to_le_bytes().to_vec()],
+            descriptor: None,
+            metadata: Default::default(),
+        }))
+        .collect::<Vec<_>>();
+    store.put_batch(items.clone()).unwrap();
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    let scanned: Vec<_> = client.scan(None).await.unwrap()
+        .map(|entry| entry.unwrap())
+        .collect()
+        .await;
+    assert_eq!(scanned, items);
+
+    // Starting between keys picks up at the next one
+    let keys: Vec<u64> = client.scan(Some(301)).await.unwrap()
+        .map(|entry| entry.unwrap().0)
+        .collect()
+        .await;
+    assert_eq!(keys, (101..count).map(|key| key * 3).collect::<Vec<_>>());
+
+    // Dropping the stream early leaves the server serving normally
+    let first: Vec<_> = client.scan(None).await.unwrap().take(5).collect().await;
+    assert_eq!(first.len(), 5);
+    assert_eq!(client.health().await.unwrap(), "healthy");
+    assert_eq!(client.list().await.unwrap().len(), count as usize);
+
+    // A named store streams its own entries
+    client.create_store("other").await.unwrap();
+    let mut other = grpc_client::KvStoreClient::builder(addr.clone()).store("other").connect().await.unwrap();
+    for key in [4, 8] {
+        other.put(key, test_value(key)).await.unwrap();
+    }
+    let scanned: Vec<_> = other.scan(None).await.unwrap()
+        .map(|entry| entry.unwrap())
+        .collect()
+        .await;
+    assert_eq!(scanned, vec![(4, test_value(4)), (8, test_value(8))]);
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_bulk_put() {
+    let temp_dir = TempDir::new("kvstore_grpc_bulk_put_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let grpc_service = grpc_server::KvStoreGrpcService::new(store.clone()).bulk_put_batch_size(10).into_server();
+    let (addr, server_handle) = serve(grpc_service).await;
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+
+    // Not a multiple of the batch size, so the last batch is partial
+    let items: Vec<_> = (0..25u64).map(|key| (key, test_value(key))).collect();
+    let count = client.bulk_put(tokio_stream::iter(items)).await.unwrap();
+    assert_eq!(count, 25);
+    assert_eq!(store.len().unwrap(), 25);
+    assert_eq!(store.get(&24).unwrap(), Some(test_value(24)));
+
+    // An invalid item fails the call; full batches before it were written
+    store.clear().unwrap();
+    let items: Vec<_> = (0..25u64).map(|key| {
+        let mut value = test_value(key);
+        if key == 23 {
+            value.descriptor = Some(String::new());
+        }
+        (key, value)
+    }).collect();
+    let status = client.bulk_put(tokio_stream::iter(items)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(store.keys().unwrap(), (0..20).collect::<Vec<_>>());
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_tls() {
+    let temp_dir = TempDir::new("kvstore_grpc_tls_test");
+    let store = Arc::new(KVStore::new(temp_dir.join("db")).unwrap());
+
+    // A throwaway CA and a server certificate it signed for "localhost"
+    let mut ca_params = rcgen::CertificateParams::new(vec![]);
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    ca_params.distinguished_name.push(rcgen::DnType::CommonName, "kvstore test CA");
+    let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+    let server_cert = rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec!["localhost".to_string()])).unwrap();
+    let (ca_path, cert_path, key_path) = (temp_dir.join("ca.pem"), temp_dir.join("server.pem"), temp_dir.join("server.key"));
+    std::fs::write(&ca_path, ca.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&cert_path, server_cert.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+    std::fs::write(&key_path, server_cert.serialize_private_key_pem()).unwrap();
+
+    let identity = grpc_server::load_identity(&cert_path, &key_path).unwrap();
+    let router = grpc_server::create_grpc_server_tls(store.clone(), identity).unwrap();
+    let (addr, incoming) = bind().await;
+    let server_handle = tokio::spawn(router.serve_with_incoming(incoming));
+
+    let ca_cert = grpc_client::KvStoreClient::load_ca_certificate(&ca_path).unwrap();
+    let mut client = grpc_client::KvStoreClient::connect_tls(format!("https://{}", addr), ca_cert, "localhost")
+        .await
+        .unwrap();
+    assert_eq!(client.health().await.unwrap(), "healthy");
+    let value = grpc_server::kvstore::Value {
+        shape: vec![1],
+        dtype: DataType::Fp64 as i32,
+        size_check: 8,
+        key_check: 1,
+        data: vec![vec![1u8; 8]],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    client.put(1, value.clone()).await.unwrap();
+    assert_eq!(client.get(1).await.unwrap(), Some(value));
+
+    // A plaintext client can't talk to the TLS server
+    let plaintext = grpc_client::KvStoreClient::connect(format!("http://{}", addr)).await;
+    if let Ok(mut plaintext) = plaintext {
+        assert!(plaintext.health().await.is_err());
+    }
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_bearer_auth() {
+    let temp_dir = TempDir::new("kvstore_grpc_auth_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let grpc_service = grpc_server::create_grpc_server_authed(store.clone(), "s3cret-token");
+    let (addr, server_handle) = serve(grpc_service).await;
+
+    let mut anonymous = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    assert_eq!(anonymous.health().await.unwrap_err().code(), tonic::Code::Unauthenticated);
+    assert_eq!(anonymous.list().await.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    for wrong in ["s3cret", "s3cret-token2", "S3CRET-TOKEN"] {
+        let mut client = grpc_client::KvStoreClient::builder(addr.clone()).token(wrong).connect().await.unwrap();
+        assert_eq!(client.health().await.unwrap_err().code(), tonic::Code::Unauthenticated);
+    }
+
+    let mut client = grpc_client::KvStoreClient::builder(addr).token("s3cret-token").connect().await.unwrap();
+    assert_eq!(client.health().await.unwrap(), "healthy");
+    assert!(client.list().await.unwrap().is_empty());
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_delete_range() {
+    let temp_dir = TempDir::new("kvstore_grpc_delete_range_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    for key in 0..10 {
+        client.put(key, test_value(key)).await.unwrap();
+    }
+
+    client.delete_range(3, 7).await.unwrap();
+    assert_eq!(client.list().await.unwrap(), vec![0, 1, 2, 7, 8, 9]);
+    assert_eq!(client.count().await.unwrap(), 6);
+    // Empty and already-deleted ranges are no-ops
+    client.delete_range(8, 8).await.unwrap();
+    client.delete_range(9, 2).await.unwrap();
+    client.delete_range(3, 7).await.unwrap();
+    assert_eq!(client.count().await.unwrap(), 6);
+    client.delete_range(0, u64::MAX).await.unwrap();
+    assert!(client.list().await.unwrap().is_empty());
+    assert_eq!(store.len().unwrap(), 0);
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_metrics() {
+    use rust_kv_store::metrics::{self, Metrics};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let temp_dir = TempDir::new("kvstore_grpc_metrics_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let metrics = Arc::new(Metrics::new());
+    let grpc_service = grpc_server::KvStoreGrpcService::new(store.clone()).metrics(metrics.clone()).into_server();
+    let (addr, server_handle) = serve(grpc_service).await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    let http_handle = tokio::spawn(async move {
+        axum::serve(listener, metrics::router(metrics, store.clone())).await
+    });
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    for key in 0..3 {
+        client.put(key, test_value(key)).await.unwrap();
+    }
+    client.get(0).await.unwrap();
+    client.get(7).await.unwrap();
+    client.delete(2).await.unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(http_addr).await.unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("kvstore_operations_total{op=\"put\"} 3"));
+    assert!(response.contains("kvstore_operations_total{op=\"get\"} 2"));
+    assert!(response.contains("kvstore_operations_total{op=\"delete\"} 1"));
+    assert!(response.contains("kvstore_request_duration_seconds_count{method=\"put\"} 3"));
+    assert!(response.contains("kvstore_entries 2"));
+    assert!(response.contains("kvstore_db_size_bytes "));
+    assert!(response.contains("kvstore_rocksdb_block_cache_hit_ratio "));
+    assert!(response.contains("kvstore_rocksdb_compaction_write_bytes "));
+
+    http_handle.abort();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_http_compression() {
+    use rust_kv_store::metrics::{self, Metrics};
+    use std::io::Read;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let temp_dir = TempDir::new("kvstore_http_compression_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    let http_handle = tokio::spawn(async move {
+        axum::serve(listener, metrics::router(Arc::new(Metrics::new()), store)).await
+    });
+
+    // HTTP/1.0 so the body isn't chunked and can be read to the end as-is
+    let fetch = |accept_encoding: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(http_addr).await.unwrap();
+        let request = format!("GET /metrics HTTP/1.0\r\nHost: localhost\r\n{}\r\n", accept_encoding);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        (String::from_utf8(response[..split].to_vec()).unwrap().to_lowercase(), response[split + 4..].to_vec())
+    };
+
+    let (headers, plain) = fetch("").await;
+    assert!(!headers.contains("content-encoding"), "{}", headers);
+    let plain = String::from_utf8(plain).unwrap();
+    assert!(plain.contains("kvstore_entries 0"));
+
+    let (headers, compressed) = fetch("Accept-Encoding: gzip\r\n").await;
+    assert!(headers.contains("content-encoding: gzip"), "{}", headers);
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(compressed.as_slice()).read_to_string(&mut decoded).unwrap();
+    assert_eq!(decoded, plain);
+
+    http_handle.abort();
+}
+
+#[tokio::test]
+async fn test_http_stats() {
+    use rust_kv_store::metrics::{self, Metrics};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let temp_dir = TempDir::new("kvstore_http_stats_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    for key in 0..3 {
+        store.put(key, test_value(key)).unwrap();
+    }
+    store.flush().unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    let router = metrics::router(Arc::new(Metrics::new()), store.clone());
+    let http_handle = tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let mut stream = tokio::net::TcpStream::connect(http_addr).await.unwrap();
+    stream.write_all(b"GET /stats HTTP/1.0\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (headers, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(headers.starts_with("HTTP/1.0 200"), "{}", headers);
+    assert!(headers.to_lowercase().contains("content-type: application/json"), "{}", headers);
+
+    let stats: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(stats["data_dir"], temp_dir.display().to_string());
+    assert_eq!(stats["len"], 3);
+    assert_eq!(stats["db_size_bytes"], store.get_db_size().unwrap());
+    assert!(stats["sst_files_size_bytes"].as_u64().unwrap() > 0);
+    assert_eq!(stats["compaction"]["running"], 0);
+    assert_eq!(stats["compaction"]["pending"], false);
+    // The store doesn't collect statistics
+    assert!(stats["compaction"]["read_bytes"].is_null());
+
+    http_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_graceful_shutdown() {
+    let temp_dir = TempDir::new("kvstore_grpc_shutdown_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let grpc_service = grpc_server::create_grpc_server(store.clone());
+    let (addr, incoming) = bind().await;
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server_handle = tokio::spawn(async move {
+        Server::builder()
+            .add_service(grpc_service)
+            .serve_with_incoming_shutdown(incoming, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+    });
+
+    let mut client = grpc_client::KvStoreClient::connect(format!("http://{}", addr)).await.unwrap();
+    let value = test_value(7);
+    client.put(7, value.clone()).await.unwrap();
+    drop(client);
+
+    shutdown_tx.send(()).unwrap();
+    server_handle.await.unwrap().unwrap();
+    store.flush().unwrap();
+    // The server has released its handle, so this closes the database
+    let store = Arc::try_unwrap(store).unwrap();
+    drop(store);
+
+    let reopened = KVStore::new(&temp_dir).unwrap();
+    assert_eq!(reopened.get(&7).unwrap(), Some(value));
+}
+
+#[tokio::test]
+async fn test_grpc_list_pagination() {
+    let temp_dir = TempDir::new("kvstore_grpc_pagination_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    // Out of order, and past the first byte so ordering relies on big-endian keys
+    let mut expected: Vec<u64> = vec![300, 5, u64::MAX, 256, 1, 70_000, 42];
+    for key in &expected {
+        client.put(*key, test_value(*key)).await.unwrap();
+    }
+    expected.sort();
+
+    let mut listed = Vec::new();
+    let mut cursor = None;
+    loop {
+        let (keys, next_cursor) = client.list_page(cursor, 3).await.unwrap();
+        assert!(keys.len() <= 3);
+        listed.extend(keys);
+        match next_cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+    assert_eq!(listed, expected);
+
+    // A page ending exactly on the last key has no cursor
+    assert_eq!(client.list_page(None, 7).await.unwrap(), (expected.clone(), None));
+    assert_eq!(client.list_page(Some(42), 2).await.unwrap(), (vec![256, 300], Some(300)));
+    assert_eq!(client.list_page(Some(u64::MAX), 2).await.unwrap(), (vec![], None));
+    // No limit lists the rest
+    assert_eq!(client.list_page(Some(300), 0).await.unwrap(), (vec![70_000, u64::MAX], None));
+    assert_eq!(client.list().await.unwrap(), expected);
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_client_pool() {
+    let temp_dir = TempDir::new("kvstore_grpc_pool_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let pool = Arc::new(grpc_client::KvStorePool::connect(addr.clone(), 4).await.unwrap());
+    assert_eq!(pool.size(), 4);
+    assert_eq!(pool.health().await.unwrap(), "healthy");
+
+    // Tasks share the pool and their requests spread over its connections
+    let tasks: Vec<_> = (0..16u64)
+        .map(|key| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                pool.put(key, test_value(key)).await.unwrap();
+                pool.get(key).await.unwrap()
+            })
+        })
+        .collect();
+    for (key, task) in tasks.into_iter().enumerate() {
+        assert_eq!(task.await.unwrap(), Some(test_value(key as u64)));
+    }
+    assert_eq!(pool.list().await.unwrap(), (0..16).collect::<Vec<u64>>());
+    pool.delete(3).await.unwrap();
+    assert_eq!(pool.get(3).await.unwrap(), None);
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+#[allow(clippy::result_large_err)] // tonic interceptors return tonic::Status
+async fn test_grpc_client_retry() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let temp_dir = TempDir::new("kvstore_grpc_retry_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    // Fails the next `failures` requests as if the server were unreachable
+    let failures = Arc::new(AtomicUsize::new(0));
+    let remaining = failures.clone();
+    let grpc_service = tonic::codegen::InterceptedService::new(
+        grpc_server::KvStoreGrpcService::new(store.clone()).into_server(),
+        move |request: tonic::Request<()>| {
+            match remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)) {
+                Ok(_) => Err(tonic::Status::unavailable("transient failure")),
+                Err(_) => Ok(request),
+            }
+        },
+    );
+    let (addr, server_handle) = serve(grpc_service).await;
+
+    let policy = rust_kv_store::RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+    };
+    let mut client = grpc_client::KvStoreClient::connect_with_retry(addr.clone(), policy).await.unwrap();
+    let mut plain = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    let value = test_value(1);
+
+    failures.store(1, Ordering::SeqCst);
+    assert_eq!(plain.health().await.unwrap_err().code(), tonic::Code::Unavailable);
+    failures.store(2, Ordering::SeqCst);
+    assert_eq!(client.health().await.unwrap(), "healthy");
+    assert_eq!(client.get(1).await.unwrap(), None);
+    // More failures than attempts
+    failures.store(3, Ordering::SeqCst);
+    assert_eq!(client.list().await.unwrap_err().code(), tonic::Code::Unavailable);
+
+    // Puts are only retried when opted in
+    failures.store(1, Ordering::SeqCst);
+    assert_eq!(client.put(1, value.clone()).await.unwrap_err().code(), tonic::Code::Unavailable);
+    let mut retrying_puts = grpc_client::KvStoreClient::builder(addr)
+        .retry(policy)
+        .retry_puts(true)
+        .connect()
+        .await
+        .unwrap();
+    failures.store(2, Ordering::SeqCst);
+    retrying_puts.put(1, value.clone()).await.unwrap();
+    assert_eq!(client.get(1).await.unwrap(), Some(value));
+
+    store.clear().unwrap();
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_client_timeout() {
+    use std::time::{Duration, Instant};
+
+    // Accepts connections and never answers on them, like a hung server
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let hung_server = tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            connections.push(socket);
+        }
+    });
+
+    let mut client = grpc_client::KvStoreClient::builder(format!("http://{}", addr))
+        .timeout(Duration::from_millis(300))
+        .connect()
+        .await
+        .unwrap();
+
+    let started = Instant::now();
+    assert_eq!(client.get(1).await.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    let started = Instant::now();
+    assert_eq!(client.get_with_timeout(1, Duration::from_millis(50)).await.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+    assert!(started.elapsed() < Duration::from_millis(300));
+    assert_eq!(client.count().await.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+
+    // Pooled connections are held to the same timeout
+    let pool = grpc_client::KvStoreClient::builder(format!("http://{}", addr))
+        .timeout(Duration::from_millis(300))
+        .connect_pool(2)
+        .await
+        .unwrap();
+    let started = Instant::now();
+    assert_eq!(pool.get(1).await.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+    assert_eq!(pool.health().await.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    hung_server.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_client_reconnect() {
+    let temp_dir = TempDir::new("kvstore_grpc_reconnect_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+
+    // Each server gets its own runtime, so shutting that down drops its open
+    // connections too, as if the process had died. The restarted server
+    // takes over the first one's port.
+    let start_server = |store: Arc<KVStore>, addr: SocketAddr| {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = {
+            let _entered = runtime.enter();
+            let socket = tokio::net::TcpSocket::new_v6().unwrap();
+            socket.set_reuseaddr(true).unwrap();
+            socket.bind(addr).unwrap();
+            socket.listen(1024).unwrap()
+        };
+        let addr = listener.local_addr().unwrap();
+        runtime.spawn(
+            Server::builder()
+                .add_service(grpc_server::create_grpc_server(store))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        (runtime, addr)
+    };
+
+    let (server, addr) = start_server(store.clone(), "[::1]:0".parse().unwrap());
+
+    let mut client = grpc_client::KvStoreClient::connect(format!("http://{}", addr)).await.unwrap();
+    let value = test_value(3);
+    client.put(3, value.clone()).await.unwrap();
+
+    server.shutdown_background();
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    assert_eq!(client.get(3).await.unwrap_err().code(), tonic::Code::Unavailable);
+
+    let (server, _) = start_server(store.clone(), addr);
+
+    // Same client, no reconnect by the caller
+    assert_eq!(client.get(3).await.unwrap(), Some(value));
+    assert_eq!(client.health().await.unwrap(), "healthy");
+    server.shutdown_background();
+}
+
+#[tokio::test]
+async fn test_grpc_reflection() {
+    use tonic_reflection::pb::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::ServerReflectionRequest;
+
+    let temp_dir = TempDir::new("kvstore_grpc_reflection_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let router = grpc_server::create_grpc_server_with_reflection(store).unwrap();
+    let (addr, incoming) = bind().await;
+    let server_handle = tokio::spawn(router.serve_with_incoming(incoming));
+
+    let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+    let mut client = ServerReflectionClient::new(channel);
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut responses = client.server_reflection_info(tokio_stream::iter(vec![request])).await.unwrap().into_inner();
+    let response = responses.message().await.unwrap().unwrap();
+    let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+        panic!("unexpected reflection response: {:?}", response.message_response);
+    };
+    let services: Vec<String> = list.service.into_iter().map(|s| s.name).collect();
+    assert!(services.contains(&"kvstore.KvStoreService".to_string()), "{:?}", services);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+#[allow(clippy::result_large_err)] // the interceptor must return tonic::Status
+async fn test_grpc_compression() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tonic::codegen::InterceptedService;
+
+    let temp_dir = TempDir::new("kvstore_grpc_compression_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+
+    // Counts requests that arrive gzip-compressed
+    let gzipped = Arc::new(AtomicUsize::new(0));
+    let counter = gzipped.clone();
+    let grpc_service = InterceptedService::new(
+        grpc_server::KvStoreGrpcService::new(store).into_server(),
+        move |request: tonic::Request<()>| {
+            if request.metadata().get("grpc-encoding").is_some_and(|encoding| encoding == "gzip") {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(request)
+        },
+    );
+    let (addr, server_handle) = serve(grpc_service).await;
+
+    // 2 MiB of float64 with few distinct bytes, which gzip shrinks a lot
+    let data: Vec<u8> = (0..262_144u64).flat_map(|i| ((i % 16) as f64).to_le_bytes()).collect();
+    let value = grpc_server::kvstore::Value {
+        shape: vec![262_144],
+        dtype: DataType::Fp64 as i32,
+        size_check: data.len() as u64,
+        key_check: 1,
+        data: vec![data],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    client.put(1, value.clone()).await.unwrap();
+    assert_eq!(client.get(1).await.unwrap(), Some(value.clone()));
+    assert_eq!(gzipped.load(Ordering::SeqCst), 2);
+
+    // Uncompressed clients are still served
+    let mut plain = grpc_client::KvStoreClient::builder(addr.clone())
+        .compression(None)
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(plain.get(1).await.unwrap(), Some(value));
+    assert_eq!(gzipped.load(Ordering::SeqCst), 2);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_request_logging() {
+    use std::sync::Mutex;
+
+    // Collects formatted log lines; the test runtime is single-threaded, so
+    // the servers' handlers log to this thread's default subscriber
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt().with_ansi(false).with_writer(move || writer.clone()).finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let temp_dir = TempDir::new("kvstore_grpc_logging_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let logged = grpc_server::KvStoreGrpcService::new(store.clone()).into_server();
+    let quiet = grpc_server::KvStoreGrpcService::new(store).log_requests(false).into_server();
+    let (logged_addr, logged_handle) = serve(logged).await;
+    let (quiet_addr, quiet_handle) = serve(quiet).await;
+
+    let value = test_value(42);
+    let mut client = grpc_client::KvStoreClient::connect(logged_addr).await.unwrap();
+    client.put(42, value).await.unwrap();
+    let log = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    let line = log.lines().find(|line| line.contains("request handled")).expect("put was logged");
+    assert!(line.contains("method=\"put\""), "{}", line);
+    assert!(line.contains("key=42"), "{}", line);
+    assert!(line.contains("payload_bytes="), "{}", line);
+    assert!(line.contains("peer=[::1]:"), "{}", line);
+    assert!(line.contains("latency_us="), "{}", line);
+
+    captured.0.lock().unwrap().clear();
+    let mut client = grpc_client::KvStoreClient::connect(quiet_addr).await.unwrap();
+    client.get(42).await.unwrap();
+    assert!(!String::from_utf8(captured.0.lock().unwrap().clone()).unwrap().contains("request handled"));
+
+    logged_handle.abort();
+    quiet_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_compare_and_swap() {
+    let temp_dir = TempDir::new("kvstore_grpc_cas_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let counter = |n: u64| grpc_server::kvstore::Value {
+        shape: vec![1],
+        dtype: DataType::Int64 as i32,
+        size_check: 8,
+        key_check: 5,
+        data: vec![n.to_le_bytes().to_vec()],
+        descriptor: None,
+        metadata: Default::default(),
+    };
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    assert!(client.compare_and_swap(5, None, counter(0)).await.unwrap());
+    assert!(!client.compare_and_swap(5, None, counter(1)).await.unwrap());
+    assert!(!client.compare_and_swap(5, Some(counter(9)), counter(1)).await.unwrap());
+    assert_eq!(client.get(5).await.unwrap(), Some(counter(0)));
+
+    // Two clients increment the counter through read-CAS loops; each lost
+    // race is retried, so no increment goes missing
+    const INCREMENTS: u64 = 25;
+    let racers: Vec<_> = (0..2).map(|_| {
+        let addr = addr.clone();
+        tokio::spawn(async move {
+            let mut client = grpc_client::KvStoreClient::connect(addr).await.unwrap();
+            for _ in 0..INCREMENTS {
+                loop {
+                    let current = client.get(5).await.unwrap().unwrap();
+                    let n = u64::from_le_bytes(current.data[0].clone().try_into().unwrap());
+                    if client.compare_and_swap(5, Some(current), counter(n + 1)).await.unwrap() {
+                        break;
+                    }
+                }
+            }
+        })
+    }).collect();
+    for racer in racers {
+        racer.await.unwrap();
+    }
+    assert_eq!(client.get(5).await.unwrap(), Some(counter(2 * INCREMENTS)));
+
+    // A named store swaps its own copy of the key
+    client.create_store("other").await.unwrap();
+    let mut other = grpc_client::KvStoreClient::builder(addr.clone()).store("other").connect().await.unwrap();
+    assert!(other.compare_and_swap(5, None, counter(7)).await.unwrap());
+    assert!(!other.compare_and_swap(5, Some(counter(0)), counter(8)).await.unwrap());
+    assert_eq!(other.get(5).await.unwrap(), Some(counter(7)));
+    assert_eq!(client.get(5).await.unwrap(), Some(counter(2 * INCREMENTS)));
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_delete_batch() {
+    let temp_dir = TempDir::new("kvstore_grpc_delete_batch_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let mut client = grpc_client::KvStoreClient::builder(addr.clone())
+        .cache(std::time::Duration::from_secs(60), 16)
+        .connect()
+        .await
+        .unwrap();
+    for key in 1..=5 {
+        client.put(key, test_value(key)).await.unwrap();
+        // Fill the cache, so a stale entry would show below
+        client.get(key).await.unwrap();
+    }
+
+    assert_eq!(client.delete_batch(vec![1, 3, 5, 7]).await.unwrap(), 3);
+    assert_eq!(client.get(3).await.unwrap(), None);
+    assert_eq!(client.list().await.unwrap(), vec![2, 4]);
+    assert_eq!(client.count().await.unwrap(), 2);
+    assert_eq!(client.delete_batch(vec![1, 3]).await.unwrap(), 0);
+    assert_eq!(client.delete_batch(vec![]).await.unwrap(), 0);
+
+    // A named store only loses its own keys
+    client.create_store("other").await.unwrap();
+    let mut other = grpc_client::KvStoreClient::builder(addr.clone())
+        .store("other")
+        .cache(std::time::Duration::from_secs(60), 16)
+        .connect()
+        .await
+        .unwrap();
+    for key in [2, 3] {
+        other.put(key, test_value(key)).await.unwrap();
+        other.get(key).await.unwrap();
+    }
+    assert_eq!(other.delete_batch(vec![2, 3, 4]).await.unwrap(), 2);
+    assert_eq!(other.get(2).await.unwrap(), None);
+    assert!(other.list().await.unwrap().is_empty());
+    assert_eq!(client.list().await.unwrap(), vec![2, 4]);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_get_batch() {
+    let temp_dir = TempDir::new("kvstore_grpc_get_batch_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    for key in [2, 4, 6] {
+        client.put(key, test_value(key)).await.unwrap();
+    }
+    assert_eq!(
+        client.get_batch(vec![6, 1, 2, 2, 5, 4]).await.unwrap(),
+        vec![Some(test_value(6)), None, Some(test_value(2)), Some(test_value(2)), None, Some(test_value(4))],
+    );
+    assert!(client.get_batch(vec![]).await.unwrap().is_empty());
+
+    // With a cache, cached keys are mixed back in at their positions
+    let mut cached = grpc_client::KvStoreClient::builder(addr.clone())
+        .cache(std::time::Duration::from_secs(60), 16)
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(cached.get(4).await.unwrap(), Some(test_value(4)));
+    store.delete(&4).unwrap();
+    assert_eq!(cached.get_batch(vec![2, 4, 3]).await.unwrap(), vec![Some(test_value(2)), Some(test_value(4)), None]);
+
+    // A named store is read from that store, and its values are cached under
+    // its client only
+    client.create_store("other").await.unwrap();
+    let mut other = grpc_client::KvStoreClient::builder(addr.clone())
+        .store("other")
+        .cache(std::time::Duration::from_secs(60), 16)
+        .connect()
+        .await
+        .unwrap();
+    let mut renamed = test_value(2);
+    renamed.metadata.insert("store".to_string(), "other".to_string());
+    other.put(2, renamed.clone()).await.unwrap();
+    assert_eq!(other.get_batch(vec![2, 6]).await.unwrap(), vec![Some(renamed.clone()), None]);
+    store.delete_cf("other", &2).unwrap();
+    assert_eq!(other.get_batch(vec![2, 6]).await.unwrap(), vec![Some(renamed), None]);
+    assert_eq!(client.get_batch(vec![2]).await.unwrap(), vec![Some(test_value(2))]);
+
+    server_handle.abort();
+}
+
+// The server shares this test's single-threaded runtime, as a busy server's
+// tasks share its workers. A compaction stalled in RocksDB must not hold up
+// unrelated calls.
+#[tokio::test(flavor = "current_thread")]
+async fn test_grpc_health_during_compaction() {
+    let temp_dir = TempDir::new("kvstore_grpc_slow_compaction_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+    store.put_batch((0..1000).map(|key| (key, test_value(key))).collect()).unwrap();
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    let compaction = tokio::spawn({
+        let store = store.clone();
+        async move {
+            store.run_blocking(|store| {
+                // Stands in for a compaction stalled on disk
+                std::thread::sleep(std::time::Duration::from_secs(2));
+                store.compact()
+            }).await
+        }
+    });
+    tokio::task::yield_now().await;
+
+    let started = std::time::Instant::now();
+    for _ in 0..5 {
+        assert_eq!(client.health().await.unwrap(), "healthy");
+    }
+    let elapsed = started.elapsed();
+    assert!(elapsed < std::time::Duration::from_millis(500), "5 health calls took {:?}", elapsed);
+    assert!(!compaction.is_finished());
+    compaction.await.unwrap().unwrap();
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_flush() {
+    let temp_dir = TempDir::new("kvstore_grpc_flush_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    let value = test_value(3);
+    client.put(3, value.clone()).await.unwrap();
+
+    // Syncing the log leaves the write in the memtable; only a flush moves
+    // it into an SST file
+    client.sync_wal().await.unwrap();
+    assert_eq!(store.estimate_live_data_size().unwrap(), 0);
+    client.flush().await.unwrap();
+    assert!(store.estimate_live_data_size().unwrap() > 0);
+    assert_eq!(client.get(3).await.unwrap(), Some(value));
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_metadata() {
+    use grpc_server::kvstore::kv_store_service_client::KvStoreServiceClient;
+    use grpc_server::kvstore::GetRequest;
+
+    let temp_dir = TempDir::new("kvstore_grpc_metadata_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let value = grpc_server::kvstore::Value {
+        shape: vec![4096],
+        dtype: DataType::Int8 as i32,
+        size_check: 4096,
+        key_check: 8,
+        data: vec![vec![1; 4096]],
+        descriptor: None,
+        metadata: [("owner".to_string(), "alice".to_string())].into(),
+    };
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    client.put(8, value.clone()).await.unwrap();
+    assert_eq!(client.get(8).await.unwrap(), Some(value.clone()));
+    assert_eq!(client.get_metadata(8).await.unwrap(), Some(value.metadata.clone()));
+    assert_eq!(client.get_metadata(9).await.unwrap(), None);
+    let meta = client.get_meta(8).await.unwrap().unwrap();
+    assert_eq!((meta.shape, meta.size_check, meta.key_check), (vec![4096], 4096, 8));
+    assert_eq!(meta.metadata, value.metadata);
+    assert_eq!(client.get_meta(9).await.unwrap(), None);
+
+    // metadata_only leaves the tensor out of the response
+    let mut raw = KvStoreServiceClient::connect(addr.clone()).await.unwrap();
+    let request = GetRequest { key: 8, metadata_only: true, ..Default::default() };
+    let header = raw.get(request).await.unwrap().into_inner().value.unwrap();
+    assert!(header.data.is_empty());
+    assert_eq!(header.shape, value.shape);
+    assert_eq!(header.metadata, value.metadata);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_returning_old_values() {
+    let temp_dir = TempDir::new("kvstore_grpc_delete_returning_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    client.put(1, test_value(1)).await.unwrap();
+    client.put(2, test_value(2)).await.unwrap();
+
+    assert_eq!(client.put_returning(3, test_value(3)).await.unwrap(), None);
+    let mut replacement = test_value(3);
+    replacement.data = vec![vec![0; 8]];
+    assert_eq!(client.put_returning(3, replacement.clone()).await.unwrap(), Some(test_value(3)));
+    assert_eq!(client.get(3).await.unwrap(), Some(replacement));
+    client.delete(3).await.unwrap();
+
+    assert_eq!(client.delete_returning(1).await.unwrap(), Some(test_value(1)));
+    assert_eq!(client.delete_returning(1).await.unwrap(), None);
+    // A plain delete still works and sends nothing back
+    client.delete(2).await.unwrap();
+    assert_eq!(client.count().await.unwrap(), 0);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_watch() {
+    use futures_util::StreamExt;
+    use grpc_server::kvstore::{ChangeOp, WatchEvent};
+
+    let temp_dir = TempDir::new("kvstore_grpc_watch_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    let mut everything = Box::pin(client.watch(None, None).await.unwrap());
+    let mut ranged = Box::pin(client.watch(Some(10), Some(20)).await.unwrap());
+
+    client.put(5, test_value(5)).await.unwrap();
+    client.put(10, test_value(10)).await.unwrap();
+    client.delete(5).await.unwrap();
+    client.delete_batch(vec![10, 20]).await.unwrap();
+    store.put(19, test_value(19)).unwrap();
+
+    async fn next_event(stream: &mut (impl futures_util::Stream<Item = Result<WatchEvent, tonic::Status>> + Unpin)) -> (u64, ChangeOp) {
+        let event = tokio::time::timeout(tokio::time::Duration::from_secs(5), stream.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(event.missed, 0);
+        (event.key, ChangeOp::try_from(event.op).unwrap())
+    }
+    for expected in [(5, ChangeOp::Put), (10, ChangeOp::Put), (5, ChangeOp::Delete), (10, ChangeOp::Delete), (19, ChangeOp::Put)] {
+        assert_eq!(next_event(&mut everything).await, expected);
+    }
+    for expected in [(10, ChangeOp::Put), (10, ChangeOp::Delete), (19, ChangeOp::Put)] {
+        assert_eq!(next_event(&mut ranged).await, expected);
+    }
+
+    // A store-bound watcher sees its own store's changes and nobody else's
+    client.create_store("other").await.unwrap();
+    let mut other = grpc_client::KvStoreClient::builder(addr.clone()).store("other").connect().await.unwrap();
+    let mut other_changes = Box::pin(other.watch(None, None).await.unwrap());
+    client.put(6, test_value(6)).await.unwrap();
+    other.put(7, test_value(7)).await.unwrap();
+    other.delete(7).await.unwrap();
+    client.delete(6).await.unwrap();
+    for expected in [(7, ChangeOp::Put), (7, ChangeOp::Delete)] {
+        assert_eq!(next_event(&mut other_changes).await, expected);
+    }
+    for expected in [(6, ChangeOp::Put), (6, ChangeOp::Delete)] {
+        assert_eq!(next_event(&mut everything).await, expected);
+    }
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_grpc_put_sync() {
+    let temp_dir = TempDir::new("kvstore_grpc_put_sync_test");
+    let store = rust_kv_store::RocksDBStore::builder().statistics(true).open(&temp_dir).unwrap();
+    let store = Arc::new(KVStore::from(store));
+    let (addr, server_handle) = start_server(store.clone()).await;
+
+    let wal_syncs = || {
+        let statistics = store.statistics().unwrap();
+        let line = statistics.lines().find(|line| line.starts_with("rocksdb.wal.synced ")).unwrap();
+        line.rsplit(' ').next().unwrap().parse::<u64>().unwrap()
+    };
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    client.put(1, test_value(1)).await.unwrap();
+    client.put_sync(2, test_value(2), false).await.unwrap();
+    assert_eq!(wal_syncs(), 0);
+    client.put_sync(3, test_value(3), true).await.unwrap();
+    assert_eq!(wal_syncs(), 1);
+    assert_eq!(client.get(3).await.unwrap(), Some(test_value(3)));
+
+    client.create_store("other").await.unwrap();
+    let mut other = grpc_client::KvStoreClient::builder(addr.clone()).store("other").connect().await.unwrap();
+    other.put_sync(1, test_value(1), true).await.unwrap();
+    assert_eq!(wal_syncs(), 2);
+    assert_eq!(store.get_cf("other", &1).unwrap(), Some(test_value(1)));
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_rate_limiting() {
+    use rust_kv_store::metrics::{self, Metrics};
+    use rust_kv_store::rate_limit::{self, RateLimit};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tonic::codegen::InterceptedService;
+
+    const LIMIT: u32 = 3;
+    let temp_dir = TempDir::new("kvstore_rate_limit_test");
+    let store = Arc::new(KVStore::new(&temp_dir).unwrap());
+    let grpc_service = InterceptedService::new(grpc_server::KvStoreGrpcService::new(store.clone()).into_server(), RateLimit::new(LIMIT));
+    let (addr, server_handle) = serve(grpc_service).await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    let router = metrics::router(Arc::new(Metrics::new()), store.clone())
+        .layer(axum::middleware::from_fn_with_state(RateLimit::new(LIMIT), rate_limit::limit_http_requests));
+    let http_handle = tokio::spawn(async move {
+        axum::serve(listener, router).await
+    });
+
+    let mut client = grpc_client::KvStoreClient::connect(addr.clone()).await.unwrap();
+    for _ in 0..LIMIT {
+        client.count().await.unwrap();
+    }
+    let err = client.count().await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+
+    let get = || async move {
+        let mut stream = tokio::net::TcpStream::connect(http_addr).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    for _ in 0..LIMIT {
+        assert_eq!(&get().await[9..12], "200");
+    }
+    // Turned away with the JSON error body
+    let response = get().await;
+    assert_eq!(&response[9..12], "429");
+    assert!(response.to_lowercase().contains("content-type: application/json"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(body, serde_json::json!({"success": false, "error": "Rate limit exceeded", "code": "resource_exhausted"}));
+
+    http_handle.abort();
+    server_handle.abort();
+}